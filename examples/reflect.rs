@@ -4,7 +4,7 @@ use clap::Parser;
 use daemonbase::{logging, process};
 use daemonbase::error::ExitError;
 use daemonbase::logging::Logger;
-use daemonbase::process::Process;
+use daemonbase::process::{Notify, Process};
 use log::{warn};
 
 #[derive(Parser)]
@@ -38,6 +38,7 @@ fn _main() -> Result<(), ExitError> {
     process.drop_privileges()?;
 
     warn!("Up and running.");
+    Notify::from_env().notify_ready();
 
     // This is where we do something useful later.
     loop {
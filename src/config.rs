@@ -1,14 +1,19 @@
 
-use std::{env, fmt, fs, ops};
+use std::{env, fmt, fs, io, ops};
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::ops::RangeInclusive;
 use std::io::Read;
+use std::net::{IpAddr, SocketAddr, TcpListener, UdpSocket};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use log::error;
+use std::time::Duration;
+use chrono::{DateTime, FixedOffset, NaiveDate, TimeZone, Utc};
+use log::{debug, error};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use toml_edit as toml;
-use crate::error::Failed;
+use crate::error::{ExitError, Failed};
+use crate::process::LIFECYCLE_TARGET;
 
 
 //------------ ConfigFile ----------------------------------------------------
@@ -29,6 +34,15 @@ pub struct ConfigFile {
     ///
     /// This is used in relative paths.
     dir: PathBuf,
+
+    /// The dotted path of the table within the file.
+    ///
+    /// This is empty for the top-level table and is used in error
+    /// messages.
+    section: String,
+
+    /// Whether to expand environment variables in string values.
+    expand_env: bool,
 }
 
 impl ConfigFile {
@@ -53,6 +67,40 @@ impl ConfigFile {
         Self::parse(&config, path).map(Some)
     }
 
+    /// Reads the config file at the given path which must exist.
+    ///
+    /// This is the same as [`read`][Self::read] except that it is an error
+    /// if there is no such file. Use this if the daemon requires a config
+    /// file, e.g., if a config file was given explicitly on the command
+    /// line.
+    pub fn read_required(path: &Path) -> Result<Self, Failed> {
+        match fs::read_to_string(path) {
+            Ok(content) => Self::parse(&content, path),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                error!("Config file {} not found.", path.display());
+                Err(Failed)
+            }
+            Err(err) => {
+                error!(
+                    "Failed to read config file {}: {}",
+                    path.display(), err
+                );
+                Err(Failed)
+            }
+        }
+    }
+
+    /// Reads the config file at the given path for use by a daemon.
+    ///
+    /// This is the same as [`read_required`][Self::read_required] except
+    /// that an error is turned into an [`ExitError`] with the exit code
+    /// [`ExitError::EX_CONFIG`]. Together with [`finish`][Self::finish],
+    /// this allows a daemon to report configuration problems to its
+    /// supervisor following the `sysexits.h` conventions.
+    pub fn load(path: &Path) -> Result<Self, ExitError> {
+        Self::read_required(path).map_err(Self::exit_error)
+    }
+
     /// Parses the content of the file from a string.
     pub fn parse(content: &str, path: &Path) -> Result<Self, Failed> {
         let content = match toml::DocumentMut::from_str(content) {
@@ -83,15 +131,425 @@ impl ConfigFile {
         Ok(ConfigFile {
             content,
             path: path.into(),
-            dir
+            dir,
+            section: String::new(),
+            expand_env: false,
         })
     }
 
+    /// Includes the files given in the `include` key.
+    ///
+    /// Including other files is opt-in: unless this method is called,
+    /// `include` is an ordinary key like any other.
+    ///
+    /// If there is a top-level `include` key, it must be a string or an
+    /// array of strings each giving the path of another config file to
+    /// include. Relative paths are resolved against the directory of the
+    /// including file. The final component of a path may contain the
+    /// wildcards `*` and `?` in which case all non-hidden files matching
+    /// the pattern are included in lexical order. The key is removed.
+    ///
+    /// The content of the included files is merged into the config file.
+    /// Tables are merged key by key but it is an error if any other key
+    /// appears in more than one file. Included files may include further
+    /// files. Relative paths in included files are resolved against the
+    /// directory of the including file, just like any other path.
+    pub fn process_includes(&mut self) -> Result<(), Failed> {
+        let mut stack = Vec::new();
+        if let Ok(path) = self.path.canonicalize() {
+            stack.push(path)
+        }
+        self.process_includes_with(&mut stack)
+    }
+
+    /// Processes the `include` key, tracking the files being included.
+    ///
+    /// The `stack` contains the canonical paths of all files currently
+    /// being processed and is used to detect include loops.
+    fn process_includes_with(
+        &mut self, stack: &mut Vec<PathBuf>
+    ) -> Result<(), Failed> {
+        let patterns = match self.content.remove("include") {
+            Some(toml::Item::Value(toml::Value::String(value))) => {
+                vec![value.into_value()]
+            }
+            Some(toml::Item::Value(toml::Value::Array(array))) => {
+                let patterns: Option<Vec<_>> = array.into_iter().map(|item| {
+                    match item {
+                        toml::Value::String(value) => Some(value.into_value()),
+                        _ => None
+                    }
+                }).collect();
+                match patterns {
+                    Some(patterns) => patterns,
+                    None => {
+                        error!(
+                            "Failed in config file {}: \
+                             'include' expected to be a string or \
+                             an array of strings.",
+                            self.path.display()
+                        );
+                        return Err(Failed)
+                    }
+                }
+            }
+            Some(_) => {
+                error!(
+                    "Failed in config file {}: \
+                     'include' expected to be a string or \
+                     an array of strings.",
+                    self.path.display()
+                );
+                return Err(Failed)
+            }
+            None => return Ok(())
+        };
+        for pattern in patterns {
+            for path in self.expand_include(&self.dir.join(pattern))? {
+                self.include_file(&path, stack)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Expands an include pattern into a list of paths.
+    ///
+    /// If the final component of the pattern contains wildcards, returns
+    /// all matching non-hidden files in lexical order. Otherwise returns
+    /// the path itself.
+    fn expand_include(&self, pattern: &Path) -> Result<Vec<PathBuf>, Failed> {
+        let name = match pattern.file_name() {
+            Some(name) => name.to_string_lossy(),
+            None => return Ok(vec![pattern.into()])
+        };
+        if !name.contains(['*', '?']) {
+            return Ok(vec![pattern.into()])
+        }
+        let dir = pattern.parent().unwrap_or(&self.dir);
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                error!(
+                    "Failed in config file {}: \
+                     cannot read include directory {}: {}",
+                    self.path.display(), dir.display(), err
+                );
+                return Err(Failed)
+            }
+        };
+        let mut res = Vec::new();
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    error!(
+                        "Failed in config file {}: \
+                         cannot read include directory {}: {}",
+                        self.path.display(), dir.display(), err
+                    );
+                    return Err(Failed)
+                }
+            };
+            let file_name = entry.file_name();
+            let file_name = file_name.to_string_lossy();
+            let path = entry.path();
+            if file_name.starts_with('.')
+                || !Self::wildcard_match(&name, &file_name)
+                || !path.is_file()
+            {
+                continue
+            }
+            res.push(path);
+        }
+        res.sort();
+        Ok(res)
+    }
+
+    /// Returns whether `name` matches a pattern with `*` and `?` wildcards.
+    fn wildcard_match(pattern: &str, name: &str) -> bool {
+        let pattern: Vec<_> = pattern.chars().collect();
+        let name: Vec<_> = name.chars().collect();
+
+        // Position in pattern and name after the last `*` seen, if any.
+        let mut star = None;
+        let (mut p, mut n) = (0, 0);
+        while n < name.len() {
+            match pattern.get(p) {
+                Some('*') => {
+                    star = Some((p + 1, n));
+                    p += 1;
+                }
+                Some(&ch) if ch == '?' || ch == name[n] => {
+                    p += 1;
+                    n += 1;
+                }
+                _ => {
+                    match star {
+                        Some((star_p, star_n)) => {
+                            p = star_p;
+                            n = star_n + 1;
+                            star = Some((star_p, star_n + 1));
+                        }
+                        None => return false
+                    }
+                }
+            }
+        }
+        pattern[p..].iter().all(|&ch| ch == '*')
+    }
+
+    /// Reads an included file and merges it into the config file.
+    fn include_file(
+        &mut self, path: &Path, stack: &mut Vec<PathBuf>
+    ) -> Result<(), Failed> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) => {
+                error!(
+                    "Failed in config file {}: \
+                     cannot read included file {}: {}",
+                    self.path.display(), path.display(), err
+                );
+                return Err(Failed)
+            }
+        };
+        let canonical = match path.canonicalize() {
+            Ok(canonical) => canonical,
+            Err(err) => {
+                error!(
+                    "Failed in config file {}: \
+                     cannot read included file {}: {}",
+                    self.path.display(), path.display(), err
+                );
+                return Err(Failed)
+            }
+        };
+        if stack.contains(&canonical) {
+            error!(
+                "Failed in config file {}: \
+                 file {} includes itself.",
+                self.path.display(), path.display()
+            );
+            return Err(Failed)
+        }
+        stack.push(canonical);
+        let included = Self::parse(&content, path).and_then(|mut included| {
+            included.process_includes_with(stack)?;
+            Ok(included)
+        });
+        stack.pop();
+        Self::merge_table(
+            self.content.as_table_mut(), included?.content.as_table(),
+            path, false, ""
+        )
+    }
+
+    /// Reads and merges all config fragments in a directory.
+    ///
+    /// All files in `dir` with a `.toml` extension are read in lexical
+    /// order of their file names and merged into a single config file.
+    /// Hidden files are ignored. If a key appears in more than one
+    /// fragment, the value from the later fragment wins. Tables are merged
+    /// key by key.
+    ///
+    /// Relative paths in all fragments are resolved against `dir`. It is an
+    /// error if the directory doesn’t exist.
+    pub fn read_dir(dir: &Path) -> Result<Self, Failed> {
+        Self::read_dir_fragments(dir, true)
+    }
+
+    /// Reads and merges config fragments, rejecting conflicting keys.
+    ///
+    /// This is the same as [`read_dir`][Self::read_dir] except that it is
+    /// an error if a key other than a table appears in more than one
+    /// fragment.
+    pub fn read_dir_strict(dir: &Path) -> Result<Self, Failed> {
+        Self::read_dir_fragments(dir, false)
+    }
+
+    /// Reads and merges config fragments.
+    fn read_dir_fragments(
+        dir: &Path, allow_override: bool
+    ) -> Result<Self, Failed> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                error!(
+                    "Failed to read config directory {}: {}",
+                    dir.display(), err
+                );
+                return Err(Failed)
+            }
+        };
+        let mut paths = Vec::new();
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    error!(
+                        "Failed to read config directory {}: {}",
+                        dir.display(), err
+                    );
+                    return Err(Failed)
+                }
+            };
+            let path = entry.path();
+            if entry.file_name().to_string_lossy().starts_with('.')
+                || path.extension().map(|ext| ext != "toml").unwrap_or(true)
+                || !path.is_file()
+            {
+                continue
+            }
+            paths.push(path);
+        }
+        paths.sort();
+
+        let mut res = Self::parse("", &dir.join("config.toml"))?;
+        res.path = dir.into();
+        for path in paths {
+            let fragment = match Self::read(&path)? {
+                Some(fragment) => fragment,
+                None => {
+                    error!(
+                        "Failed to read config file {}.", path.display()
+                    );
+                    return Err(Failed)
+                }
+            };
+            Self::merge_table(
+                res.content.as_table_mut(), fragment.content.as_table(),
+                &path, allow_override, ""
+            )?;
+        }
+        Ok(res)
+    }
+
+    /// Merges the content of a fragment into a table.
+    fn merge_table(
+        target: &mut toml::Table,
+        source: &toml::Table,
+        path: &Path,
+        allow_override: bool,
+        prefix: &str,
+    ) -> Result<(), Failed> {
+        for (key, item) in source.iter() {
+            let full_key = if prefix.is_empty() {
+                key.to_string()
+            }
+            else {
+                format!("{}.{}", prefix, key)
+            };
+            match (target.get_mut(key), item) {
+                (
+                    Some(toml::Item::Table(target)), toml::Item::Table(source)
+                ) => {
+                    Self::merge_table(
+                        target, source, path, allow_override, &full_key
+                    )?;
+                }
+                (Some(_), _) if !allow_override => {
+                    error!(
+                        "Failed in config file {}: \
+                         '{}' already set in an earlier file.",
+                        path.display(), full_key
+                    );
+                    return Err(Failed)
+                }
+                _ => {
+                    target.insert(key, item.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Returns a reference to the path of the config file.
     pub fn path(&self) -> &Path {
         &self.path
     }
 
+    /// Returns the directory relative paths are resolved against.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Sets whether environment variables in string values are expanded.
+    ///
+    /// If enabled, [`take_string`][Self::take_string] and all methods
+    /// based on it, such as [`take_path`][Self::take_path] and
+    /// [`take_from_str`][Self::take_from_str], as well as the array
+    /// variants such as [`take_string_array`][Self::take_string_array],
+    /// replace each `${VAR}` in the values with the content of the
+    /// environment variable `VAR`. A
+    /// literal `$` can be written as `$$`. It is an error if a variable
+    /// isn’t set. Values taken via [`take_raw_path`][Self::take_raw_path]
+    /// are never expanded.
+    ///
+    /// Expansion is disabled by default. Tables taken from the file via
+    /// [`take_table`][Self::take_table] inherit the setting.
+    pub fn set_expand_env(&mut self, expand: bool) {
+        self.expand_env = expand
+    }
+
+    /// Migrates the config file to the current schema version.
+    ///
+    /// The schema version of the file is taken from the `config-version`
+    /// key which must be a non-negative integer. If the key is missing,
+    /// the version is assumed to be 0.
+    ///
+    /// Each element of `migrations` contains the version a migration
+    /// upgrades to and the function performing the migration by
+    /// manipulating the config file. All migrations for versions greater
+    /// than the file’s version up to and including `current` are applied
+    /// in the order of their versions.
+    ///
+    /// Afterwards, the `config-version` key is set to `current`, so that
+    /// writing the file back records the new version. The key needs to be
+    /// taken again before calling
+    /// [`check_exhausted`][Self::check_exhausted].
+    ///
+    /// The method should be called before any other values are taken. It
+    /// returns the version of the file before migration. It returns an
+    /// error if the file’s version is newer than `current`.
+    pub fn migrate(
+        &mut self,
+        current: u32,
+        migrations: &[Migration],
+    ) -> Result<u32, Failed> {
+        let version = match self.take_u64("config-version")? {
+            Some(version) => match u32::try_from(version) {
+                Ok(version) => version,
+                Err(_) => {
+                    error!(
+                        "Failed in config file {}: \
+                         invalid value for 'config-version'.",
+                        self.path.display()
+                    );
+                    return Err(Failed)
+                }
+            }
+            None => 0
+        };
+        if version > current {
+            error!(
+                "Failed in config file {}: \
+                 config version {} is newer than supported version {}.",
+                self.path.display(), version, current
+            );
+            return Err(Failed)
+        }
+
+        let mut migrations: Vec<_> = migrations.iter().filter(|item| {
+            item.0 > version && item.0 <= current
+        }).collect();
+        migrations.sort_by_key(|item| item.0);
+        for (_, op) in migrations {
+            op(self)
+        }
+        self.insert_value("config-version", i64::from(current));
+        Ok(version)
+    }
+
     /// Takes a value from the from the config file if present.
     pub fn take_value(
         &mut self, key: &str
@@ -102,7 +560,7 @@ impl ConfigFile {
                 error!(
                     "Failed in config file {}: \
                      '{}' expected to be a value.",
-                    self.path.display(), key
+                    self.path.display(), self.section_key(key)
                 );
                 Err(Failed)
             }
@@ -111,6 +569,131 @@ impl ConfigFile {
     }
     
 
+    /// Returns whether the given key contains a table.
+    ///
+    /// This is true for both a table given via the `[key]` syntax and an
+    /// inline table.
+    pub fn is_table(&self, key: &str) -> bool {
+        matches!(
+            self.content.get(key),
+            Some(toml::Item::Table(_))
+            | Some(toml::Item::Value(toml::Value::InlineTable(_)))
+        )
+    }
+
+    /// Takes a table from the config file.
+    ///
+    /// The table is taken from the given `key` and returned as a config file
+    /// of its own with the same path and directory. This allows processing
+    /// nested sections, such as a `[tls]` section, with the same methods as
+    /// the top level. In particular, relative paths are resolved against
+    /// the directory of the config file and
+    /// [`check_exhausted`][Self::check_exhausted] on the returned value
+    /// checks for unknown settings in the section.
+    ///
+    /// Returns `Ok(None)` if there is no such key. Returns an error if the
+    /// key exists but the value isn’t a table.
+    pub fn take_table(
+        &mut self, key: &str
+    ) -> Result<Option<ConfigFile>, Failed> {
+        let table = match self.content.remove(key) {
+            Some(toml::Item::Table(table)) => table,
+            Some(toml::Item::Value(toml::Value::InlineTable(table))) => {
+                table.into_table()
+            }
+            Some(_) => {
+                error!(
+                    "Failed in config file {}: \
+                     '{}' expected to be a table.",
+                    self.path.display(), self.section_key(key)
+                );
+                return Err(Failed)
+            }
+            None => return Ok(None)
+        };
+        Ok(Some(ConfigFile {
+            content: table.into(),
+            path: self.path.clone(),
+            dir: self.dir.clone(),
+            section: self.section_key(key),
+            expand_env: self.expand_env,
+        }))
+    }
+
+    /// Returns whether the given key contains an array of tables.
+    ///
+    /// This is true for both an array of tables given via the
+    /// `[[key]]` syntax and an array of inline tables.
+    pub(crate) fn is_table_array(&self, key: &str) -> bool {
+        match self.content.get(key) {
+            Some(toml::Item::ArrayOfTables(_)) => true,
+            Some(toml::Item::Value(toml::Value::Array(array))) => {
+                !array.is_empty() && array.iter().all(|item| {
+                    item.is_inline_table()
+                })
+            }
+            _ => false
+        }
+    }
+
+    /// Takes an array of tables from the config file.
+    ///
+    /// The array is taken from the given `key`. It can be given either via
+    /// the `[[key]]` syntax or as an array of inline tables. Each table is
+    /// returned as a config file of its own with the same path and
+    /// directory.
+    ///
+    /// Returns `Ok(None)` if there is no such key. Returns an error if the
+    /// key exists but the value isn’t an array of tables.
+    pub(crate) fn take_table_array(
+        &mut self, key: &str
+    ) -> Result<Option<Vec<ConfigFile>>, Failed> {
+        let tables = match self.content.remove(key) {
+            Some(toml::Item::ArrayOfTables(array)) => {
+                array.into_iter().collect()
+            }
+            Some(toml::Item::Value(toml::Value::Array(array))) => {
+                let tables: Option<Vec<_>> = array.into_iter().map(|item| {
+                    match item {
+                        toml::Value::InlineTable(table) => {
+                            Some(table.into_table())
+                        }
+                        _ => None
+                    }
+                }).collect();
+                match tables {
+                    Some(tables) => tables,
+                    None => {
+                        error!(
+                            "Failed in config file {}: \
+                             '{}' expected to be an array of tables.",
+                            self.path.display(), self.section_key(key)
+                        );
+                        return Err(Failed)
+                    }
+                }
+            }
+            Some(_) => {
+                error!(
+                    "Failed in config file {}: \
+                     '{}' expected to be an array of tables.",
+                    self.path.display(), self.section_key(key)
+                );
+                return Err(Failed)
+            }
+            None => return Ok(None)
+        };
+        Ok(Some(tables.into_iter().enumerate().map(|(idx, table)| {
+            ConfigFile {
+                content: table.into(),
+                path: self.path.clone(),
+                dir: self.dir.clone(),
+                section: format!("{}[{}]", self.section_key(key), idx),
+                expand_env: self.expand_env,
+            }
+        }).collect()))
+    }
+
     /// Takes a boolean value from the config file.
     ///
     /// The value is taken from the given `key`. Returns `Ok(None)` if there
@@ -123,7 +706,7 @@ impl ConfigFile {
                 error!(
                     "Failed in config file {}: \
                      '{}' expected to be a boolean.",
-                    self.path.display(), key
+                    self.path.display(), self.section_key(key)
                 );
                 Err(Failed)
             }
@@ -131,6 +714,45 @@ impl ConfigFile {
         }
     }
 
+    /// Takes a boolean value from the config file allowing strings.
+    ///
+    /// The value is taken from the given `key`. In addition to native
+    /// booleans, the strings `"on"`, `"yes"`, `"true"`, and `"1"` are
+    /// accepted for true and `"off"`, `"no"`, `"false"`, and `"0"` for
+    /// false, all ignoring case.
+    ///
+    /// Returns `Ok(None)` if there is no such key. Returns an error if the
+    /// key exists but the value isn’t one of the above.
+    pub fn take_flexible_bool(
+        &mut self, key: &str
+    ) -> Result<Option<bool>, Failed> {
+        let res = match self.take_value(key)? {
+            Some(toml::Value::Boolean(res)) => Some(res.into_value()),
+            Some(toml::Value::String(value)) => {
+                let value = value.value().to_ascii_lowercase();
+                match value.as_str() {
+                    "on" | "yes" | "true" | "1" => Some(true),
+                    "off" | "no" | "false" | "0" => Some(false),
+                    _ => None
+                }
+            }
+            Some(_) => None,
+            None => return Ok(None)
+        };
+        match res {
+            Some(res) => Ok(Some(res)),
+            None => {
+                error!(
+                    "Failed in config file {}: \
+                     '{}' expected to be a boolean or one of \
+                     'on', 'off', 'yes', 'no', '1', '0'.",
+                    self.path.display(), self.section_key(key)
+                );
+                Err(Failed)
+            }
+        }
+    }
+
     /// Takes an unsigned integer value from the config file.
     ///
     /// The value is taken from the given `key`. Returns `Ok(None)` if there
@@ -145,7 +767,49 @@ impl ConfigFile {
                         error!(
                             "Failed in config file {}: \
                             '{}' expected to be a positive integer.",
-                            self.path.display(), key
+                            self.path.display(), self.section_key(key)
+                        );
+                        Err(Failed)
+                    }
+                }
+            }
+            Some(_) => {
+                error!(
+                    "Failed in config file {}: \
+                     '{}' expected to be an integer.",
+                    self.path.display(), self.section_key(key)
+                );
+                Err(Failed)
+            }
+            None => Ok(None)
+        }
+    }
+
+    /// Takes an unsigned integer value within a range from the config file.
+    ///
+    /// The value is taken from the given `key`. Returns `Ok(None)` if there
+    /// is no such key. Returns an error if the key exists but the value
+    /// isn’t an integer or is outside the inclusive range from `min` to
+    /// `max`.
+    ///
+    /// `min` must not be greater than `max`. This is checked in debug
+    /// builds.
+    pub fn take_u64_range(
+        &mut self, key: &str, min: u64, max: u64,
+    ) -> Result<Option<u64>, Failed> {
+        debug_assert!(min <= max, "empty range {}..={}", min, max);
+        match self.take_value(key)? {
+            Some(toml::Value::Integer(value)) => {
+                match u64::try_from(value.into_value()) {
+                    Ok(value) if (min..=max).contains(&value) => {
+                        Ok(Some(value))
+                    }
+                    _ => {
+                        error!(
+                            "Failed in config file {}: \
+                            '{}' expected integer between {} and {}.",
+                            self.path.display(),
+                            self.section_key(key), min, max,
                         );
                         Err(Failed)
                     }
@@ -155,7 +819,7 @@ impl ConfigFile {
                 error!(
                     "Failed in config file {}: \
                      '{}' expected to be an integer.",
-                    self.path.display(), key
+                    self.path.display(), self.section_key(key)
                 );
                 Err(Failed)
             }
@@ -179,7 +843,8 @@ impl ConfigFile {
                             error!(
                                 "Failed in config file {}: \
                                 '{}' expected integer between 0 and {}.",
-                                self.path.display(), key, limit,
+                                self.path.display(),
+                                self.section_key(key), limit,
                             );
                             Err(Failed)
                         }
@@ -191,7 +856,7 @@ impl ConfigFile {
                         error!(
                             "Failed in config file {}: \
                             '{}' expected integer between 0 and {}.",
-                            self.path.display(), key, limit,
+                            self.path.display(), self.section_key(key), limit,
                         );
                         Err(Failed)
                     }
@@ -215,7 +880,7 @@ impl ConfigFile {
                         error!(
                             "Failed in config file {}: \
                             '{}' expected to be a positive integer.",
-                            self.path.display(), key
+                            self.path.display(), self.section_key(key)
                         );
                         Err(Failed)
                     }
@@ -242,7 +907,7 @@ impl ConfigFile {
                     error!(
                         "Failed in config file {}: \
                         value for '{}' is too large.",
-                        self.path.display(), key
+                        self.path.display(), self.section_key(key)
                     );
                     Err(Failed)
                 }
@@ -254,23 +919,302 @@ impl ConfigFile {
         }
     }
 
-    /// Takes a string value from the config file.
+    /// Takes an unsigned integer value within a range from the config file.
     ///
-    /// The value is taken from the given `key`. Returns `Ok(None)` if there
-    /// is no such key. Returns an error if the key exists but the value
-    /// isn’t a string.
-    pub fn take_string(
-        &mut self, key: &str
-    ) -> Result<Option<String>, Failed> {
+    /// This is the same as [`take_u64_range`][Self::take_u64_range] but
+    /// for a `usize`.
+    pub fn take_usize_range(
+        &mut self, key: &str, min: usize, max: usize,
+    ) -> Result<Option<usize>, Failed> {
+        // usize is at most 64 bits, so converting the bounds is lossless
+        // and the result is within the range of usize.
+        self.take_u64_range(key, min as u64, max as u64).map(|value| {
+            value.map(|value| value as usize)
+        })
+    }
+
+    /// Takes a floating point value from the config file.
+    ///
+    /// The value is taken from the given `key`. Both floating point numbers
+    /// and integers are accepted, the latter are converted into floating
+    /// point numbers.
+    ///
+    /// Returns `Ok(None)` if there is no such key. Returns an error if the
+    /// key exists but the value isn’t a number.
+    pub fn take_f64(&mut self, key: &str) -> Result<Option<f64>, Failed> {
         match self.take_value(key)? {
-            Some(toml::Value::String(value)) => {
-                Ok(Some(value.into_value()))
+            Some(value) => match Self::f64_from_value(&value) {
+                Some(value) => Ok(Some(value)),
+                None => {
+                    error!(
+                        "Failed in config file {}: \
+                         '{}' expected to be a number.",
+                        self.path.display(), self.section_key(key)
+                    );
+                    Err(Failed)
+                }
             }
-            Some(_) => {
+            None => Ok(None)
+        }
+    }
+
+    /// Converts a config value into a floating point number.
+    ///
+    /// Returns `None` if the value isn’t a number.
+    fn f64_from_value(value: &toml::Value) -> Option<f64> {
+        match value {
+            toml::Value::Float(value) => Some(*value.value()),
+            toml::Value::Integer(value) => Some(*value.value() as f64),
+            _ => None
+        }
+    }
+
+    /// Takes a Unix file mode from the config file.
+    ///
+    /// The value is taken from the given `key`. It can either be given as
+    /// an integer, in which case the TOML octal notation `0o640` is
+    /// recommended, or as a string containing an octal number such as
+    /// `"640"`.
+    ///
+    /// Returns `Ok(None)` if there is no such key. Returns an error if the
+    /// key exists but the value isn’t a valid mode, i.e., not in the range
+    /// from 0 to `0o7777`.
+    pub fn take_mode(&mut self, key: &str) -> Result<Option<u32>, Failed> {
+        let mode = match self.take_value(key)? {
+            Some(toml::Value::Integer(value)) => {
+                u32::try_from(value.into_value()).ok()
+            }
+            Some(toml::Value::String(value)) => {
+                u32::from_str_radix(value.value(), 8).ok()
+            }
+            Some(_) => None,
+            None => return Ok(None)
+        };
+        match mode {
+            Some(mode) if mode <= 0o7777 => Ok(Some(mode)),
+            _ => {
+                error!(
+                    "Failed in config file {}: \
+                     '{}' expected to be a file mode.",
+                    self.path.display(), self.section_key(key)
+                );
+                Err(Failed)
+            }
+        }
+    }
+
+    /// Takes a duration from the config file.
+    ///
+    /// The value is taken from the given `key`. It can either be a
+    /// non-negative integer giving the duration in seconds or a string
+    /// consisting of one or more non-negative integers each followed by
+    /// one of the units `ns`, `us`, `ms`, `s`, `m`, `h`, or `d`, e.g.,
+    /// `"500ms"`, `"5m"`, or `"1m30s"`. The values of all parts are added
+    /// up. A string containing only an integer is in seconds, too.
+    ///
+    /// Returns `Ok(None)` if there is no such key. Returns an error if the
+    /// key exists but the value isn’t a valid duration.
+    pub fn take_duration(
+        &mut self, key: &str
+    ) -> Result<Option<Duration>, Failed> {
+        let value = match self.take_value(key)? {
+            Some(value) => value,
+            None => return Ok(None)
+        };
+        match Self::duration_from_value(&value, Duration::from_secs) {
+            Some(res) => Ok(Some(res)),
+            None => {
+                error!(
+                    "Failed in config file {}: \
+                     '{}' expected to be a number of seconds or a \
+                     duration such as \"500ms\", \"30s\", or \"1m30s\".",
+                    self.path.display(), self.section_key(key)
+                );
+                Err(Failed)
+            }
+        }
+    }
+
+    /// Takes a duration in milliseconds from the config file.
+    ///
+    /// This is the same as [`take_duration`][Self::take_duration] except
+    /// that an integer without a unit is interpreted as milliseconds. Use
+    /// this for settings that are conventionally given in milliseconds,
+    /// such as network timeouts.
+    pub fn take_duration_millis(
+        &mut self, key: &str
+    ) -> Result<Option<Duration>, Failed> {
+        let value = match self.take_value(key)? {
+            Some(value) => value,
+            None => return Ok(None)
+        };
+        match Self::duration_from_value(&value, Duration::from_millis) {
+            Some(res) => Ok(Some(res)),
+            None => {
+                error!(
+                    "Failed in config file {}: \
+                     '{}' expected to be a number of milliseconds or a \
+                     duration such as \"500ms\", \"30s\", or \"1m30s\".",
+                    self.path.display(), self.section_key(key)
+                );
+                Err(Failed)
+            }
+        }
+    }
+
+    /// Converts a config value into a duration.
+    ///
+    /// An integer without a unit is converted via `bare`. Returns `None` if
+    /// the value isn’t a valid duration.
+    fn duration_from_value(
+        value: &toml::Value, bare: fn(u64) -> Duration,
+    ) -> Option<Duration> {
+        match value {
+            toml::Value::Integer(value) => {
+                u64::try_from(*value.value()).ok().map(bare)
+            }
+            toml::Value::String(value) => {
+                Self::duration_from_str(value.value().trim(), bare)
+            }
+            _ => None
+        }
+    }
+
+    /// Converts a string into a duration.
+    fn duration_from_str(
+        mut value: &str, bare: fn(u64) -> Duration,
+    ) -> Option<Duration> {
+        if value.is_empty() {
+            return None
+        }
+        if value.bytes().all(|ch| ch.is_ascii_digit()) {
+            return u64::from_str(value).ok().map(bare)
+        }
+        let mut res = Duration::ZERO;
+        while !value.is_empty() {
+            let split = value.find(|ch: char| {
+                !ch.is_ascii_digit()
+            }).unwrap_or(value.len());
+            let (number, tail) = value.split_at(split);
+            let number = u64::from_str(number).ok()?;
+            let tail = tail.trim_start();
+            let split = tail.find(|ch: char| {
+                !ch.is_ascii_alphabetic()
+            }).unwrap_or(tail.len());
+            let (unit, tail) = tail.split_at(split);
+            let part = match unit {
+                "ns" => Duration::from_nanos(number),
+                "us" => Duration::from_micros(number),
+                "ms" => Duration::from_millis(number),
+                "s" => Duration::from_secs(number),
+                "m" => Duration::from_secs(number.checked_mul(60)?),
+                "h" => Duration::from_secs(number.checked_mul(60 * 60)?),
+                "d" => {
+                    Duration::from_secs(number.checked_mul(24 * 60 * 60)?)
+                }
+                _ => return None
+            };
+            res = res.checked_add(part)?;
+            value = tail.trim_start();
+        }
+        Some(res)
+    }
+
+    /// Takes a date and time from the config file.
+    ///
+    /// The value is taken from the given `key`. It must be a TOML date-time
+    /// with a time zone offset, such as `2025-01-01T12:00:00Z`, or a TOML
+    /// date, such as `2025-01-01`, which is interpreted as midnight UTC of
+    /// that day. The value is converted to UTC.
+    ///
+    /// Returns `Ok(None)` if there is no such key. Returns an error if the
+    /// key exists but the value is not such a date-time. In particular,
+    /// local date-times without an offset and times without a date are
+    /// rejected.
+    pub fn take_datetime(
+        &mut self, key: &str
+    ) -> Result<Option<DateTime<Utc>>, Failed> {
+        let value = match self.take_value(key)? {
+            Some(toml::Value::Datetime(value)) => value.into_value(),
+            Some(_) => {
+                error!(
+                    "Failed in config file {}: \
+                     '{}' expected to be a date-time.",
+                    self.path.display(), self.section_key(key)
+                );
+                return Err(Failed)
+            }
+            None => return Ok(None)
+        };
+        match Self::datetime_from_toml(&value) {
+            Some(res) => Ok(Some(res)),
+            None => {
+                error!(
+                    "Failed in config file {}: \
+                     '{}' expected to be a date or a date-time with \
+                     time zone offset.",
+                    self.path.display(), self.section_key(key)
+                );
+                Err(Failed)
+            }
+        }
+    }
+
+    /// Converts a TOML date-time into a UTC date-time.
+    ///
+    /// Returns `None` if the value is missing a date, has a time but no
+    /// offset, or is out of range.
+    fn datetime_from_toml(value: &toml::Datetime) -> Option<DateTime<Utc>> {
+        let date = value.date.as_ref()?;
+        let date = NaiveDate::from_ymd_opt(
+            date.year.into(), date.month.into(), date.day.into()
+        )?;
+        let (time, offset) = match (value.time.as_ref(), value.offset) {
+            (Some(time), Some(offset)) => (time, offset),
+            (None, None) => {
+                return Some(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0)?))
+            }
+            _ => return None
+        };
+        let datetime = date.and_hms_nano_opt(
+            time.hour.into(), time.minute.into(), time.second.into(),
+            time.nanosecond
+        )?;
+        let offset = match offset {
+            toml::Offset::Z => 0,
+            toml::Offset::Custom { minutes } => i32::from(minutes) * 60,
+        };
+        Some(
+            FixedOffset::east_opt(offset)?.from_local_datetime(
+                &datetime
+            ).single()?.with_timezone(&Utc)
+        )
+    }
+
+    /// Takes a string value from the config file.
+    ///
+    /// The value is taken from the given `key`. Returns `Ok(None)` if there
+    /// is no such key. Returns an error if the key exists but the value
+    /// isn’t a string.
+    pub fn take_string(
+        &mut self, key: &str
+    ) -> Result<Option<String>, Failed> {
+        self.take_raw_string(key)?.map(|value| {
+            self.expand_string(key, value)
+        }).transpose()
+    }
+
+    /// Takes a string value without expanding environment variables.
+    fn take_raw_string(
+        &mut self, key: &str
+    ) -> Result<Option<String>, Failed> {
+        match self.take_value(key)? {
+            Some(toml::Value::String(value)) => Ok(Some(value.into_value())),
+            Some(_) => {
                 error!(
                     "Failed in config file {}: \
                      '{}' expected to be a string.",
-                    self.path.display(), key
+                    self.path.display(), self.section_key(key)
                 );
                 Err(Failed)
             }
@@ -278,6 +1222,125 @@ impl ConfigFile {
         }
     }
 
+    /// Takes a string value given either inline or via a file.
+    ///
+    /// The value is taken from the given `key`. It can either be a string,
+    /// which is returned as is, or a table with a single key `file`
+    /// containing the path of a file whose content is returned. The path is
+    /// resolved relative to the directory of the config file just like
+    /// with [`take_path`][Self::take_path]. For instance, both
+    /// `banner = "Welcome"` and `banner = { file = "banner.txt" }` are
+    /// possible.
+    ///
+    /// Returns `Ok(None)` if there is no such key. Returns an error if the
+    /// key exists but the value is neither a string nor such a table or if
+    /// the file cannot be read.
+    pub fn take_string_or_file(
+        &mut self, key: &str
+    ) -> Result<Option<String>, Failed> {
+        if !self.is_table(key) {
+            return self.take_string(key)
+        }
+        let mut table = match self.take_table(key)? {
+            Some(table) => table,
+            None => return Ok(None)
+        };
+        let path = match table.take_path("file")? {
+            Some(path) => path,
+            None => {
+                error!(
+                    "Failed in config file {}: \
+                     '{}' expected to be a string or a table with \
+                     a 'file' key.",
+                    self.path.display(), self.section_key(key)
+                );
+                return Err(Failed)
+            }
+        };
+        table.check_exhausted()?;
+        match fs::read_to_string(&path) {
+            Ok(content) => Ok(Some(content)),
+            Err(err) => {
+                error!(
+                    "Failed in config file {}: \
+                     cannot read file {} given in '{}': {}",
+                    self.path.display(), path.display(),
+                    self.section_key(key), err
+                );
+                Err(Failed)
+            }
+        }
+    }
+
+    /// Expands environment variables in `value` if expansion is enabled.
+    fn expand_string(
+        &self, key: &str, value: String
+    ) -> Result<String, Failed> {
+        if self.expand_env {
+            self.expand_env_vars(key, &value)
+        }
+        else {
+            Ok(value)
+        }
+    }
+
+    /// Expands environment variables in the string value of `key`.
+    fn expand_env_vars(
+        &self, key: &str, value: &str
+    ) -> Result<String, Failed> {
+        let mut res = String::with_capacity(value.len());
+        let mut rest = value;
+        while let Some(pos) = rest.find('$') {
+            res.push_str(&rest[..pos]);
+            rest = &rest[pos + 1..];
+            if let Some(tail) = rest.strip_prefix('$') {
+                res.push('$');
+                rest = tail;
+                continue
+            }
+            let name = match rest.strip_prefix('{').and_then(|tail| {
+                tail.find('}').map(|end| (&tail[..end], &tail[end + 1..]))
+            }) {
+                Some((name, tail)) if !name.is_empty() => {
+                    rest = tail;
+                    name
+                }
+                _ => {
+                    error!(
+                        "Failed in config file {}: \
+                         malformed variable reference in '{}'. \
+                         Use '${{NAME}}' for a variable or '$$' for a \
+                         literal '$'.",
+                        self.path.display(), self.section_key(key)
+                    );
+                    return Err(Failed)
+                }
+            };
+            match env::var(name) {
+                Ok(var) => res.push_str(&var),
+                Err(env::VarError::NotPresent) => {
+                    error!(
+                        "Failed in config file {}: \
+                         environment variable '{}' used in '{}' is not set.",
+                        self.path.display(), name, self.section_key(key)
+                    );
+                    return Err(Failed)
+                }
+                Err(env::VarError::NotUnicode(_)) => {
+                    error!(
+                        "Failed in config file {}: \
+                         environment variable '{}' used in '{}' \
+                         is not valid Unicode.",
+                        self.path.display(), name, self.section_key(key)
+                    );
+                    return Err(Failed)
+                }
+            }
+        }
+        res.push_str(rest);
+        Ok(res)
+    }
+
     /// Takes a string encoded value from the config file.
     ///
     /// The value is taken from the given `key`. It is expected to be a
@@ -295,7 +1358,7 @@ impl ConfigFile {
                         error!(
                             "Failed in config file {}: \
                              illegal value in '{}': {}.",
-                            self.path.display(), key, err
+                            self.path.display(), self.section_key(key), err
                         );
                         Err(Failed)
                     }
@@ -322,6 +1385,23 @@ impl ConfigFile {
         })
     }
 
+    /// Takes a path value from the config file without resolving it.
+    ///
+    /// The path is taken from the given `key`. It must be a string value.
+    /// Unlike [`take_path`][Self::take_path], the path is returned exactly
+    /// as given, i.e., a relative path stays relative and environment
+    /// variables are not expanded even if
+    /// [`set_expand_env`][Self::set_expand_env] is enabled. This leaves
+    /// it to the caller to interpret any variables.
+    ///
+    /// Returns `Ok(None)` if the key does not exist. Returns an error if the
+    /// key exists but the value isn’t a string.
+    pub fn take_raw_path(
+        &mut self, key: &str
+    ) -> Result<Option<PathBuf>, Failed> {
+        self.take_raw_string(key).map(|opt| opt.map(Into::into))
+    }
+
     /// Takes a mandatory path value from the config file.
     ///
     /// This is the pretty much the same as [`take_path`] but also returns
@@ -336,13 +1416,89 @@ impl ConfigFile {
             None => {
                 error!(
                     "Failed in config file {}: missing required '{}'.",
-                    self.path.display(), key
+                    self.path.display(), self.section_key(key)
                 );
                 Err(Failed)
             }
         }
     }
 
+    /// Takes the path to a private key from the config file.
+    ///
+    /// The path is taken from the given `key` and resolved just like
+    /// [`take_path`][Self::take_path] does. In addition, on Unix systems,
+    /// the file is checked for being accessible by group or others. If it
+    /// is and `strict` is `true`, an error is returned. If `strict` is
+    /// `false`, only a warning is logged.
+    ///
+    /// Returns `Ok(None)` if the key does not exist. Returns an error if the
+    /// key exists but the value isn’t a string, if the file cannot be
+    /// accessed, or if the permissions are too open in strict mode.
+    pub fn take_private_key_path(
+        &mut self, key: &str, strict: bool,
+    ) -> Result<Option<ConfigPath>, Failed> {
+        let path = match self.take_path(key)? {
+            Some(path) => path,
+            None => return Ok(None)
+        };
+        self.check_private_key_mode(key, &path, strict)?;
+        Ok(Some(path))
+    }
+
+    /// Checks that a private key file isn’t accessible by group or others.
+    #[cfg(unix)]
+    fn check_private_key_mode(
+        &self, key: &str, path: &Path, strict: bool,
+    ) -> Result<(), Failed> {
+        use std::os::unix::fs::PermissionsExt;
+        use log::warn;
+
+        let mode = match fs::metadata(path) {
+            Ok(metadata) => metadata.permissions().mode(),
+            Err(err) => {
+                error!(
+                    "Failed in config file {}: \
+                     cannot access private key file {} given in '{}': {}",
+                    self.path.display(), path.display(),
+                    self.section_key(key), err
+                );
+                return Err(Failed)
+            }
+        };
+        if mode & 0o077 == 0 {
+            return Ok(())
+        }
+        if strict {
+            error!(
+                "Failed in config file {}: \
+                 private key file {} given in '{}' has permissions {:o} \
+                 which allow access by group or others.",
+                self.path.display(), path.display(),
+                self.section_key(key), mode & 0o777
+            );
+            Err(Failed)
+        }
+        else {
+            warn!(
+                "Private key file {} given in '{}' has permissions {:o} \
+                 which allow access by group or others.",
+                path.display(), self.section_key(key), mode & 0o777
+            );
+            Ok(())
+        }
+    }
+
+    /// Checks that a private key file isn’t accessible by group or others.
+    ///
+    /// There are no Unix-style permissions here, so this does nothing.
+    #[cfg(not(unix))]
+    fn check_private_key_mode(
+        &self, key: &str, path: &Path, strict: bool,
+    ) -> Result<(), Failed> {
+        let _ = (key, path, strict);
+        Ok(())
+    }
+
     /// Takes an array of strings from the config file.
     ///
     /// The value is taken from the entry with the given `key` and, if
@@ -358,14 +1514,14 @@ impl ConfigFile {
                 let mut res = Vec::new();
                 for value in vec.into_iter() {
                     if let toml::Value::String(value) = value {
-                        res.push(value.into_value())
+                        res.push(self.expand_string(key, value.into_value())?)
                     }
                     else {
                         error!(
                             "Failed in config file {}: \
                             '{}' expected to be a array of strings.",
                             self.path.display(),
-                            key
+                            self.section_key(key)
                         );
                         return Err(Failed);
                     }
@@ -376,7 +1532,7 @@ impl ConfigFile {
                 error!(
                     "Failed in config file {}: \
                      '{}' expected to be a array of strings.",
-                    self.path.display(), key
+                    self.path.display(), self.section_key(key)
                 );
                 Err(Failed)
             }
@@ -403,13 +1559,17 @@ impl ConfigFile {
                 let mut res = Vec::new();
                 for value in vec.into_iter() {
                     if let toml::Value::String(value) = value {
-                        match T::from_str(value.value()) {
+                        let value = self.expand_string(
+                            key, value.into_value()
+                        )?;
+                        match T::from_str(&value) {
                             Ok(value) => res.push(value),
                             Err(err) => {
                                 error!(
                                     "Failed in config file {}: \
                                      Invalid value in '{}': {}",
-                                    self.path.display(), key, err
+                                    self.path.display(),
+                                    self.section_key(key), err
                                 );
                                 return Err(Failed)
                             }
@@ -420,7 +1580,7 @@ impl ConfigFile {
                             "Failed in config file {}: \
                             '{}' expected to be a array of strings.",
                             self.path.display(),
-                            key
+                            self.section_key(key)
                         );
                         return Err(Failed)
                     }
@@ -431,7 +1591,7 @@ impl ConfigFile {
                 error!(
                     "Failed in config file {}: \
                      '{}' expected to be a array of strings.",
-                    self.path.display(), key
+                    self.path.display(), self.section_key(key)
                 );
                 Err(Failed)
             }
@@ -439,6 +1599,102 @@ impl ConfigFile {
         }
     }
 
+    /// Takes an enum value from the config file.
+    ///
+    /// The value is taken from the given `key`. It must be a string which
+    /// must be one of the names given in `valid`. It is converted to the
+    /// output type via `FromStr::from_str`.
+    ///
+    /// Returns `Ok(None)` if the key doesn’t exist. Returns an error if the
+    /// key exists but the value isn’t a string, isn’t a valid name, or
+    /// conversion fails. The error message lists the valid names.
+    pub fn take_enum<T>(
+        &mut self,
+        key: &str,
+        valid: &[&str],
+    ) -> Result<Option<T>, Failed>
+    where T: FromStr, T::Err: fmt::Display {
+        let value = match self.take_string(key)? {
+            Some(value) => value,
+            None => return Ok(None)
+        };
+        if !valid.contains(&value.as_str()) {
+            error!(
+                "Failed in config file {}: \
+                 invalid value '{}' in '{}', expected one of: {}.",
+                self.path.display(), value,
+                self.section_key(key), valid.join(", ")
+            );
+            return Err(Failed)
+        }
+        match T::from_str(&value) {
+            Ok(value) => Ok(Some(value)),
+            Err(err) => {
+                error!(
+                    "Failed in config file {}: \
+                     illegal value in '{}': {}.",
+                    self.path.display(), self.section_key(key), err
+                );
+                Err(Failed)
+            }
+        }
+    }
+
+    /// Takes an array of enum values from the config file.
+    ///
+    /// The value is taken from the entry with the given `key` and, if
+    /// present, the entry is removed. The value must be an array of strings,
+    /// each of which must be one of the names given in `valid`. Each string
+    /// is converted to the output type via `FromStr::from_str`.
+    ///
+    /// If the key is not present, returns `Ok(None)`. If the entry is present
+    /// but not an array of strings, if any of the strings is not a valid
+    /// name, or if a name appears more than once, returns an error. The
+    /// error message names the offending entry and lists the valid names.
+    pub fn take_enum_array<T>(
+        &mut self,
+        key: &str,
+        valid: &[&str],
+    ) -> Result<Option<Vec<T>>, Failed>
+    where T: FromStr, T::Err: fmt::Display {
+        let values = match self.take_string_array(key)? {
+            Some(values) => values,
+            None => return Ok(None)
+        };
+        let mut res = Vec::with_capacity(values.len());
+        for (idx, value) in values.iter().enumerate() {
+            if !valid.contains(&value.as_str()) {
+                error!(
+                    "Failed in config file {}: \
+                     invalid value '{}' in '{}', expected one of: {}.",
+                    self.path.display(), value,
+                    self.section_key(key), valid.join(", ")
+                );
+                return Err(Failed)
+            }
+            if values[..idx].contains(value) {
+                error!(
+                    "Failed in config file {}: \
+                     duplicate value '{}' in '{}'.",
+                    self.path.display(), value, self.section_key(key)
+                );
+                return Err(Failed)
+            }
+            match T::from_str(value) {
+                Ok(value) => res.push(value),
+                Err(err) => {
+                    error!(
+                        "Failed in config file {}: \
+                         Invalid value in '{}': {}",
+                        self.path.display(), self.section_key(key), err
+                    );
+                    return Err(Failed)
+                }
+            }
+        }
+        Ok(Some(res))
+    }
+
     /// Takes an array of paths from the config file.
     ///
     /// The values are taken from the given `key` which must be an array of
@@ -454,20 +1710,24 @@ impl ConfigFile {
     ) -> Result<Option<Vec<ConfigPath>>, Failed> {
         match self.take_value(key)? {
             Some(toml::Value::String(value)) => {
-                Ok(Some(vec![self.dir.join(value.into_value()).into()]))
+                let value = self.expand_string(key, value.into_value())?;
+                Ok(Some(vec![self.dir.join(value).into()]))
             }
             Some(toml::Value::Array(vec)) => {
                 let mut res = Vec::new();
                 for value in vec.into_iter() {
                     if let toml::Value::String(value) = value {
-                        res.push(self.dir.join(value.into_value()).into())
+                        let value = self.expand_string(
+                            key, value.into_value()
+                        )?;
+                        res.push(self.dir.join(value).into())
                     }
                     else {
                         error!(
                             "Failed in config file {}: \
                             '{}' expected to be a array of paths.",
                             self.path.display(),
-                            key
+                            self.section_key(key)
                         );
                         return Err(Failed);
                     }
@@ -478,7 +1738,7 @@ impl ConfigFile {
                 error!(
                     "Failed in config file {}: \
                      '{}' expected to be a array of paths.",
-                    self.path.display(), key
+                    self.path.display(), self.section_key(key)
                 );
                 Err(Failed)
             }
@@ -486,47 +1746,332 @@ impl ConfigFile {
         }
     }
 
-    /// Takes a string-to-string hashmap from the config file.
-    pub fn take_string_map(
+    /// Takes an array of durations from the config file.
+    ///
+    /// The value is taken from the entry with the given `key` and, if
+    /// present, the entry is removed. The value must be an array. Each
+    /// element is a duration as described for
+    /// [`take_duration`][Self::take_duration].
+    ///
+    /// If the key is not present, returns `Ok(None)`. If the entry is present
+    /// but not an array or if any of its elements is invalid, returns an
+    /// error.
+    pub fn take_duration_array(
         &mut self,
         key: &str
-    ) -> Result<Option<HashMap<String, String>>, Failed> {
+    ) -> Result<Option<Vec<Duration>>, Failed> {
         match self.take_value(key)? {
             Some(toml::Value::Array(vec)) => {
-                let mut res = HashMap::new();
-                for value in vec.into_iter() {
-                    let mut pair = match value {
-                        toml::Value::Array(pair) => pair.into_iter(),
-                        _ => {
-                            error!(
-                                "Failed in config file {}: \
-                                '{}' expected to be a array of string pairs.",
-                                self.path.display(),
-                                key
-                            );
-                            return Err(Failed);
-                        }
-                    };
-                    let left = match pair.next() {
-                        Some(toml::Value::String(value)) => value,
-                        _ => {
+                let mut res = Vec::new();
+                for value in vec.iter() {
+                    match Self::duration_from_value(
+                        value, Duration::from_secs
+                    ) {
+                        Some(value) => res.push(value),
+                        None => {
                             error!(
                                 "Failed in config file {}: \
-                                '{}' expected to be a array of string pairs.",
+                                 invalid duration {} in '{}'.",
                                 self.path.display(),
-                                key
+                                value.to_string().trim(), self.section_key(key)
                             );
                             return Err(Failed);
                         }
-                    };
-                    let right = match pair.next() {
-                        Some(toml::Value::String(value)) => value,
-                        _ => {
-                            error!(
-                                "Failed in config file {}: \
-                                '{}' expected to be a array of string pairs.",
-                                self.path.display(),
-                                key
+                    }
+                }
+                Ok(Some(res))
+            }
+            Some(_) => {
+                error!(
+                    "Failed in config file {}: \
+                     '{}' expected to be a array of durations.",
+                    self.path.display(), self.section_key(key)
+                );
+                Err(Failed)
+            }
+            None => Ok(None)
+        }
+    }
+
+    /// Takes an array of floating point values from the config file.
+    ///
+    /// The value is taken from the entry with the given `key` and, if
+    /// present, the entry is removed. The value must be an array of
+    /// numbers. Integers are converted into floating point numbers.
+    ///
+    /// If the key is not present, returns `Ok(None)`. If the entry is present
+    /// but not an array of numbers, returns an error.
+    pub fn take_f64_array(
+        &mut self,
+        key: &str
+    ) -> Result<Option<Vec<f64>>, Failed> {
+        match self.take_value(key)? {
+            Some(toml::Value::Array(vec)) => {
+                let mut res = Vec::new();
+                for value in vec.iter() {
+                    match Self::f64_from_value(value) {
+                        Some(value) => res.push(value),
+                        None => {
+                            error!(
+                                "Failed in config file {}: \
+                                 '{}' expected to be a array of numbers.",
+                                self.path.display(), self.section_key(key)
+                            );
+                            return Err(Failed);
+                        }
+                    }
+                }
+                Ok(Some(res))
+            }
+            Some(_) => {
+                error!(
+                    "Failed in config file {}: \
+                     '{}' expected to be a array of numbers.",
+                    self.path.display(), self.section_key(key)
+                );
+                Err(Failed)
+            }
+            None => Ok(None)
+        }
+    }
+
+    /// Takes an array of numeric ranges from the config file.
+    ///
+    /// The value is taken from the entry with the given `key` and, if
+    /// present, the entry is removed. The value must be an array. Each
+    /// element is either a non-negative integer, a string containing such
+    /// an integer, or a string of the form `"start-end"` describing an
+    /// inclusive range. The start of a range must not be larger than its
+    /// end.
+    ///
+    /// If the key is not present, returns `Ok(None)`. If the entry is present
+    /// but not an array or if any of its elements is invalid, returns an
+    /// error.
+    pub fn take_range_array(
+        &mut self,
+        key: &str
+    ) -> Result<Option<RangeList>, Failed> {
+        match self.take_value(key)? {
+            Some(toml::Value::Array(vec)) => {
+                let mut res = Vec::new();
+                for value in vec.into_iter() {
+                    let range = match value {
+                        toml::Value::Integer(value) => {
+                            u64::try_from(value.into_value()).ok().map(|n| {
+                                n..=n
+                            })
+                        }
+                        toml::Value::String(value) => {
+                            RangeList::parse_range(value.value())
+                        }
+                        _ => None
+                    };
+                    match range {
+                        Some(range) => res.push(range),
+                        None => {
+                            error!(
+                                "Failed in config file {}: \
+                                '{}' expected to be a array of ranges.",
+                                self.path.display(),
+                                self.section_key(key)
+                            );
+                            return Err(Failed);
+                        }
+                    }
+                }
+                Ok(Some(RangeList(res)))
+            }
+            Some(_) => {
+                error!(
+                    "Failed in config file {}: \
+                     '{}' expected to be a array of ranges.",
+                    self.path.display(), self.section_key(key)
+                );
+                Err(Failed)
+            }
+            None => Ok(None)
+        }
+    }
+
+    /// Takes an IP network prefix from the config file.
+    ///
+    /// The value is taken from the given `key` and must be a string in
+    /// CIDR notation such as `"192.0.2.0/24"` or `"2001:db8::/32"`. A
+    /// plain address is taken as a host prefix.
+    ///
+    /// This method is only available with the `ipnet` feature.
+    ///
+    /// Returns `Ok(None)` if there is no such key. Returns an error if the
+    /// key exists but the value isn’t a valid prefix.
+    #[cfg(feature = "ipnet")]
+    pub fn take_ip_net(
+        &mut self, key: &str
+    ) -> Result<Option<ipnet::IpNet>, Failed> {
+        Ok(self.take_from_str::<HostOrNet>(key)?.map(|net| net.0))
+    }
+
+    /// Takes an array of IP network prefixes from the config file.
+    ///
+    /// The value is taken from the given `key` and must be an array of
+    /// strings in the format accepted by
+    /// [`take_ip_net`][Self::take_ip_net].
+    ///
+    /// This method is only available with the `ipnet` feature.
+    ///
+    /// Returns `Ok(None)` if there is no such key. Returns an error if the
+    /// key exists but the value isn’t an array of valid prefixes.
+    #[cfg(feature = "ipnet")]
+    pub fn take_ip_net_array(
+        &mut self, key: &str
+    ) -> Result<Option<Vec<ipnet::IpNet>>, Failed> {
+        Ok(self.take_from_str_array::<HostOrNet>(key)?.map(|nets| {
+            nets.into_iter().map(|net| net.0).collect()
+        }))
+    }
+
+    /// Takes a socket address from the config file.
+    ///
+    /// The value is taken from the given `key` and must be a string
+    /// containing an IP address and a port, such as `"192.0.2.1:53"` or
+    /// `"[2001:db8::1]:53"`.
+    ///
+    /// Returns `Ok(None)` if there is no such key. Returns an error if the
+    /// key exists but the value isn’t a valid socket address. The error
+    /// message explains whether the port is missing or the address or port
+    /// are malformed.
+    pub fn take_addr(
+        &mut self, key: &str
+    ) -> Result<Option<SocketAddr>, Failed> {
+        let value = match self.take_string(key)? {
+            Some(value) => value,
+            None => return Ok(None)
+        };
+        match Self::parse_addr(&value) {
+            Ok(addr) => Ok(Some(addr)),
+            Err(err) => {
+                error!(
+                    "Failed in config file {}: \
+                     invalid address '{}' in '{}': {}.",
+                    self.path.display(), value, self.section_key(key), err
+                );
+                Err(Failed)
+            }
+        }
+    }
+
+    /// Takes an array of socket addresses from the config file.
+    ///
+    /// The value is taken from the given `key` and must be an array of
+    /// strings in the format accepted by [`take_addr`][Self::take_addr].
+    ///
+    /// Returns `Ok(None)` if there is no such key. Returns an error if the
+    /// key exists but the value isn’t an array of valid socket addresses.
+    pub fn take_addr_array(
+        &mut self, key: &str
+    ) -> Result<Option<Vec<SocketAddr>>, Failed> {
+        let values = match self.take_string_array(key)? {
+            Some(values) => values,
+            None => return Ok(None)
+        };
+        let mut res = Vec::with_capacity(values.len());
+        for value in values {
+            match Self::parse_addr(&value) {
+                Ok(addr) => res.push(addr),
+                Err(err) => {
+                    error!(
+                        "Failed in config file {}: \
+                         invalid address '{}' in '{}': {}.",
+                        self.path.display(), value, self.section_key(key), err
+                    );
+                    return Err(Failed)
+                }
+            }
+        }
+        Ok(Some(res))
+    }
+
+    /// Parses a socket address.
+    ///
+    /// If parsing fails, tries to figure out what is wrong and returns a
+    /// description of the problem.
+    fn parse_addr(value: &str) -> Result<SocketAddr, String> {
+        if let Ok(addr) = SocketAddr::from_str(value) {
+            return Ok(addr)
+        }
+        if IpAddr::from_str(value).is_ok()
+            || (value.starts_with('[') && value.ends_with(']'))
+        {
+            return Err("missing port".into())
+        }
+        let (addr, port) = match value.rsplit_once(':') {
+            Some(parts) => parts,
+            None => {
+                return Err(format!("malformed IP address '{}'", value))
+            }
+        };
+        let ip = match addr.strip_prefix('[') {
+            Some(addr) => addr.strip_suffix(']'),
+            None if addr.contains(':') => {
+                // An IPv6 address without brackets and port. Since this
+                // didn’t parse as an address above, it is malformed.
+                return Err(format!("malformed IP address '{}'", value))
+            }
+            None => Some(addr),
+        };
+        match ip.map(IpAddr::from_str) {
+            Some(Ok(_)) => {
+                if u16::from_str(port).is_err() {
+                    Err(format!("malformed port '{}'", port))
+                }
+                else {
+                    Err("malformed address".into())
+                }
+            }
+            _ => Err(format!("malformed IP address '{}'", addr)),
+        }
+    }
+
+    /// Takes a string-to-string hashmap from the config file.
+    pub fn take_string_map(
+        &mut self,
+        key: &str
+    ) -> Result<Option<HashMap<String, String>>, Failed> {
+        match self.take_value(key)? {
+            Some(toml::Value::Array(vec)) => {
+                let mut res = HashMap::new();
+                for value in vec.into_iter() {
+                    let mut pair = match value {
+                        toml::Value::Array(pair) => pair.into_iter(),
+                        _ => {
+                            error!(
+                                "Failed in config file {}: \
+                                '{}' expected to be a array of string pairs.",
+                                self.path.display(),
+                                self.section_key(key)
+                            );
+                            return Err(Failed);
+                        }
+                    };
+                    let left = match pair.next() {
+                        Some(toml::Value::String(value)) => value,
+                        _ => {
+                            error!(
+                                "Failed in config file {}: \
+                                '{}' expected to be a array of string pairs.",
+                                self.path.display(),
+                                self.section_key(key)
+                            );
+                            return Err(Failed);
+                        }
+                    };
+                    let right = match pair.next() {
+                        Some(toml::Value::String(value)) => value,
+                        _ => {
+                            error!(
+                                "Failed in config file {}: \
+                                '{}' expected to be a array of string pairs.",
+                                self.path.display(),
+                                self.section_key(key)
                             );
                             return Err(Failed);
                         }
@@ -536,18 +2081,18 @@ impl ConfigFile {
                             "Failed in config file {}: \
                             '{}' expected to be a array of string pairs.",
                             self.path.display(),
-                            key
+                            self.section_key(key)
                         );
                         return Err(Failed);
                     }
-                    if res.insert(
-                        left.into_value(), right.into_value()
-                    ).is_some() {
+                    let left = self.expand_string(key, left.into_value())?;
+                    let right = self.expand_string(key, right.into_value())?;
+                    if res.insert(left, right).is_some() {
                         error!(
                             "Failed in config file {}: \
                             'duplicate item in '{}'.",
                             self.path.display(),
-                            key
+                            self.section_key(key)
                         );
                         return Err(Failed);
                     }
@@ -558,7 +2103,7 @@ impl ConfigFile {
                 error!(
                     "Failed in config file {}: \
                      '{}' expected to be a array of string pairs.",
-                    self.path.display(), key
+                    self.path.display(), self.section_key(key)
                 );
                 Err(Failed)
             }
@@ -566,30 +2111,110 @@ impl ConfigFile {
         }
     }
 
+    /// Returns an iterator over the keys not yet taken.
+    pub fn remaining_keys(&self) -> impl Iterator<Item = &str> {
+        self.content.iter().map(|(key, _)| key)
+    }
+
+    /// Takes all remaining entries from the config file.
+    ///
+    /// Afterwards, the config file is empty and
+    /// [`check_exhausted`][Self::check_exhausted] will succeed. This can be
+    /// used to pass on keys to be handled elsewhere.
+    pub fn take_remaining(&mut self) -> toml::Table {
+        std::mem::take(self.content.as_table_mut())
+    }
+
     /// Checks whether the config file is now empty.
     ///
-    /// If it isn’t, logs a complaint and returns an error.
+    /// If it isn’t, logs a complaint listing the full dotted path of every
+    /// remaining key, including keys in nested tables, and returns an
+    /// error. For a config file returned by [`take_table`][Self::take_table],
+    /// the paths include the key of the table.
     pub fn check_exhausted(&self) -> Result<(), Failed> {
-        if !self.content.is_empty() {
-            print!(
-                "Failed in config file {}: Unknown settings ",
-                self.path.display()
-            );
-            let mut first = true;
-            for (key, _) in self.content.iter() {
-                if !first {
-                    print!(",");
+        let keys = self.unused_keys();
+        if keys.is_empty() {
+            return Ok(())
+        }
+        error!(
+            "Failed in config file {}: Unknown settings {}.",
+            self.path.display(), keys.join(", ")
+        );
+        Err(Failed)
+    }
+
+    /// Checks whether the config file is now empty for use by a daemon.
+    ///
+    /// This is the same as [`check_exhausted`][Self::check_exhausted]
+    /// except that an error is turned into an [`ExitError`] with the exit
+    /// code [`ExitError::EX_CONFIG`].
+    pub fn finish(&self) -> Result<(), ExitError> {
+        self.check_exhausted().map_err(Self::exit_error)
+    }
+
+    /// Converts a failure to process the config file into an exit error.
+    fn exit_error(err: Failed) -> ExitError {
+        ExitError::from(err).with_code(ExitError::EX_CONFIG)
+    }
+
+    /// Returns the dotted paths of all keys remaining in the file.
+    fn unused_keys(&self) -> Vec<String> {
+        let mut keys = Vec::new();
+        Self::collect_keys(
+            self.content.as_table(), &self.section, &mut keys
+        );
+        keys
+    }
+
+    /// Collects the dotted paths of all keys in a table.
+    ///
+    /// Nested tables and arrays of tables are descended into. A nested
+    /// table without any keys is reported itself.
+    fn collect_keys(
+        table: &dyn toml::TableLike, prefix: &str, keys: &mut Vec<String>
+    ) {
+        for (key, item) in table.iter() {
+            let path = Self::join_key(prefix, key);
+            if let Some(array) = item.as_array_of_tables() {
+                for (idx, table) in array.iter().enumerate() {
+                    let path = format!("{}[{}]", path, idx);
+                    if table.is_empty() {
+                        keys.push(path)
+                    }
+                    else {
+                        Self::collect_keys(table, &path, keys)
+                    }
                 }
-                else {
-                    first = false
+                continue
+            }
+            match item.as_table_like() {
+                Some(table) if !table.is_empty() => {
+                    Self::collect_keys(table, &path, keys)
                 }
-                print!("{}", key);
+                _ => keys.push(path)
             }
-            error!(".");
-            Err(Failed)
+        }
+    }
+
+    /// Returns the dotted path of the section within the file.
+    ///
+    /// This is empty for the top level of the file.
+    pub(crate) fn section(&self) -> &str {
+        &self.section
+    }
+
+    /// Returns the dotted path of `key` within the file.
+    pub(crate) fn section_key(&self, key: &str) -> String {
+        Self::join_key(&self.section, key)
+    }
+
+    /// Appends `key` to the dotted path `prefix`.
+    fn join_key(prefix: &str, key: &str) -> String {
+        if prefix.is_empty() {
+            key.into()
         }
         else {
-            Ok(())
+            format!("{}.{}", prefix, key)
         }
     }
 
@@ -604,6 +2229,11 @@ impl ConfigFile {
         ));
     }
 
+    /// Inserts an arbitrary value.
+    pub fn insert_value(&mut self, key: &str, value: impl Into<toml::Value>) {
+        self.content.insert(key, toml::Item::Value(value.into()));
+    }
+
     /// Insert a path value.
     pub fn insert_path(&mut self, key: &str, path: &Path) {
         let path = match path.strip_prefix(&self.dir) {
@@ -612,6 +2242,391 @@ impl ConfigFile {
         };
         self.insert_string(key, path.display())
     }
+
+    /// Writes the config file to the given path.
+    ///
+    /// The current content is written, i.e., keys that have been taken are
+    /// missing and inserted keys are present. Formatting, comments, and
+    /// the order of all untouched keys are preserved. Use a clone of the
+    /// config file if it should be written in full after processing.
+    ///
+    /// The content of the file can also be obtained as a string via the
+    /// `Display` implementation.
+    pub fn write(&self, path: &Path) -> Result<(), Failed> {
+        if let Err(err) = fs::write(path, self.content.to_string()) {
+            error!(
+                "Failed to write config file {}: {}",
+                path.display(), err
+            );
+            return Err(Failed)
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ConfigFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.content.fmt(f)
+    }
+}
+
+
+//------------ Migration -----------------------------------------------------
+
+/// A migration of a config file to a new schema version.
+///
+/// The first element is the version the migration upgrades to, the second
+/// is the function performing the migration.
+///
+/// This is used by [`ConfigFile::migrate`].
+pub type Migration = (u32, fn(&mut ConfigFile));
+
+
+//------------ RangeList -----------------------------------------------------
+
+/// A list of inclusive numeric ranges.
+///
+/// This is returned by [`ConfigFile::take_range_array`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RangeList(Vec<RangeInclusive<u64>>);
+
+impl RangeList {
+    /// Returns whether `value` is contained in any of the ranges.
+    ///
+    /// This is named differently from the slice method `contains`
+    /// available through `Deref` which checks for a range instead.
+    pub fn contains_value(&self, value: u64) -> bool {
+        self.0.iter().any(|range| range.contains(&value))
+    }
+
+    /// Returns a slice of the ranges.
+    pub fn as_slice(&self) -> &[RangeInclusive<u64>] {
+        self.0.as_slice()
+    }
+
+    /// Parses a single range from a string.
+    ///
+    /// Returns `None` if the string is not a valid range.
+    fn parse_range(s: &str) -> Option<RangeInclusive<u64>> {
+        let s = s.trim();
+        match s.split_once('-') {
+            Some((start, end)) => {
+                let start = u64::from_str(start.trim()).ok()?;
+                let end = u64::from_str(end.trim()).ok()?;
+                if start > end {
+                    return None
+                }
+                Some(start..=end)
+            }
+            None => {
+                let value = u64::from_str(s).ok()?;
+                Some(value..=value)
+            }
+        }
+    }
+}
+
+impl From<RangeList> for Vec<RangeInclusive<u64>> {
+    fn from(list: RangeList) -> Self {
+        list.0
+    }
+}
+
+impl ops::Deref for RangeList {
+    type Target = [RangeInclusive<u64>];
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_slice()
+    }
+}
+
+
+//------------ HostOrNet -----------------------------------------------------
+
+/// An IP network prefix that may also be given as a plain address.
+///
+/// This is used by [`ConfigFile::take_ip_net`] since [`ipnet::IpNet`]
+/// itself requires the prefix length to be present.
+#[cfg(feature = "ipnet")]
+struct HostOrNet(ipnet::IpNet);
+
+#[cfg(feature = "ipnet")]
+impl FromStr for HostOrNet {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = IpAddr::from_str(s) {
+            return Ok(HostOrNet(addr.into()))
+        }
+        ipnet::IpNet::from_str(s).map(HostOrNet).map_err(|_| {
+            "invalid IP prefix"
+        })
+    }
+}
+
+
+//------------ ValidationReport ----------------------------------------------
+
+/// The result of validating a configuration.
+///
+/// The report consists of a list of checked items, each with a name, a
+/// flag whether the check passed, and a message. It can be written either
+/// as a human-readable table or as JSON for consumption by other tools.
+#[derive(Clone, Debug, Default)]
+pub struct ValidationReport {
+    /// The checked items in order of checking.
+    items: Vec<ValidationItem>,
+}
+
+/// A single item of a validation report.
+#[derive(Clone, Debug)]
+pub struct ValidationItem {
+    /// The name of the item checked.
+    pub name: String,
+
+    /// Whether the check passed.
+    pub passed: bool,
+
+    /// A message describing the outcome.
+    pub message: String,
+}
+
+impl ValidationReport {
+    /// Creates a new, empty report.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a passed check to the report.
+    pub fn pass(
+        &mut self, name: impl Into<String>, message: impl Into<String>
+    ) {
+        self.items.push(ValidationItem {
+            name: name.into(), passed: true, message: message.into()
+        })
+    }
+
+    /// Adds a failed check to the report.
+    pub fn fail(
+        &mut self, name: impl Into<String>, message: impl Into<String>
+    ) {
+        self.items.push(ValidationItem {
+            name: name.into(), passed: false, message: message.into()
+        })
+    }
+
+    /// Checks that a TCP listener can be bound to the given address.
+    ///
+    /// The listener is closed again right away. Since binding to
+    /// privileged ports may need the privileges the daemon is started
+    /// with, this should be called before dropping them.
+    pub fn check_tcp_bind(
+        &mut self, name: impl Into<String>, addr: SocketAddr
+    ) {
+        match TcpListener::bind(addr) {
+            Ok(_) => self.pass(name, format!("TCP {} can be bound", addr)),
+            Err(err) => self.fail(
+                name, format!("cannot bind TCP {}: {}", addr, err)
+            ),
+        }
+    }
+
+    /// Checks that a UDP socket can be bound to the given address.
+    ///
+    /// The socket is closed again right away. Since binding to
+    /// privileged ports may need the privileges the daemon is started
+    /// with, this should be called before dropping them.
+    pub fn check_udp_bind(
+        &mut self, name: impl Into<String>, addr: SocketAddr
+    ) {
+        match UdpSocket::bind(addr) {
+            Ok(_) => self.pass(name, format!("UDP {} can be bound", addr)),
+            Err(err) => self.fail(
+                name, format!("cannot bind UDP {}: {}", addr, err)
+            ),
+        }
+    }
+
+    /// Returns whether all checks in the report have passed.
+    pub fn is_ok(&self) -> bool {
+        self.items.iter().all(|item| item.passed)
+    }
+
+    /// Returns a slice of all the items in the report.
+    pub fn items(&self) -> &[ValidationItem] {
+        self.items.as_slice()
+    }
+
+    /// Writes the report in the given format.
+    pub fn write(
+        &self, format: ReportFormat, target: &mut impl io::Write
+    ) -> Result<(), io::Error> {
+        match format {
+            ReportFormat::Text => self.write_text(target),
+            ReportFormat::Json => self.write_json(target),
+        }
+    }
+
+    /// Writes the report as a human-readable table.
+    pub fn write_text(
+        &self, target: &mut impl io::Write
+    ) -> Result<(), io::Error> {
+        let width = self.items.iter().map(|item| {
+            item.name.chars().count()
+        }).max().unwrap_or(0);
+        for item in &self.items {
+            writeln!(target, "{:<width$}  {}  {}",
+                item.name,
+                if item.passed { "ok  " } else { "FAIL" },
+                item.message,
+                width = width,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes the report as JSON.
+    ///
+    /// The output is an object with a boolean `ok` member stating whether
+    /// all checks passed and an `items` array containing an object with
+    /// the `name`, `passed`, and `message` members for each check.
+    pub fn write_json(
+        &self, target: &mut impl io::Write
+    ) -> Result<(), io::Error> {
+        writeln!(target, "{{")?;
+        writeln!(target, "  \"ok\": {},", self.is_ok())?;
+        writeln!(target, "  \"items\": [")?;
+        for (idx, item) in self.items.iter().enumerate() {
+            writeln!(target,
+                "    {{ \"name\": \"{}\", \"passed\": {}, \
+                 \"message\": \"{}\" }}{}",
+                JsonEscape(&item.name), item.passed,
+                JsonEscape(&item.message),
+                if idx + 1 < self.items.len() { "," } else { "" }
+            )?;
+        }
+        writeln!(target, "  ]")?;
+        writeln!(target, "}}")
+    }
+}
+
+
+//------------ ReportFormat --------------------------------------------------
+
+/// The output format of a validation report.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, clap::ValueEnum)]
+pub enum ReportFormat {
+    /// A human-readable table.
+    #[default]
+    Text,
+
+    /// A JSON document.
+    Json,
+}
+
+
+//------------ ReportArgs ----------------------------------------------------
+
+/// The command line arguments for outputting a validation report.
+#[derive(Clone, Debug, clap::Args)]
+#[group(id = "report-args")]
+pub struct ReportArgs {
+    /// The output format of the configuration check
+    #[arg(long, value_name = "FORMAT", value_enum, default_value_t)]
+    format: ReportFormat,
+}
+
+impl ReportArgs {
+    /// Returns the selected output format.
+    pub fn format(&self) -> ReportFormat {
+        self.format
+    }
+}
+
+
+//------------ JsonEscape ----------------------------------------------------
+
+/// Displays a string escaped for use in a JSON string.
+pub(crate) struct JsonEscape<'a>(pub &'a str);
+
+impl fmt::Display for JsonEscape<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use std::fmt::Write;
+
+        for ch in self.0.chars() {
+            match ch {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\r' => f.write_str("\\r")?,
+                '\t' => f.write_str("\\t")?,
+                ch if (ch as u32) < 0x20 => {
+                    write!(f, "\\u{:04x}", ch as u32)?
+                }
+                ch => f.write_char(ch)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+
+//------------ parent_dir ----------------------------------------------------
+
+/// Returns the directory the given path lives in.
+///
+/// Unlike `Path::parent`, this returns `.` rather than an empty path for
+/// a relative path consisting of a single component. Returns `None` if
+/// the path is a root or empty.
+pub(crate) fn parent_dir(path: &Path) -> Option<&Path> {
+    match path.parent() {
+        Some(dir) if dir.as_os_str().is_empty() => Some(Path::new(".")),
+        res => res
+    }
+}
+
+
+//------------ Logging Settings ----------------------------------------------
+
+/// Parts of names that indicate secrets.
+///
+/// Settings or environment variables whose upper-cased name contains any
+/// of these are never logged.
+const SECRET_NAME_PATTERNS: &[&str] = &[
+    "PASS", "SECRET", "TOKEN", "KEY", "CREDENTIAL", "AUTH", "PRIVATE",
+    "COOKIE", "SESSION",
+];
+
+/// Returns whether the name of a setting or variable suggests a secret.
+pub(crate) fn is_secret_name(name: &str) -> bool {
+    let upper = name.to_ascii_uppercase();
+    SECRET_NAME_PATTERNS.iter().any(|pat| upper.contains(pat))
+}
+
+/// Logs a setting of the effective configuration at debug level.
+///
+/// If the name of the setting suggests that its value is a secret, the
+/// value is replaced with `<redacted>`.
+pub(crate) fn log_setting(key: &str, value: impl fmt::Display) {
+    if is_secret_name(key) {
+        debug!(target: LIFECYCLE_TARGET, "Config: {} = <redacted>", key);
+    }
+    else {
+        debug!(target: LIFECYCLE_TARGET, "Config: {} = {}", key, value);
+    }
+}
+
+/// Logs an optional setting of the effective configuration.
+///
+/// This is like [`log_setting`] but logs `<not set>` if the value is
+/// `None`.
+pub(crate) fn log_opt_setting(key: &str, value: Option<impl fmt::Display>) {
+    match value {
+        Some(value) => log_setting(key, value),
+        None => {
+            debug!(target: LIFECYCLE_TARGET, "Config: {} = <not set>", key)
+        }
+    }
 }
 
 
@@ -687,6 +2702,20 @@ impl ConfigPath {
     pub fn as_path(&self) -> &Path {
         self.0.as_ref()
     }
+
+    /// Returns whether the path refers to the same file as `other`.
+    ///
+    /// If both paths exist, they are compared after resolving symbolic
+    /// links. Otherwise they are compared as they are.
+    pub fn is_same_file(&self, other: &Path) -> bool {
+        if self.0 == other {
+            return true
+        }
+        match (self.0.canonicalize(), other.canonicalize()) {
+            (Ok(left), Ok(right)) => left == right,
+            _ => false
+        }
+    }
 }
 
 impl From<PathBuf> for ConfigPath {
@@ -785,3 +2814,592 @@ impl clap::builder::TypedValueParser for ConfigPathParser {
     }
 }
 
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config(content: &str) -> ConfigFile {
+        ConfigFile::parse(content, Path::new("/test.conf")).unwrap()
+    }
+
+    #[test]
+    fn take_raw_path_is_not_expanded() {
+        env::set_var("DAEMONBASE_TEST_RAW_PATH", "expanded");
+        let mut file = config(
+            "raw = \"$STATE_DIRECTORY/${DAEMONBASE_TEST_RAW_PATH}\"\n\
+             path = \"${DAEMONBASE_TEST_RAW_PATH}/log\"\n"
+        );
+        file.set_expand_env(true);
+        assert_eq!(
+            file.take_raw_path("raw").unwrap(),
+            Some(PathBuf::from("$STATE_DIRECTORY/${DAEMONBASE_TEST_RAW_PATH}"))
+        );
+        assert_eq!(
+            file.take_path("path").unwrap().unwrap().as_path(),
+            Path::new("/expanded/log")
+        );
+    }
+
+    #[test]
+    fn expand_env() {
+        env::set_var("DAEMONBASE_TEST_EXPAND", "value");
+        env::remove_var("DAEMONBASE_TEST_UNSET");
+        let content = "var = \"a-${DAEMONBASE_TEST_EXPAND}-b\"\n\
+                       dollar = \"$$HOME\"\n\
+                       unset = \"${DAEMONBASE_TEST_UNSET}\"\n\
+                       bare = \"$HOME\"\n\
+                       unclosed = \"${HOME\"\n\
+                       empty = \"${}\"\n\
+                       [section]\n\
+                       var = \"${DAEMONBASE_TEST_EXPAND}\"\n";
+
+        let mut file = config(content);
+        assert_eq!(
+            file.take_string("var").unwrap().unwrap(),
+            "a-${DAEMONBASE_TEST_EXPAND}-b"
+        );
+        assert_eq!(file.take_string("dollar").unwrap().unwrap(), "$$HOME");
+
+        let mut file = config(content);
+        file.set_expand_env(true);
+        assert_eq!(file.take_string("var").unwrap().unwrap(), "a-value-b");
+        assert_eq!(file.take_string("dollar").unwrap().unwrap(), "$HOME");
+        assert!(file.take_string("unset").is_err());
+        assert!(file.take_string("bare").is_err());
+        assert!(file.take_string("unclosed").is_err());
+        assert!(file.take_string("empty").is_err());
+        assert_eq!(
+            file.take_table("section").unwrap().unwrap()
+                .take_string("var").unwrap().unwrap(),
+            "value"
+        );
+    }
+
+    #[test]
+    fn expand_env_in_arrays() {
+        env::set_var("DAEMONBASE_TEST_ARRAY", "value");
+        env::remove_var("DAEMONBASE_TEST_ARRAY_UNSET");
+        let content = "strings = [\"a\", \"${DAEMONBASE_TEST_ARRAY}\"]\n\
+                       numbers = [\"1\", \"${DAEMONBASE_TEST_ARRAY}\"]\n\
+                       paths = [\"${DAEMONBASE_TEST_ARRAY}/log\"]\n\
+                       path = \"${DAEMONBASE_TEST_ARRAY}/log\"\n\
+                       map = [[\"k\", \"${DAEMONBASE_TEST_ARRAY}\"]]\n\
+                       unset = [\"${DAEMONBASE_TEST_ARRAY_UNSET}\"]\n";
+
+        let mut file = config(content);
+        assert_eq!(
+            file.take_string_array("strings").unwrap().unwrap(),
+            ["a", "${DAEMONBASE_TEST_ARRAY}"]
+        );
+        assert!(file.take_from_str_array::<u8>("numbers").is_err());
+        assert_eq!(
+            file.take_path_array("paths").unwrap().unwrap()[0].as_path(),
+            Path::new("/${DAEMONBASE_TEST_ARRAY}/log")
+        );
+        assert!(file.take_string_array("unset").is_ok());
+
+        let mut file = config(content);
+        file.set_expand_env(true);
+        assert_eq!(
+            file.take_string_array("strings").unwrap().unwrap(),
+            ["a", "value"]
+        );
+        assert!(file.take_from_str_array::<u8>("numbers").is_err());
+        assert_eq!(
+            file.take_path_array("paths").unwrap().unwrap()[0].as_path(),
+            Path::new("/value/log")
+        );
+        assert_eq!(
+            file.take_path_array("path").unwrap().unwrap()[0].as_path(),
+            Path::new("/value/log")
+        );
+        assert_eq!(
+            file.take_string_map("map").unwrap().unwrap().get("k"),
+            Some(&String::from("value"))
+        );
+        assert!(file.take_string_array("unset").is_err());
+
+        env::set_var("DAEMONBASE_TEST_EXPAND_NUMBER", "2");
+        let mut file = config(
+            "numbers = [\"1\", \"${DAEMONBASE_TEST_EXPAND_NUMBER}\"]\n"
+        );
+        file.set_expand_env(true);
+        assert_eq!(
+            file.take_from_str_array::<u8>("numbers").unwrap().unwrap(),
+            [1, 2]
+        );
+    }
+
+    #[test]
+    fn includes() {
+        let dir = env::temp_dir().join(format!(
+            "daemonbase-test-{}-includes", std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("conf.d")).unwrap();
+        fs::write(dir.join("conf.d/a.toml"), "a = 1\n[table]\nx = 1\n")
+            .unwrap();
+        fs::write(dir.join("conf.d/b.toml"), "b = 2\n[table]\ny = 2\n")
+            .unwrap();
+        fs::write(dir.join("conf.d/.hidden.toml"), "c = 3\n").unwrap();
+        fs::write(dir.join("loop.toml"), "include = \"loop.toml\"\n")
+            .unwrap();
+        let content = "include = \"conf.d/*.toml\"\n";
+
+        // Without opting in, include is an ordinary key.
+        let mut file = ConfigFile::parse(
+            content, &dir.join("main.toml")
+        ).unwrap();
+        assert_eq!(
+            file.take_string("include").unwrap().as_deref(),
+            Some("conf.d/*.toml")
+        );
+
+        let mut file = ConfigFile::parse(
+            content, &dir.join("main.toml")
+        ).unwrap();
+        file.process_includes().unwrap();
+        assert_eq!(file.take_u64("a").unwrap(), Some(1));
+        assert_eq!(file.take_u64("b").unwrap(), Some(2));
+        let mut table = file.take_table("table").unwrap().unwrap();
+        assert_eq!(table.take_u64("x").unwrap(), Some(1));
+        assert_eq!(table.take_u64("y").unwrap(), Some(2));
+        assert!(file.check_exhausted().is_ok());
+
+        let mut file = ConfigFile::parse(
+            "include = \"loop.toml\"\n", &dir.join("main.toml")
+        ).unwrap();
+        assert!(file.process_includes().is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn take_enum() {
+        let mut file = config(
+            "mode = \"fast\"\n\
+             bad = \"medium\"\n\
+             number = 1\n"
+        );
+        let valid = &["fast", "slow"];
+        assert_eq!(
+            file.take_enum::<String>("mode", valid).unwrap().as_deref(),
+            Some("fast")
+        );
+        assert!(file.take_enum::<String>("bad", valid).is_err());
+        assert!(file.take_enum::<String>("number", valid).is_err());
+        assert_eq!(file.take_enum::<String>("missing", valid).unwrap(), None);
+    }
+
+    #[test]
+    fn take_datetime() {
+        let mut file = config(
+            "utc = 2025-01-01T12:00:00Z\n\
+             offset = 2025-01-01T12:00:00+02:00\n\
+             date = 2025-01-01\n\
+             local = 2025-01-01T12:00:00\n\
+             time = 12:00:00\n\
+             string = \"2025-01-01\"\n"
+        );
+        let expected = |s: &str| {
+            Some(DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc))
+        };
+        assert_eq!(
+            file.take_datetime("utc").unwrap(),
+            expected("2025-01-01T12:00:00Z")
+        );
+        assert_eq!(
+            file.take_datetime("offset").unwrap(),
+            expected("2025-01-01T10:00:00Z")
+        );
+        assert_eq!(
+            file.take_datetime("date").unwrap(),
+            expected("2025-01-01T00:00:00Z")
+        );
+        assert!(file.take_datetime("local").is_err());
+        assert!(file.take_datetime("time").is_err());
+        assert!(file.take_datetime("string").is_err());
+        assert_eq!(file.take_datetime("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn take_u64_range() {
+        let mut file = config(
+            "low = 1\n\
+             high = 10\n\
+             below = 0\n\
+             above = 11\n\
+             negative = -1\n\
+             string = \"5\"\n"
+        );
+        assert_eq!(file.take_u64_range("low", 1, 10).unwrap(), Some(1));
+        assert_eq!(file.take_u64_range("high", 1, 10).unwrap(), Some(10));
+        assert!(file.take_u64_range("below", 1, 10).is_err());
+        assert!(file.take_u64_range("above", 1, 10).is_err());
+        assert!(file.take_u64_range("negative", 1, 10).is_err());
+        assert!(file.take_u64_range("string", 1, 10).is_err());
+        assert_eq!(file.take_u64_range("missing", 1, 10).unwrap(), None);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "empty range")]
+    fn take_u64_range_empty() {
+        let _ = config("value = 5\n").take_u64_range("value", 10, 1);
+    }
+
+    #[test]
+    fn exit_code() {
+        let err = ConfigFile::load(
+            Path::new("/nonexistent/daemonbase.conf")
+        ).unwrap_err();
+        assert_eq!(err.code(), ExitError::EX_CONFIG);
+
+        let mut file = config("known = 1\nunknown = 2\n");
+        assert_eq!(file.take_u64("known").unwrap(), Some(1));
+        assert_eq!(file.finish().unwrap_err().code(), ExitError::EX_CONFIG);
+        assert_eq!(file.take_u64("unknown").unwrap(), Some(2));
+        assert!(file.finish().is_ok());
+    }
+
+    #[test]
+    fn migrate() {
+        fn rename(file: &mut ConfigFile) {
+            let value = file.take_value("old").unwrap().unwrap();
+            file.insert_value("middle", value);
+        }
+        fn rename_again(file: &mut ConfigFile) {
+            let value = file.take_value("middle").unwrap().unwrap();
+            file.insert_value("new", value);
+        }
+        let migrations: &[Migration] = &[(2, rename_again), (1, rename)];
+
+        let mut file = config("old = 1\n");
+        assert_eq!(file.migrate(2, migrations).unwrap(), 0);
+        assert_eq!(file.take_u64("config-version").unwrap(), Some(2));
+        assert_eq!(file.take_u64("new").unwrap(), Some(1));
+        assert!(file.check_exhausted().is_ok());
+
+        let mut file = config("config-version = 1\nmiddle = 1\n");
+        assert_eq!(file.migrate(2, migrations).unwrap(), 1);
+        assert_eq!(file.to_string(), "new = 1\nconfig-version = 2\n");
+
+        let mut file = config("config-version = 3\n");
+        assert!(file.migrate(2, migrations).is_err());
+    }
+
+    #[test]
+    fn write_preserves_formatting() {
+        let content = "# Leading comment.\n\
+                       first   = 1   # Trailing comment.\n\
+                       second = \"two\"\n\
+                       \n\
+                       # Third.\n\
+                       third = [1, 2]\n";
+        let mut file = config(content);
+        assert_eq!(file.to_string(), content);
+
+        assert_eq!(file.take_string("second").unwrap().unwrap(), "two");
+        file.insert_value("fourth", 4);
+        let expected = "# Leading comment.\n\
+                        first   = 1   # Trailing comment.\n\
+                        \n\
+                        # Third.\n\
+                        third = [1, 2]\n\
+                        fourth = 4\n";
+        assert_eq!(file.to_string(), expected);
+
+        let path = env::temp_dir().join(format!(
+            "daemonbase-test-{}-write.conf", std::process::id()
+        ));
+        file.write(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), expected);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn validation_report() {
+        let mut report = ValidationReport::new();
+        report.check_tcp_bind("listen", "127.0.0.1:0".parse().unwrap());
+        assert!(report.is_ok());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        report.check_tcp_bind("listen", listener.local_addr().unwrap());
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        report.check_udp_bind("listen", socket.local_addr().unwrap());
+        assert!(!report.is_ok());
+        assert_eq!(
+            report.items().iter().map(|item| item.passed).collect::<Vec<_>>(),
+            [true, false, false]
+        );
+
+        let mut json = Vec::new();
+        report.write(ReportFormat::Json, &mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.contains("\"ok\": false"));
+        assert_eq!(json.matches("\"name\": \"listen\"").count(), 3);
+    }
+
+    #[test]
+    fn report_args() {
+        use clap::{Args, Command, FromArgMatches};
+
+        let parse = |args: &[&str]| {
+            ReportArgs::from_arg_matches(
+                &ReportArgs::augment_args(Command::new("test"))
+                    .try_get_matches_from(args).unwrap()
+            ).unwrap().format()
+        };
+        assert_eq!(parse(&["test"]), ReportFormat::Text);
+        assert_eq!(parse(&["test", "--format", "json"]), ReportFormat::Json);
+    }
+
+    #[test]
+    fn parent_dir() {
+        assert_eq!(
+            super::parent_dir(Path::new("daemon.pid")),
+            Some(Path::new("."))
+        );
+        assert_eq!(
+            super::parent_dir(Path::new("run/daemon.pid")),
+            Some(Path::new("run"))
+        );
+        assert_eq!(
+            super::parent_dir(Path::new("/daemon.pid")),
+            Some(Path::new("/"))
+        );
+        assert_eq!(super::parent_dir(Path::new("/")), None);
+    }
+
+    #[test]
+    #[cfg(feature = "ipnet")]
+    fn take_ip_net() {
+        let mut file = config(
+            "net = \"192.0.2.0/24\"\n\
+             host = \"2001:db8::1\"\n\
+             list = [\"192.0.2.1\", \"2001:db8::/32\"]\n\
+             bad = \"192.0.2.0/33\"\n"
+        );
+        assert_eq!(
+            file.take_ip_net("net").unwrap(),
+            Some("192.0.2.0/24".parse().unwrap())
+        );
+        assert_eq!(
+            file.take_ip_net("host").unwrap(),
+            Some("2001:db8::1/128".parse().unwrap())
+        );
+        assert_eq!(
+            file.take_ip_net_array("list").unwrap(),
+            Some(vec![
+                "192.0.2.1/32".parse().unwrap(),
+                "2001:db8::/32".parse().unwrap(),
+            ])
+        );
+        assert!(file.take_ip_net("bad").is_err());
+        assert_eq!(file.take_ip_net("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn section_key() {
+        let mut file = config(
+            "[tls]\n\
+             path = 1\n\
+             [tls.client]\n\
+             path = 2\n\
+             [[log]]\n\
+             path = 3\n"
+        );
+        assert_eq!(file.section(), "");
+        assert_eq!(file.section_key("path"), "path");
+
+        let mut tls = file.take_table("tls").unwrap().unwrap();
+        assert_eq!(tls.section(), "tls");
+        assert_eq!(tls.section_key("path"), "tls.path");
+        assert!(tls.take_string("path").is_err());
+
+        let client = tls.take_table("client").unwrap().unwrap();
+        assert_eq!(client.section_key("path"), "tls.client.path");
+
+        let log = file.take_table_array("log").unwrap().unwrap();
+        assert_eq!(log[0].section_key("path"), "log[0].path");
+    }
+
+    #[test]
+    fn duration_from_str() {
+        fn secs(value: &str) -> Option<Duration> {
+            ConfigFile::duration_from_str(value, Duration::from_secs)
+        }
+
+        assert_eq!(secs("1m30s"), Some(Duration::from_secs(90)));
+        assert_eq!(secs("1m 30s"), Some(Duration::from_secs(90)));
+        assert_eq!(secs("2h"), Some(Duration::from_secs(7200)));
+        assert_eq!(secs("1d"), Some(Duration::from_secs(86400)));
+        assert_eq!(secs("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(secs("20us"), Some(Duration::from_micros(20)));
+        assert_eq!(secs("7ns"), Some(Duration::from_nanos(7)));
+        assert_eq!(
+            secs("1s500ms10ns"), Some(Duration::new(1, 500_000_010))
+        );
+        assert_eq!(secs("30"), Some(Duration::from_secs(30)));
+
+        // Unknown unit, missing unit after a part, no number, empty.
+        assert_eq!(secs("5y"), None);
+        assert_eq!(secs("1m30"), None);
+        assert_eq!(secs("ms"), None);
+        assert_eq!(secs("-5s"), None);
+        assert_eq!(secs(""), None);
+
+        // Overflow.
+        assert_eq!(secs("99999999999999999999s"), None);
+        assert_eq!(secs("18446744073709551615d"), None);
+        assert_eq!(
+            secs("18446744073709551615s18446744073709551615s"), None
+        );
+    }
+
+    #[test]
+    fn take_duration() {
+        let mut file = config(
+            "secs = 30\n\
+             millis = 30\n\
+             str-secs = \"30\"\n\
+             str-millis = \"30\"\n\
+             unit-secs = \"1m30s\"\n\
+             unit-millis = \"1m30s\"\n\
+             negative = -1\n\
+             unknown = \"5y\"\n"
+        );
+        assert_eq!(
+            file.take_duration("secs").unwrap(),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            file.take_duration_millis("millis").unwrap(),
+            Some(Duration::from_millis(30))
+        );
+        assert_eq!(
+            file.take_duration("str-secs").unwrap(),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            file.take_duration_millis("str-millis").unwrap(),
+            Some(Duration::from_millis(30))
+        );
+        assert_eq!(
+            file.take_duration("unit-secs").unwrap(),
+            Some(Duration::from_secs(90))
+        );
+        assert_eq!(
+            file.take_duration_millis("unit-millis").unwrap(),
+            Some(Duration::from_secs(90))
+        );
+        assert!(file.take_duration("negative").is_err());
+        assert!(file.take_duration_millis("unknown").is_err());
+        assert_eq!(file.take_duration("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn parse_addr() {
+        fn err(value: &str) -> String {
+            ConfigFile::parse_addr(value).unwrap_err()
+        }
+
+        assert_eq!(
+            ConfigFile::parse_addr("[::1]:53").unwrap(),
+            SocketAddr::from((std::net::Ipv6Addr::LOCALHOST, 53))
+        );
+        assert_eq!(
+            ConfigFile::parse_addr("192.0.2.1:53").unwrap(),
+            SocketAddr::from(([192, 0, 2, 1], 53))
+        );
+        assert_eq!(err("192.0.2.1"), "missing port");
+        assert_eq!(err("[::1]"), "missing port");
+
+        // This is the IPv6 address ::1:53, not ::1 with port 53.
+        assert_eq!(err("::1:53"), "missing port");
+        assert_eq!(err("::1::53"), "malformed IP address '::1::53'");
+        assert_eq!(err("1.2.3.4:99999"), "malformed port '99999'");
+        assert_eq!(err("1.2.3.4:"), "malformed port ''");
+        assert_eq!(err("foo:53"), "malformed IP address 'foo'");
+        assert_eq!(err("[foo]:53"), "malformed IP address '[foo]'");
+        assert_eq!(err("foo"), "malformed IP address 'foo'");
+    }
+
+    #[test]
+    fn unused_keys() {
+        let content = "used = 1\n\
+                       unused = 2\n\
+                       [server]\n\
+                       port = 53\n\
+                       extra = true\n\
+                       [server.tls]\n\
+                       cert = \"cert.pem\"\n\
+                       [empty]\n\
+                       [[log]]\n\
+                       target = \"stderr\"\n\
+                       x = 1\n\
+                       [[log]]\n";
+
+        let mut file = config(content);
+        file.take_u64("used").unwrap();
+        assert_eq!(
+            file.unused_keys(),
+            [
+                "unused", "server.port", "server.extra", "server.tls.cert",
+                "empty", "log[0].target", "log[0].x", "log[1]",
+            ]
+        );
+        assert!(file.check_exhausted().is_err());
+
+        let mut file = config(content);
+        let mut server = file.take_table("server").unwrap().unwrap();
+        server.take_u64("port").unwrap();
+        assert_eq!(
+            server.unused_keys(), ["server.extra", "server.tls.cert"]
+        );
+        assert!(server.check_exhausted().is_err());
+
+        let mut logs = file.take_table_array("log").unwrap().unwrap();
+        logs[0].take_string("target").unwrap();
+        assert_eq!(logs[0].unused_keys(), ["log[0].x"]);
+        assert!(logs[0].check_exhausted().is_err());
+        assert!(logs[1].check_exhausted().is_ok());
+
+        file.take_u64("used").unwrap();
+        file.take_u64("unused").unwrap();
+        file.take_table("empty").unwrap();
+        assert!(file.check_exhausted().is_ok());
+    }
+
+    #[test]
+    fn take_range_array() {
+        let mut file = config(
+            "ports = [80, \"443\", \"8000-8010\", \" 20 - 21 \"]\n\
+             single = [\"80\", 80]\n\
+             inverted = [\"10-5\"]\n\
+             text = [\"http\"]\n\
+             half = [\"10-\"]\n\
+             negative = [-1]\n\
+             string = \"80\"\n"
+        );
+        let ports = file.take_range_array("ports").unwrap().unwrap();
+        assert_eq!(
+            ports.as_slice(), [80..=80, 443..=443, 8000..=8010, 20..=21]
+        );
+        assert!(ports.contains_value(8005));
+        assert!(ports.contains_value(21));
+        assert!(!ports.contains_value(8011));
+        assert!(ports.contains(&(443..=443)));
+        assert_eq!(
+            file.take_range_array("single").unwrap().unwrap().as_slice(),
+            [80..=80, 80..=80]
+        );
+        assert!(file.take_range_array("inverted").is_err());
+        assert!(file.take_range_array("text").is_err());
+        assert!(file.take_range_array("half").is_err());
+        assert!(file.take_range_array("negative").is_err());
+        assert!(file.take_range_array("string").is_err());
+        assert_eq!(file.take_range_array("missing").unwrap(), None);
+    }
+}
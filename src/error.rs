@@ -28,12 +28,97 @@ pub struct Failed;
 //------------ ExitError -----------------------------------------------------
 
 /// An error happened that should lead to terminating the program.
-#[derive(Clone, Copy, Debug, Default)]
-pub struct ExitError(());
+///
+/// The error carries the exit code the program should terminate with. By
+/// default, this is 1. Constants for the exit codes defined by the BSD
+/// `sysexits.h` conventions are provided as associated constants, so that
+/// supervisors can distinguish categories of failures. A [`Failed`] can be
+/// mapped to a specific exit code via, e.g.,
+/// `ExitError::from(err).with_code(ExitError::EX_CONFIG)`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ExitError {
+    /// The exit code.
+    code: i32,
+}
 
 impl ExitError {
+    /// A general error.
+    pub const EX_FAILURE: i32 = 1;
+
+    /// The command was used incorrectly.
+    pub const EX_USAGE: i32 = 64;
+
+    /// The input data was incorrect.
+    pub const EX_DATAERR: i32 = 65;
+
+    /// An input file did not exist or was not readable.
+    pub const EX_NOINPUT: i32 = 66;
+
+    /// The user specified did not exist.
+    pub const EX_NOUSER: i32 = 67;
+
+    /// The host specified did not exist.
+    pub const EX_NOHOST: i32 = 68;
+
+    /// A service is unavailable.
+    pub const EX_UNAVAILABLE: i32 = 69;
+
+    /// An internal software error has been detected.
+    pub const EX_SOFTWARE: i32 = 70;
+
+    /// An operating system error has been detected.
+    pub const EX_OSERR: i32 = 71;
+
+    /// A system file does not exist, cannot be opened, or has an error.
+    pub const EX_OSFILE: i32 = 72;
+
+    /// A user specified output file cannot be created.
+    pub const EX_CANTCREAT: i32 = 73;
+
+    /// An error occurred while doing I/O on some file.
+    pub const EX_IOERR: i32 = 74;
+
+    /// A temporary failure, the operation may succeed when retried.
+    pub const EX_TEMPFAIL: i32 = 75;
+
+    /// The remote system returned something invalid during a protocol
+    /// exchange.
+    pub const EX_PROTOCOL: i32 = 76;
+
+    /// Insufficient permission to perform the operation.
+    pub const EX_NOPERM: i32 = 77;
+
+    /// Something was found in an unconfigured or misconfigured state.
+    pub const EX_CONFIG: i32 = 78;
+
+    /// Creates a new exit error with the given exit code.
+    pub const fn new(code: i32) -> Self {
+        ExitError { code }
+    }
+
+    /// Returns a copy of the error with the exit code replaced.
+    pub const fn with_code(self, code: i32) -> Self {
+        ExitError { code }
+    }
+
+    /// Returns the exit code.
+    pub const fn code(self) -> i32 {
+        self.code
+    }
+
+    /// Exits the process with the exit code of the error.
+    ///
+    /// Any callbacks registered via [`process::on_exit`] are run first.
+    ///
+    /// [`process::on_exit`]: crate::process::on_exit
     pub fn exit(self) -> ! {
-        std::process::exit(1);
+        crate::process::exit(self.code)
+    }
+}
+
+impl Default for ExitError {
+    fn default() -> Self {
+        ExitError::new(ExitError::EX_FAILURE)
     }
 }
 
@@ -43,4 +128,3 @@ impl From<Failed> for ExitError {
         ExitError::default()
     }
 }
-
@@ -1,5 +1,8 @@
 pub mod config;
+#[allow(clippy::empty_line_after_doc_comments)]
 pub mod error;
 pub mod logging;
 pub mod process;
 
+#[cfg(all(unix, feature = "testing"))]
+pub mod testing;
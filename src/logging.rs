@@ -1,16 +1,25 @@
 //! Logging.
 
-use std::{fmt, fs, io};
-use std::io::Write;
-use std::ops::{Deref, DerefMut};
-use std::path::PathBuf;
+use std::{env, fmt, fs, io, mem};
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::ffi::OsString;
+use std::io::{IsTerminal, Write};
+#[cfg(unix)]
+use std::os::fd::RawFd;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::sync::{Mutex, MutexGuard, OnceLock, RwLock, TryLockError};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use clap::ArgAction;
 use log::LevelFilter;
-use log::error;
+use log::{error, warn};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use crate::config::{ConfigFile, ConfigPath};
+use crate::config::{
+    ConfigFile, ConfigPath, JsonEscape, ValidationReport, log_opt_setting,
+    parent_dir,
+};
 use crate::error::{ExitError, Failed};
 
 
@@ -22,8 +31,38 @@ pub struct Logger {
     /// The log levels to be logged.
     level: LevelFilter,
 
-    /// The target to log to.
-    target: Target,
+    /// The targets to log to.
+    ///
+    /// Each target can have its own log level overriding `level`.
+    targets: Vec<(Target, Option<LevelFilter>)>,
+
+    /// The application name to use where one is needed.
+    app_name: Option<String>,
+
+    /// How to format messages written to files or stderr.
+    format: FormatOptions,
+
+    /// Log levels for specific modules overriding `level`.
+    module_levels: Vec<(String, LevelFilter)>,
+
+    /// Modules whose less important messages are suppressed.
+    suppress: Suppression,
+
+    /// Size-based rotation of log files.
+    rotation: Option<Rotation>,
+
+    /// The message format to use for syslog.
+    #[cfg(unix)]
+    syslog_format: SyslogFormat,
+
+    /// The number of recent messages to keep in memory.
+    memory_buffer: Option<usize>,
+
+    /// Whether to color messages written to stderr.
+    color: ColorMode,
+
+    /// Whether to defer opening log files until activation.
+    defer_open: bool,
 }
 
 impl Logger {
@@ -33,42 +72,152 @@ impl Logger {
     /// read the configuration. The function sets a maximum log level of
     /// `warn`, leading to only printing important information, and directs
     /// all log output to stderr.
+    ///
+    /// On Unix systems, the function also arranges for all log output to
+    /// be flushed when the process exits normally, i.e., by returning from
+    /// `main` or via [`std::process::exit`]. On other systems, [`flush`]
+    /// needs to be called explicitly before exiting.
     pub fn init_logging() -> Result<(), ExitError> {
         log::set_max_level(LevelFilter::Warn);
         if let Err(err) = log::set_logger(&GLOBAL_LOGGER) {
             eprintln!("Failed to initialize logger: {}.\nAborting.", err);
             return Err(ExitError::default())
         }
+        #[cfg(unix)]
+        {
+            extern "C" fn atexit_handler() {
+                // If logging is suppressed, we may be exiting from within
+                // the logger while holding its locks.
+                if !is_suppressed() {
+                    self::flush_at_exit()
+                }
+            }
+
+            // Safety: The function doesn’t unwind and only uses state
+            //         that is still valid during exit.
+            unsafe { libc::atexit(atexit_handler); }
+        }
         Ok(())
     }
 
     /// Creates the logger from a config struct.
     pub fn from_config(config: &Config) -> Result<Self, Failed> {
+        config.check_consistency()?;
         Ok(Self {
             level: config.log_level.0,
-            target: match config.log_target {
-                TargetName::Default => Target::Default,
-                #[cfg(unix)]
-                TargetName::Syslog => {
-                    Target::Syslog(config.syslog_facility.into())
-                }
-                TargetName::Stderr => Target::Stderr,
-                TargetName::File => {
-                    match config.log_file.as_ref() {
-                        Some(LogPath::Stderr) => Target::Stderr,
-                        Some(LogPath::Path(ref file)) => {
-                            Target::File(file.clone().into())
-                        }
-                        None => {
-                            error!("Missing 'log-file' option in config.");
-                            return Err(Failed)
+            targets: config.targets().iter().chain(
+                config.error_log_target().as_ref()
+            ).map(|target| {
+                let level = target.level.map(|level| level.0);
+                let path = match target.path.as_ref() {
+                    Some(path) => Some(path),
+                    None => config.log_file.as_ref(),
+                };
+                Ok((match target.target {
+                    TargetName::Default => Target::Default,
+                    TargetName::Auto => Target::Auto,
+                    #[cfg(unix)]
+                    TargetName::Syslog => {
+                        Target::Syslog(config.syslog_facility.into())
+                    }
+                    TargetName::Stderr => Target::Stderr,
+                    #[cfg(unix)]
+                    TargetName::Journal => Target::Journal,
+                    TargetName::File => {
+                        match path {
+                            Some(LogPath::Stderr) => Target::Stderr,
+                            Some(LogPath::Path(ref file)) => {
+                                Target::File(file.clone().into())
+                            }
+                            None => {
+                                error!(
+                                    "Missing 'log-file' option in config."
+                                );
+                                return Err(Failed)
+                            }
                         }
                     }
-                }
+                }, level))
+            }).collect::<Result<_, _>>()?,
+            app_name: None,
+            format: FormatOptions {
+                timestamps: config.timestamps,
+                format: config.format,
+            },
+            module_levels: config.module_levels.iter().map(|(module, level)| {
+                (module.clone(), level.0)
+            }).collect(),
+            suppress: Suppression {
+                below_info: config.suppress_below_info.clone().unwrap_or_else(
+                    || Suppression::default().below_info
+                ),
+                errors_only: config.errors_only.clone().unwrap_or_else(
+                    || Suppression::default().errors_only
+                ),
             },
+            rotation: config.log_file_max_size.map(|max_size| {
+                Rotation {
+                    max_size,
+                    keep: config.log_file_keep.unwrap_or(
+                        Rotation::DEFAULT_KEEP
+                    ),
+                    max_total_size: config.log_file_max_total_size,
+                }
+            }),
+            #[cfg(unix)]
+            syslog_format: config.syslog_format,
+            memory_buffer: config.memory_buffer,
+            color: config.color,
+            defer_open: false,
         })
     }
 
+    /// Creates a logger writing to an inherited file descriptor.
+    ///
+    /// This is intended for child processes that cannot open any files
+    /// themselves, e.g., because they run in a sandbox. The parent opens
+    /// the log sink and passes its file descriptor `fd` to the child which
+    /// then logs to it in the same format as to a log file.
+    ///
+    /// The close-on-exec flag is set on `fd` so it isn’t passed on to
+    /// any further programs executed by the child. All options other than
+    /// the target are taken from `config`.
+    #[cfg(unix)]
+    pub fn from_inherited_fd(
+        fd: RawFd, config: &Config
+    ) -> Result<Self, Failed> {
+        unix::set_cloexec(fd)?;
+        let mut res = Self::from_config(config)?;
+        res.targets = vec![(Target::Fd(fd), None)];
+        Ok(res)
+    }
+
+    /// Sets the application name.
+    ///
+    /// The name is used as the process name when logging to syslog. If it
+    /// isn’t set, the file name of the current executable is used or, if
+    /// that cannot be determined, `"daemon"`.
+    pub fn set_app_name(&mut self, name: impl Into<String>) {
+        self.app_name = Some(name.into())
+    }
+
+    /// Sets whether opening log files should be deferred.
+    ///
+    /// If `defer` is `true`, log files are not opened when switching
+    /// logging but only when [`activate`][Self::activate] or
+    /// [`activate_with`][Self::activate_with] is called. This allows
+    /// opening them after privileges have been dropped so they are created
+    /// with the daemon’s identity and inside its chroot.
+    ///
+    /// Until then, messages for the log files are kept in memory and are
+    /// written once the files are opened. Only the first
+    /// 10,000 messages are kept, later ones are dropped. If the process
+    /// exits without the files ever having been opened, the messages are
+    /// written to stderr instead.
+    pub fn set_defer_open(&mut self, defer: bool) {
+        self.defer_open = defer
+    }
+
     /// Switches logging to the configured target.
     ///
     /// Once the configuration has been successfully loaded, logging should
@@ -79,15 +228,24 @@ impl Logger {
     /// available via one of the standard Unix sockets. Otherwise, stderr is
     /// used.
     ///
-    /// This method should only be called once. It returns an error if called
-    /// again.
+    /// If logging has been switched before, the previous target is replaced
+    /// by the new one. This can be used to reconfigure logging at run time.
     pub fn switch_logging(
         &self,
         daemon: bool,
     ) -> Result<(), Failed> {
         let logger = Dispatch::new(self, daemon)?;
         GLOBAL_LOGGER.switch(logger);
-        log::set_max_level(self.level);
+        let mut max_level = self.level;
+        for (_, level) in &self.targets {
+            if let Some(level) = level {
+                max_level = max_level.max(*level)
+            }
+        }
+        for (_, level) in &self.module_levels {
+            max_level = max_level.max(*level)
+        }
+        log::set_max_level(max_level);
         Ok(())
     }
 
@@ -95,130 +253,1486 @@ impl Logger {
     pub fn rotate_log(&self) -> Result<(), Failed> {
         GLOBAL_LOGGER.rotate()
     }
+
+    /// Opens all log files whose opening has been deferred.
+    ///
+    /// See [`set_defer_open`][Self::set_defer_open] for details. Messages
+    /// logged so far are written to the files. Does nothing if there are
+    /// no deferred log files.
+    pub fn activate(&self) -> Result<(), Failed> {
+        self.activate_with(Ok::<_, Infallible>)
+    }
+
+    /// Opens all deferred log files after adjusting their paths.
+    ///
+    /// Before opening it, the path of each file is passed to `adjust`. This
+    /// can be used to adjust paths after changing the file system root,
+    /// e.g., via [`Process::current_path`]. If `adjust` returns an error,
+    /// activation fails. The logger isn’t locked while `adjust` runs, so
+    /// it may log.
+    ///
+    /// [`Process::current_path`]: crate::process::Process::current_path
+    pub fn activate_with<E: fmt::Display>(
+        &self, mut adjust: impl FnMut(PathBuf) -> Result<PathBuf, E>
+    ) -> Result<(), Failed> {
+        GLOBAL_LOGGER.activate(&mut |path| {
+            let display = path.display().to_string();
+            adjust(path).map_err(|err| {
+                format!("Cannot use log file '{}': {}", display, err)
+            })
+        })
+    }
+
+    /// Returns the most recent log messages.
+    ///
+    /// The messages are only kept if the `log-memory-buffer` option is
+    /// set. They are kept in addition to logging them to the configured
+    /// target and are returned formatted like for a log file, oldest
+    /// first. If no messages are kept, the returned vec is empty.
+    pub fn recent_logs() -> Vec<String> {
+        GLOBAL_LOGGER.recent_logs()
+    }
+}
+
+
+//------------ Config --------------------------------------------------------
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Config {
+    #[serde(rename = "log-level", alias = "log_level", default)]
+    log_level: LevelName,
+
+    /// The targets to log to.
+    ///
+    /// If this is empty, the default target is used.
+    #[serde(
+        rename = "log", alias = "log_target", default,
+        deserialize_with = "TargetConfig::deserialize_list",
+        serialize_with = "TargetConfig::serialize_list",
+    )]
+    log_targets: Vec<TargetConfig>,
+
+    #[cfg(unix)]
+    #[serde(rename = "syslog-facility", alias = "log_facility", default)]
+    syslog_facility: unix::FacilityArg,
+
+    /// The message format to use for syslog.
+    #[cfg(unix)]
+    #[serde(rename = "syslog-format", default)]
+    syslog_format: SyslogFormat,
+
+    #[serde(rename = "log-file", alias = "log_file")]
+    log_file: Option<LogPath>,
+
+    /// An additional log file receiving only important messages.
+    #[serde(rename = "error-log-file")]
+    error_log_file: Option<ConfigPath>,
+
+    /// The log level for the error log file.
+    ///
+    /// If this is `None`, `warn` is used.
+    #[serde(rename = "error-log-level")]
+    error_log_level: Option<LevelName>,
+
+    /// How to timestamp log messages.
+    #[serde(rename = "log-timestamps", default)]
+    timestamps: TimestampMode,
+
+    /// The format of log messages.
+    #[serde(rename = "log-format", default)]
+    format: LogFormat,
+
+    /// Whether to color log messages written to stderr.
+    #[serde(rename = "log-color", default)]
+    color: ColorMode,
+
+    /// Log levels for specific modules.
+    ///
+    /// Each entry consists of a module path prefix and the level for all
+    /// modules starting with it. The prefix is also matched against the
+    /// target of a log message, such as
+    /// [`LIFECYCLE_TARGET`][crate::process::LIFECYCLE_TARGET].
+    #[serde(rename = "log-module-levels", default)]
+    module_levels: Vec<(String, LevelName)>,
+
+    /// Modules for which only messages of level info or above are logged.
+    ///
+    /// If this is `None`, a built-in list of chatty dependencies is used.
+    #[serde(rename = "log-suppress-below-info")]
+    suppress_below_info: Option<Vec<String>>,
+
+    /// Modules for which only error messages are logged.
+    ///
+    /// If this is `None`, a built-in list is used.
+    #[serde(rename = "log-errors-only")]
+    errors_only: Option<Vec<String>>,
+
+    /// The size in bytes at which log files are rotated.
+    ///
+    /// If this is `None`, log files are never rotated by us.
+    #[serde(rename = "log-file-max-size")]
+    log_file_max_size: Option<u64>,
+
+    /// The number of rotated log files to keep.
+    ///
+    /// If this is `None`, `Rotation::DEFAULT_KEEP` is used. The value is
+    /// limited to `Rotation::MAX_KEEP`.
+    #[serde(rename = "log-file-keep")]
+    log_file_keep: Option<usize>,
+
+    /// The maximum combined size in bytes of all rotated log files.
+    ///
+    /// If this is `None`, only `log_file_keep` limits the rotated files.
+    #[serde(rename = "log-file-max-total-size")]
+    log_file_max_total_size: Option<u64>,
+
+    /// The number of recent messages to keep in memory.
+    ///
+    /// If this is `None`, no messages are kept. The value is limited to
+    /// `MemoryBuffer::MAX_CAPACITY`.
+    #[serde(rename = "log-memory-buffer")]
+    memory_buffer: Option<usize>,
 }
 
+impl Config {
+    /// Creates the logger from a config file.
+    pub fn from_config_file(file: &mut ConfigFile) -> Result<Self, Failed> {
+        Ok(Self {
+            log_level: LevelName::take_from_config(
+                file, "log-level"
+            )?.unwrap_or_default(),
+            log_targets: Self::take_targets(file)?,
+            #[cfg(unix)]
+            syslog_facility: unix::FacilityArg::take_from_config(
+                file, "syslog-facility"
+            )?.unwrap_or_default(),
+            #[cfg(unix)]
+            syslog_format: file.take_from_str::<SyslogFormat>(
+                "syslog-format"
+            )?.unwrap_or_default(),
+            log_file: file.take_string("log-file")?.map(Into::into),
+            error_log_file: file.take_path("error-log-file")?,
+            error_log_level: LevelName::take_from_config(
+                file, "error-log-level"
+            )?,
+            timestamps: file.take_from_str::<TimestampMode>(
+                "log-timestamps"
+            )?.unwrap_or_default(),
+            format: file.take_from_str::<LogFormat>(
+                "log-format"
+            )?.unwrap_or_default(),
+            color: file.take_from_str::<ColorMode>(
+                "log-color"
+            )?.unwrap_or_default(),
+            module_levels: Self::take_module_levels(file)?,
+            suppress_below_info: file.take_string_array(
+                "log-suppress-below-info"
+            )?,
+            errors_only: file.take_string_array("log-errors-only")?,
+            log_file_max_size: Self::take_max_size(file)?,
+            log_file_keep: file.take_usize_range(
+                "log-file-keep", 0, Rotation::MAX_KEEP
+            )?,
+            log_file_max_total_size: file.take_u64(
+                "log-file-max-total-size"
+            )?,
+            memory_buffer: file.take_usize_range(
+                "log-memory-buffer", 0, MemoryBuffer::MAX_CAPACITY
+            )?,
+        })
+    }
+
+    /// Takes the log targets from the config file.
+    ///
+    /// The targets can be given either as a single string, as an array
+    /// of strings, or as an array of tables with one table per target.
+    fn take_targets(
+        file: &mut ConfigFile
+    ) -> Result<Vec<TargetConfig>, Failed> {
+        const KEY: &str = "log";
+
+        if file.is_table_array(KEY) {
+            return Self::take_target_tables(file)
+        }
+
+        let values = match file.take_value(KEY)? {
+            Some(toml_edit::Value::String(value)) => vec![value.into_value()],
+            Some(toml_edit::Value::Array(array)) => {
+                let values: Option<Vec<_>> = array.iter().map(|item| {
+                    item.as_str().map(String::from)
+                }).collect();
+                match values {
+                    Some(values) => values,
+                    None => {
+                        error!(
+                            "Failed in config file {}: \
+                             '{}' expected to be a string, an array \
+                             of strings, or an array of tables.",
+                            file.path().display(), file.section_key(KEY)
+                        );
+                        return Err(Failed)
+                    }
+                }
+            }
+            Some(_) => {
+                error!(
+                    "Failed in config file {}: \
+                     '{}' expected to be a string, an array of strings, \
+                     or an array of tables.",
+                    file.path().display(), file.section_key(KEY)
+                );
+                return Err(Failed)
+            }
+            None => return Ok(Vec::new())
+        };
+        let mut res = Vec::with_capacity(values.len());
+        for (idx, value) in values.iter().enumerate() {
+            if values[..idx].contains(value) {
+                error!(
+                    "Failed in config file {}: \
+                     duplicate value '{}' in '{}'.",
+                    file.path().display(), value, file.section_key(KEY)
+                );
+                return Err(Failed)
+            }
+            match TargetName::from_str(value) {
+                Ok(target) => res.push(target.into()),
+                Err(err) => {
+                    error!(
+                        "Failed in config file {}: \
+                         illegal value in '{}': {}.",
+                        file.path().display(), file.section_key(KEY), err
+                    );
+                    return Err(Failed)
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    /// Takes the log targets given as an array of tables.
+    ///
+    /// Each table describes one target via the `target` key, the path of
+    /// the log file for the `file` target via `path`, and an optional
+    /// log level for the target via `level`.
+    fn take_target_tables(
+        file: &mut ConfigFile
+    ) -> Result<Vec<TargetConfig>, Failed> {
+        let tables = file.take_table_array("log")?.unwrap_or_default();
+        let mut res = Vec::<TargetConfig>::with_capacity(tables.len());
+        for (idx, mut table) in tables.into_iter().enumerate() {
+            let entry = TargetConfig::from_config_table(&mut table)?;
+            if res.iter().any(|other| entry.is_same_target(other)) {
+                error!(
+                    "Failed in config file {}: \
+                     '{}[{}]' duplicates an earlier target.",
+                    file.path().display(), file.section_key("log"), idx
+                );
+                return Err(Failed)
+            }
+            res.push(entry);
+        }
+        Ok(res)
+    }
+
+    /// Takes the maximum log file size from the config file.
+    fn take_max_size(file: &mut ConfigFile) -> Result<Option<u64>, Failed> {
+        match file.take_u64("log-file-max-size")? {
+            Some(0) => {
+                error!(
+                    "Failed in config file {}: \
+                     '{}' must be greater than zero.",
+                    file.path().display(),
+                    file.section_key("log-file-max-size")
+                );
+                Err(Failed)
+            }
+            res => Ok(res)
+        }
+    }
+
+    /// Takes the per-module log levels from the config file.
+    ///
+    /// The levels are given as an array of arrays with two elements: the
+    /// module path prefix and the level.
+    fn take_module_levels(
+        file: &mut ConfigFile
+    ) -> Result<Vec<(String, LevelName)>, Failed> {
+        const KEY: &str = "log-module-levels";
+
+        let array = match file.take_value(KEY)? {
+            Some(toml_edit::Value::Array(array)) => array,
+            Some(_) => {
+                error!(
+                    "Failed in config file {}: \
+                     '{}' expected to be an array of \
+                     [\"module\", \"level\"] pairs.",
+                    file.path().display(), file.section_key(KEY)
+                );
+                return Err(Failed)
+            }
+            None => return Ok(Vec::new())
+        };
+        let mut res = Vec::new();
+        for item in array.iter() {
+            let pair = item.as_array().filter(|pair| pair.len() == 2);
+            let entry = pair.and_then(|pair| {
+                let module = pair.get(0)?.as_str()?;
+                let level = match pair.get(1)? {
+                    toml_edit::Value::String(value) => {
+                        LevelName::from_str(value.value()).ok()?
+                    }
+                    toml_edit::Value::Integer(value) => {
+                        LevelName::from_verbosity(
+                            u64::try_from(*value.value()).ok()?
+                        )?
+                    }
+                    _ => return None
+                };
+                Some((String::from(module), level))
+            });
+            match entry {
+                Some(entry) => res.push(entry),
+                None => {
+                    error!(
+                        "Failed in config file {}: \
+                         invalid entry {} in '{}', expected a \
+                         [\"module\", \"level\"] pair.",
+                        file.path().display(), item.to_string().trim(),
+                        file.section_key(KEY)
+                    );
+                    return Err(Failed)
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    /// Re-reads the logging configuration and applies it.
+    ///
+    /// Only the logging options are taken from the config file at `path`.
+    /// All other options are ignored. The new configuration replaces that
+    /// of the installed logger, keeping the daemon mode and application
+    /// name it was switched with.
+    ///
+    /// This is intended for reloading log settings upon `SIGHUP` without
+    /// touching the rest of the daemon’s state. If the file system root
+    /// has been changed, use
+    /// [`reload_from_file_with`][Self::reload_from_file_with] instead.
+    pub fn reload_from_file(path: &Path) -> Result<(), Failed> {
+        Self::reload_from_file_with(path, Ok::<_, Infallible>)
+    }
+
+    /// Re-reads the logging configuration after adjusting paths.
+    ///
+    /// This is like [`reload_from_file`][Self::reload_from_file] but the
+    /// path of the config file and the paths of all log files are passed
+    /// to `adjust` first. This can be used to adjust paths after changing
+    /// the file system root, e.g., via [`Process::current_path`]. Relative
+    /// paths in the config file are still resolved against the original
+    /// location of the file. If `adjust` returns an error, reloading
+    /// fails.
+    ///
+    /// [`Process::current_path`]: crate::process::Process::current_path
+    pub fn reload_from_file_with<E: fmt::Display>(
+        path: &Path, mut adjust: impl FnMut(PathBuf) -> Result<PathBuf, E>
+    ) -> Result<(), Failed> {
+        let actual = match adjust(path.to_path_buf()) {
+            Ok(actual) => actual,
+            Err(err) => {
+                error!(
+                    "Cannot use config file '{}': {}", path.display(), err
+                );
+                return Err(Failed)
+            }
+        };
+        let content = match fs::read_to_string(&actual) {
+            Ok(content) => content,
+            Err(err) => {
+                error!(
+                    "Failed to read config file {}: {}",
+                    actual.display(), err
+                );
+                return Err(Failed)
+            }
+        };
+        let mut file = ConfigFile::parse(&content, path)?;
+        let mut config = Self::from_config_file(&mut file)?;
+        config.adjust_paths(&mut adjust)?;
+        let mut logger = Logger::from_config(&config)?;
+        let (daemon, app_name) = GLOBAL_LOGGER.settings();
+        logger.app_name = app_name;
+        logger.switch_logging(daemon)
+    }
+
+    /// Replaces the paths of all log files with the result of `adjust`.
+    fn adjust_paths<E: fmt::Display>(
+        &mut self, adjust: &mut impl FnMut(PathBuf) -> Result<PathBuf, E>
+    ) -> Result<(), Failed> {
+        let paths = self.log_targets.iter_mut().filter_map(|target| {
+            match target.path.as_mut() {
+                Some(LogPath::Path(path)) => Some(path),
+                _ => None
+            }
+        }).chain(
+            match self.log_file.as_mut() {
+                Some(LogPath::Path(path)) => Some(path),
+                _ => None
+            }
+        ).chain(self.error_log_file.as_mut());
+        for path in paths {
+            match adjust(path.as_path().to_path_buf()) {
+                Ok(adjusted) => *path = adjusted.into(),
+                Err(err) => {
+                    error!(
+                        "Cannot use log file '{}': {}",
+                        path.as_path().display(), err
+                    );
+                    return Err(Failed)
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn from_args(args: &Args) -> Self {
+        let mut res = Self::default();
+        res.apply_args(args);
+        res
+    }
+
+    /// Applies the arguments to the logger.
+    pub fn apply_args(&mut self, args: &Args) {
+        if let Some(level) = args.opt_level() {
+            self.log_level = LevelName(level)
+        }
+
+        if args.stderr {
+            self.log_targets = vec![TargetName::Stderr.into()];
+        }
+        else if let Some(path) = args.logfile.as_ref() {
+            self.log_targets = vec![TargetName::File.into()];
+            self.log_file = Some(path.clone());
+        }
+        else {
+            #[cfg(unix)]
+            if args.syslog {
+                self.log_targets = vec![TargetName::Syslog.into()];
+            }
+        }
+
+        #[cfg(unix)]
+        if let Some(facility) = args.syslog_facility {
+            self.syslog_facility = facility;
+        }
+
+        #[cfg(unix)]
+        if let Some(format) = args.syslog_format {
+            self.syslog_format = format;
+        }
+
+        if let Some(timestamps) = args.log_timestamps {
+            self.timestamps = timestamps;
+        }
+
+        if let Some(format) = args.log_format {
+            self.format = format;
+        }
+
+        if let Some(color) = args.log_color {
+            self.color = color;
+        }
+    }
+
+    /// Validates the configuration and adds the results to `report`.
+    ///
+    /// Checks that the options are consistent and, if logging to a file is
+    /// configured, that the file can be written to or created.
+    pub fn validate(&self, report: &mut ValidationReport) {
+        for issue in self.consistency_errors() {
+            report.fail("consistency", issue);
+        }
+        for issue in self.consistency_warnings() {
+            report.pass("consistency", format!("warning: {}", issue));
+        }
+        match (self.uses_log_file(), self.log_file.as_ref()) {
+            (true, Some(LogPath::Path(path))) => {
+                Self::validate_file("log-file", path, report)
+            }
+            (true, None) => {
+                report.fail("log-file", "missing 'log-file' option");
+            }
+            _ => { }
+        }
+        for path in self.target_paths() {
+            Self::validate_file("log", path, report)
+        }
+        if let Some(path) = self.error_log_file.as_ref() {
+            Self::validate_file("error-log-file", path, report)
+        }
+    }
+
+    /// Checks that the options of the configuration are consistent.
+    ///
+    /// This catches combinations of options that are individually valid
+    /// but together result in broken logging. Each such problem is logged
+    /// as an error and makes the check fail. Combinations that are merely
+    /// unusual, such as options that have no effect, are logged as
+    /// warnings only.
+    pub fn check_consistency(&self) -> Result<(), Failed> {
+        for issue in self.consistency_warnings() {
+            warn!("Possibly inconsistent logging configuration: {}", issue);
+        }
+        let issues = self.consistency_errors();
+        for issue in &issues {
+            error!("Inconsistent logging configuration: {}", issue);
+        }
+        if issues.is_empty() {
+            Ok(())
+        }
+        else {
+            Err(Failed)
+        }
+    }
+
+    /// Returns the configured log targets.
+    fn targets(&self) -> &[TargetConfig] {
+        const DEFAULT: &[TargetConfig] = &[TargetConfig {
+            target: TargetName::Default, path: None, level: None
+        }];
+
+        if self.log_targets.is_empty() {
+            DEFAULT
+        }
+        else {
+            &self.log_targets
+        }
+    }
+
+    /// Returns the target for the error log file if there is one.
+    ///
+    /// The error log file is a file target with its own log level just
+    /// like one given in the `log` array of tables. Its level is `warn`
+    /// unless configured otherwise.
+    fn error_log_target(&self) -> Option<TargetConfig> {
+        self.error_log_file.as_ref().map(|path| {
+            TargetConfig {
+                target: TargetName::File,
+                path: Some(LogPath::Path(path.clone())),
+                level: Some(
+                    self.error_log_level.unwrap_or(
+                        LevelName(LevelFilter::Warn)
+                    )
+                ),
+            }
+        })
+    }
+
+    /// Returns whether one of the targets logs to `log-file`.
+    ///
+    /// This is the case for file targets that don’t have their own path.
+    fn uses_log_file(&self) -> bool {
+        self.targets().iter().any(|target| {
+            matches!(target.target, TargetName::File) && target.path.is_none()
+        })
+    }
+
+    /// Returns the paths of the file targets that have their own path.
+    fn target_paths(&self) -> impl Iterator<Item = &ConfigPath> {
+        self.log_targets.iter().filter_map(|target| {
+            match target.path.as_ref() {
+                Some(LogPath::Path(path)) => Some(path),
+                _ => None
+            }
+        })
+    }
+
+    /// Returns a description of every contradiction in the configuration.
+    fn consistency_errors(&self) -> Vec<&'static str> {
+        let mut res = Vec::new();
+        if self.uses_log_file() && self.log_file.is_none() {
+            res.push(
+                "log target is 'file' but 'log-file' is missing"
+            );
+        }
+        if let Some(LogPath::Path(path)) = self.log_file.as_ref() {
+            if let Some(error_path) = self.error_log_file.as_ref() {
+                if path.is_same_file(error_path) {
+                    res.push(
+                        "'log-file' and 'error-log-file' are the same file"
+                    );
+                }
+            }
+            match fs::metadata(path) {
+                Ok(meta) if meta.is_dir() => {
+                    res.push("'log-file' is a directory");
+                }
+                Ok(meta) if !Self::is_loggable_file(&meta) => {
+                    res.push("'log-file' is a special file");
+                }
+                _ => { }
+            }
+        }
+        for path in self.target_paths() {
+            if let Some(error_path) = self.error_log_file.as_ref() {
+                if path.is_same_file(error_path) {
+                    res.push(
+                        "a file target in 'log' and 'error-log-file' are \
+                         the same file"
+                    );
+                }
+            }
+            match fs::metadata(path) {
+                Ok(meta) if meta.is_dir() => {
+                    res.push("a file target in 'log' is a directory");
+                }
+                Ok(meta) if !Self::is_loggable_file(&meta) => {
+                    res.push("a file target in 'log' is a special file");
+                }
+                _ => { }
+            }
+        }
+        res
+    }
+
+    /// Returns a description of every option that has no effect.
+    ///
+    /// These are usually a mistake but don’t prevent logging from working.
+    fn consistency_warnings(&self) -> Vec<&'static str> {
+        let mut res = Vec::new();
+        if !self.uses_log_file() && self.log_file.is_some() {
+            res.push(
+                "'log-file' is set but the log target is not 'file'; \
+                 the file will not be used"
+            );
+        }
+        if self.error_log_level.is_some() && self.error_log_file.is_none() {
+            res.push(
+                "'error-log-level' is set but 'error-log-file' is missing"
+            );
+        }
+        if self.log_file_keep.is_some() && self.log_file_max_size.is_none() {
+            res.push(
+                "'log-file-keep' is set but 'log-file-max-size' is missing"
+            );
+        }
+        if self.log_file_max_total_size.is_some()
+            && self.log_file_max_size.is_none()
+        {
+            res.push(
+                "'log-file-max-total-size' is set but 'log-file-max-size' \
+                 is missing"
+            );
+        }
+        res
+    }
+
+    /// Returns whether a file with the given metadata can be logged to.
+    ///
+    /// Apart from regular files, this allows character devices such as
+    /// `/dev/null` or a terminal.
+    fn is_loggable_file(meta: &fs::Metadata) -> bool {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+
+            meta.is_file() || meta.file_type().is_char_device()
+        }
+        #[cfg(not(unix))]
+        {
+            meta.is_file()
+        }
+    }
+
+    /// Returns the paths of all files the configuration logs to.
+    ///
+    /// Each path is returned together with the name of its config option.
+    pub fn file_paths(&self) -> Vec<(&'static str, &Path)> {
+        let mut res = Vec::new();
+        if let Some(LogPath::Path(path)) = self.log_file.as_ref() {
+            res.push(("log-file", path.as_path()))
+        }
+        for path in self.target_paths() {
+            res.push(("log", path.as_path()))
+        }
+        if let Some(path) = self.error_log_file.as_ref() {
+            res.push(("error-log-file", path.as_path()))
+        }
+        res
+    }
+
+    /// Checks that the log file at `path` can be written to or created.
+    fn validate_file(
+        name: &str, path: &Path, report: &mut ValidationReport
+    ) {
+        match fs::metadata(path) {
+            Ok(meta) if meta.is_dir() => {
+                report.fail(
+                    name,
+                    format!("{} is a directory", path.display())
+                );
+            }
+            Ok(meta) if meta.permissions().readonly() => {
+                report.fail(
+                    name,
+                    format!("{} is not writable", path.display())
+                );
+            }
+            Ok(_) => {
+                report.pass(
+                    name,
+                    format!("{} is writable", path.display())
+                );
+            }
+            Err(_) => {
+                match parent_dir(path).map(fs::metadata) {
+                    Some(Ok(meta)) if meta.is_dir() => {
+                        report.pass(
+                            name,
+                            format!("{} can be created", path.display())
+                        );
+                    }
+                    _ => {
+                        report.fail(
+                            name,
+                            format!(
+                                "directory for {} does not exist",
+                                path.display()
+                            )
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Adds the configuration a config file
+    pub fn add_to_config_file(&self, config: &mut ConfigFile) {
+        config.insert_string("log-level", self.log_level.as_str());
+        let targets = self.targets();
+        if targets.iter().any(|target| !target.is_plain()) {
+            config.insert_value(
+                "log",
+                targets.iter().map(|target| {
+                    target.to_inline_table()
+                }).collect::<toml_edit::Array>()
+            )
+        }
+        else if let [target] = targets {
+            config.insert_string("log", target.target.as_str())
+        }
+        else {
+            config.insert_value(
+                "log",
+                targets.iter().map(|target| {
+                    target.target.as_str()
+                }).collect::<toml_edit::Array>()
+            )
+        }
+        #[cfg(unix)]
+        if !self.syslog_facility.is_default() {
+            config.insert_string(
+                "syslog-facility",
+                self.syslog_facility.as_str()
+            );
+        }
+        #[cfg(unix)]
+        if !matches!(self.syslog_format, SyslogFormat::Rfc3164) {
+            config.insert_string("syslog-format", self.syslog_format.as_str());
+        }
+        if let Some(path) = self.log_file.as_ref() {
+            config.insert_string(
+                "log-file", path
+            );
+        }
+        if let Some(path) = self.error_log_file.as_ref() {
+            config.insert_path("error-log-file", path);
+        }
+        if let Some(level) = self.error_log_level {
+            config.insert_string("error-log-level", level.as_str());
+        }
+        if !matches!(self.timestamps, TimestampMode::Local) {
+            config.insert_string("log-timestamps", self.timestamps.as_str());
+        }
+        if !matches!(self.format, LogFormat::Human) {
+            config.insert_string("log-format", self.format.as_str());
+        }
+        if !matches!(self.color, ColorMode::Auto) {
+            config.insert_string("log-color", self.color.as_str());
+        }
+        if !self.module_levels.is_empty() {
+            config.insert_value(
+                "log-module-levels",
+                self.module_levels.iter().map(|(module, level)| {
+                    toml_edit::Value::Array(
+                        [module.as_str(), level.as_str()].into_iter().collect()
+                    )
+                }).collect::<toml_edit::Array>()
+            );
+        }
+        if let Some(modules) = self.suppress_below_info.as_ref() {
+            config.insert_value(
+                "log-suppress-below-info",
+                modules.iter().collect::<toml_edit::Array>()
+            );
+        }
+        if let Some(modules) = self.errors_only.as_ref() {
+            config.insert_value(
+                "log-errors-only",
+                modules.iter().collect::<toml_edit::Array>()
+            );
+        }
+        if let Some(size) = self.log_file_max_size {
+            config.insert_value(
+                "log-file-max-size", i64::try_from(size).unwrap_or(i64::MAX)
+            );
+        }
+        if let Some(keep) = self.log_file_keep {
+            config.insert_value(
+                "log-file-keep", i64::try_from(keep).unwrap_or(i64::MAX)
+            );
+        }
+        if let Some(size) = self.log_file_max_total_size {
+            config.insert_value(
+                "log-file-max-total-size",
+                i64::try_from(size).unwrap_or(i64::MAX)
+            );
+        }
+        if let Some(capacity) = self.memory_buffer {
+            config.insert_value(
+                "log-memory-buffer",
+                i64::try_from(capacity).unwrap_or(i64::MAX)
+            );
+        }
+    }
+
+    /// Logs the effective settings at debug level.
+    ///
+    /// Each setting is logged as a record of its own. Where an option
+    /// that isn’t set has a default, the default is logged.
+    pub fn log_settings(&self) {
+        self.visit_settings(&mut |key, value| log_opt_setting(key, value))
+    }
+
+    /// Calls `op` with the name and effective value of every setting.
+    ///
+    /// The value is `None` if the option isn’t set and has no default.
+    fn visit_settings(
+        &self, op: &mut dyn FnMut(&'static str, Option<&dyn fmt::Display>)
+    ) {
+        fn opt<T: fmt::Display>(
+            value: &Option<T>
+        ) -> Option<&dyn fmt::Display> {
+            value.as_ref().map(|value| value as _)
+        }
+
+        op("log-level", Some(&self.log_level.as_str()));
+        op(
+            "log",
+            Some(&self.targets().iter().map(|target| {
+                if target.is_plain() {
+                    target.target.as_str().into()
+                }
+                else {
+                    target.to_inline_table()
+                }
+            }).collect::<toml_edit::Array>())
+        );
+        #[cfg(unix)]
+        op("syslog-facility", Some(&self.syslog_facility.as_str()));
+        #[cfg(unix)]
+        op("syslog-format", Some(&self.syslog_format.as_str()));
+        op("log-file", opt(&self.log_file));
+        op(
+            "error-log-file",
+            opt(&self.error_log_file.as_ref().map(|path| path.display()))
+        );
+        op(
+            "error-log-level",
+            opt(&self.error_log_file.as_ref().map(|_| {
+                self.error_log_level.unwrap_or(
+                    LevelName(LevelFilter::Warn)
+                ).as_str()
+            }))
+        );
+        op("log-timestamps", Some(&self.timestamps.as_str()));
+        op("log-format", Some(&self.format.as_str()));
+        op("log-color", Some(&self.color.as_str()));
+        op(
+            "log-module-levels",
+            Some(&self.module_levels.iter().map(|(module, level)| {
+                toml_edit::Value::Array(
+                    [module.as_str(), level.as_str()].into_iter().collect()
+                )
+            }).collect::<toml_edit::Array>())
+        );
+        let default_suppress = Suppression::default();
+        op(
+            "log-suppress-below-info",
+            Some(&self.suppress_below_info.as_ref().unwrap_or(
+                &default_suppress.below_info
+            ).iter().collect::<toml_edit::Array>())
+        );
+        op(
+            "log-errors-only",
+            Some(&self.errors_only.as_ref().unwrap_or(
+                &default_suppress.errors_only
+            ).iter().collect::<toml_edit::Array>())
+        );
+        op("log-file-max-size", opt(&self.log_file_max_size));
+        op(
+            "log-file-keep",
+            opt(&self.log_file_max_size.map(|_| {
+                self.log_file_keep.unwrap_or(Rotation::DEFAULT_KEEP)
+            }))
+        );
+        op("log-file-max-total-size", opt(&self.log_file_max_total_size));
+        op("log-memory-buffer", opt(&self.memory_buffer));
+    }
+}
+
+
+//------------ TargetName ----------------------------------------------------
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "&'static str")]
+enum TargetName {
+    #[default]
+    Default,
+
+    Auto,
+    #[cfg(unix)]
+    Syslog,
+    Stderr,
+    #[cfg(unix)]
+    Journal,
+    File
+}
+
+impl TargetName {
+    fn as_str(self) -> &'static str {
+        match self {
+            TargetName::Default => "default",
+            TargetName::Auto => "auto",
+            #[cfg(unix)]
+            TargetName::Syslog => "syslog",
+            TargetName::Stderr => "stderr",
+            #[cfg(unix)]
+            TargetName::Journal => "journal",
+            TargetName::File => "file",
+        }
+    }
+}
+
+impl From<TargetName> for &'static str {
+    fn from(target: TargetName) -> Self {
+        target.as_str()
+    }
+}
+
+impl TryFrom<String> for TargetName {
+    type Error = &'static str;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Self::from_str(&s)
+    }
+}
+
+impl FromStr for TargetName {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(TargetName::Default),
+            "auto" => Ok(TargetName::Auto),
+            #[cfg(unix)]
+            "syslog" => Ok(TargetName::Syslog),
+            "stderr" => Ok(TargetName::Stderr),
+            #[cfg(unix)]
+            "journal" => Ok(TargetName::Journal),
+            "file" => Ok(TargetName::File),
+            _ => Err("invalid log target")
+        }
+    }
+}
+
+
+//------------ TargetConfig --------------------------------------------------
+
+/// The configuration of a single log target.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct TargetConfig {
+    /// The target to log to.
+    target: TargetName,
+
+    /// The path of the log file for a file target.
+    ///
+    /// If this is `None`, the `log-file` option is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    path: Option<LogPath>,
+
+    /// The log level for this target.
+    ///
+    /// If this is `None`, the `log-level` option is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    level: Option<LevelName>,
+}
+
+impl TargetConfig {
+    /// Takes the target from a table of the `log` array of tables.
+    fn from_config_table(
+        table: &mut ConfigFile
+    ) -> Result<Self, Failed> {
+        let file_path = table.path().to_path_buf();
+        let section = table.section().to_string();
+        let fail = |msg: fmt::Arguments| {
+            error!(
+                "Failed in config file {}: '{}': {}.",
+                file_path.display(), section, msg
+            );
+            Failed
+        };
+
+        let target = match table.take_value("target")? {
+            Some(toml_edit::Value::String(value)) => {
+                TargetName::from_str(value.value()).map_err(|_| {
+                    fail(format_args!(
+                        "invalid log target '{}'", value.value()
+                    ))
+                })?
+            }
+            Some(_) => {
+                return Err(fail(format_args!(
+                    "'target' expected to be a string"
+                )))
+            }
+            None => {
+                return Err(fail(format_args!("missing 'target'")))
+            }
+        };
+        let path = match table.take_value("path")? {
+            Some(toml_edit::Value::String(value)) => {
+                Some(LogPath::from(value.into_value()))
+            }
+            Some(_) => {
+                return Err(fail(format_args!(
+                    "'path' expected to be a string"
+                )))
+            }
+            None => None
+        };
+        match (target, path.is_some()) {
+            (TargetName::File, false) => {
+                return Err(fail(format_args!(
+                    "missing 'path' for target 'file'"
+                )))
+            }
+            (TargetName::File, true) | (_, false) => { }
+            (target, true) => {
+                return Err(fail(format_args!(
+                    "'path' is not allowed for target '{}'", target.as_str()
+                )))
+            }
+        }
+        let level = match table.take_value("level")? {
+            Some(toml_edit::Value::String(value)) => {
+                Some(LevelName::from_str(value.value()).ok())
+            }
+            Some(toml_edit::Value::Integer(value)) => {
+                Some(u64::try_from(*value.value()).ok().and_then(
+                    LevelName::from_verbosity
+                ))
+            }
+            Some(_) => Some(None),
+            None => None
+        };
+        let level = match level {
+            Some(Some(level)) => Some(level),
+            Some(None) => {
+                return Err(fail(format_args!(
+                    "'level' expected to be a log level name or a number \
+                     between 0 and 4"
+                )))
+            }
+            None => None
+        };
+        if let Some(key) = table.remaining_keys().next() {
+            return Err(fail(format_args!("unknown setting '{}'", key)))
+        }
+        Ok(TargetConfig { target, path, level })
+    }
+
+    /// Returns whether both configs describe the same target.
+    ///
+    /// File targets are the same if they use the same file. All other
+    /// targets are the same if they are of the same kind.
+    fn is_same_target(&self, other: &Self) -> bool {
+        match (self.path.as_ref(), other.path.as_ref()) {
+            (Some(LogPath::Path(left)), Some(LogPath::Path(right))) => {
+                left.is_same_file(right)
+            }
+            (Some(LogPath::Stderr), Some(LogPath::Stderr)) => true,
+            (None, None) => self.target.as_str() == other.target.as_str(),
+            _ => false
+        }
+    }
+
+    /// Returns whether the config only consists of the target name.
+    fn is_plain(&self) -> bool {
+        self.path.is_none() && self.level.is_none()
+    }
+
+    /// Converts the config into an inline table for a config file.
+    fn to_inline_table(&self) -> toml_edit::Value {
+        let mut res = toml_edit::InlineTable::new();
+        res.insert("target", self.target.as_str().into());
+        if let Some(path) = self.path.as_ref() {
+            res.insert("path", path.to_string().into());
+        }
+        if let Some(level) = self.level {
+            res.insert("level", level.as_str().into());
+        }
+        toml_edit::Value::InlineTable(res)
+    }
+
+    /// Deserializes a single target or a list of targets.
+    ///
+    /// The list can contain both target names and tables.
+    fn deserialize_list<'de, D: Deserializer<'de>>(
+        deserializer: D
+    ) -> Result<Vec<Self>, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Entry {
+            Name(TargetName),
+            Table(TargetConfig),
+        }
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum List {
+            One(TargetName),
+            Many(Vec<Entry>),
+        }
+
+        match List::deserialize(deserializer)? {
+            List::One(target) => Ok(vec![target.into()]),
+            List::Many(targets) => {
+                Ok(targets.into_iter().map(|entry| {
+                    match entry {
+                        Entry::Name(target) => target.into(),
+                        Entry::Table(config) => config,
+                    }
+                }).collect())
+            }
+        }
+    }
+
+    /// Serializes the targets.
+    ///
+    /// A single plain target is serialized as a string, multiple plain
+    /// targets as a list of strings, and everything else as a list of
+    /// tables.
+    fn serialize_list<S: Serializer>(
+        targets: &[Self], serializer: S
+    ) -> Result<S::Ok, S::Error> {
+        if targets.iter().any(|target| !target.is_plain()) {
+            return targets.serialize(serializer)
+        }
+        match targets {
+            [] => TargetName::Default.serialize(serializer),
+            [target] => target.target.serialize(serializer),
+            targets => {
+                targets.iter().map(|target| {
+                    target.target
+                }).collect::<Vec<_>>().serialize(serializer)
+            }
+        }
+    }
+}
+
+impl From<TargetName> for TargetConfig {
+    fn from(target: TargetName) -> Self {
+        TargetConfig { target, path: None, level: None }
+    }
+}
+
+
+//------------ TimestampMode -------------------------------------------------
+
+/// How to timestamp log messages.
+///
+/// This only applies to messages written to a log file or stderr. Syslog
+/// and the systemd journal add their own timestamps.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "&'static str")]
+pub enum TimestampMode {
+    /// Use the local time.
+    #[default]
+    Local,
+
+    /// Use UTC.
+    Utc,
+
+    /// Don’t add timestamps at all.
+    None,
+}
+
+impl TimestampMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            TimestampMode::Local => "local",
+            TimestampMode::Utc => "utc",
+            TimestampMode::None => "none",
+        }
+    }
+}
+
+impl From<TimestampMode> for &'static str {
+    fn from(mode: TimestampMode) -> Self {
+        mode.as_str()
+    }
+}
+
+impl TryFrom<String> for TimestampMode {
+    type Error = &'static str;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Self::from_str(&s)
+    }
+}
+
+impl FromStr for TimestampMode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(TimestampMode::Local),
+            "utc" => Ok(TimestampMode::Utc),
+            "none" => Ok(TimestampMode::None),
+            _ => Err("invalid timestamp mode")
+        }
+    }
+}
+
+impl clap::ValueEnum for TimestampMode {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[TimestampMode::Local, TimestampMode::Utc, TimestampMode::None]
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        Some(clap::builder::PossibleValue::new(self.as_str()))
+    }
+}
+
+
+//------------ LogFormat -----------------------------------------------------
+
+/// The format of log messages.
+///
+/// This only applies to messages written to a log file or stderr.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "&'static str")]
+pub enum LogFormat {
+    /// A human-readable line with timestamp, level, and message.
+    #[default]
+    Human,
+
+    /// One JSON object per line.
+    ///
+    /// The object contains the members `timestamp` (unless timestamps are
+    /// disabled), `level`, `target`, `module_path` (if available), and
+    /// `message`.
+    Json,
+}
+
+impl LogFormat {
+    fn as_str(self) -> &'static str {
+        match self {
+            LogFormat::Human => "human",
+            LogFormat::Json => "json",
+        }
+    }
+}
+
+impl From<LogFormat> for &'static str {
+    fn from(format: LogFormat) -> Self {
+        format.as_str()
+    }
+}
+
+impl TryFrom<String> for LogFormat {
+    type Error = &'static str;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Self::from_str(&s)
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(LogFormat::Human),
+            "json" => Ok(LogFormat::Json),
+            _ => Err("invalid log format")
+        }
+    }
+}
+
+impl clap::ValueEnum for LogFormat {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[LogFormat::Human, LogFormat::Json]
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        Some(clap::builder::PossibleValue::new(self.as_str()))
+    }
+}
 
-//------------ Config --------------------------------------------------------
 
-#[derive(Clone, Debug, Default, Deserialize, Serialize)]
-pub struct Config {
-    #[serde(rename = "log-level", alias = "log_level", default)]
-    log_level: LevelName,
+//------------ ColorMode -----------------------------------------------------
 
-    #[serde(rename = "log", alias = "log_target", default)]
-    log_target: TargetName,
+/// Whether to color log messages.
+///
+/// This only applies to messages written to stderr in the human-readable
+/// format. The level of each message is colored according to its severity.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "&'static str")]
+pub enum ColorMode {
+    /// Use color if stderr is a terminal.
+    ///
+    /// The `NO_COLOR` and `CLICOLOR_FORCE` environment variables are
+    /// respected: If `NO_COLOR` is set, no color is used. Otherwise, if
+    /// `CLICOLOR_FORCE` is set to anything but `0`, color is used even if
+    /// stderr isn’t a terminal.
+    #[default]
+    Auto,
 
-    #[cfg(unix)]
-    #[serde(rename = "syslog-facility", alias = "log_facility", default)]
-    syslog_facility: unix::FacilityArg,
+    /// Always use color.
+    Always,
 
-    #[serde(rename = "log-file", alias = "log_file")]
-    log_file: Option<LogPath>,
+    /// Never use color.
+    Never,
 }
 
-impl Config {
-    /// Creates the logger from a config file.
-    pub fn from_config_file(file: &mut ConfigFile) -> Result<Self, Failed> {
-        Ok(Self {
-            log_level: file.take_from_str::<LevelName>(
-                "log-level"
-            )?.unwrap_or_default(),
-            log_target: file.take_from_str::<TargetName>(
-                "log"
-            )?.unwrap_or_default(),
-            #[cfg(unix)]
-            syslog_facility: file.take_from_str::<unix::FacilityArg>(
-                "syslog-facility"
-            )?.unwrap_or_default(),
-            log_file: file.take_string("log-file")?.map(Into::into),
-        })
-    }
-
-    pub fn from_args(args: &Args) -> Self {
-        let mut res = Self::default();
-        res.apply_args(args);
-        res
+impl ColorMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            ColorMode::Auto => "auto",
+            ColorMode::Always => "always",
+            ColorMode::Never => "never",
+        }
     }
 
-    /// Applies the arguments to the logger.
-    pub fn apply_args(&mut self, args: &Args) {
-        if let Some(level) = args.opt_level() {
-            self.log_level = LevelName(level)
+    /// Returns whether to use color when writing to stderr.
+    fn use_color(self) -> bool {
+        match self {
+            ColorMode::Auto => {
+                Self::auto_color(
+                    env::var_os("NO_COLOR"),
+                    env::var_os("CLICOLOR_FORCE"),
+                    io::stderr().is_terminal(),
+                )
+            }
+            ColorMode::Always => true,
+            ColorMode::Never => false,
         }
+    }
 
-        if args.stderr {
-            self.log_target = TargetName::Stderr;
-        }
-        else if let Some(path) = args.logfile.as_ref() {
-            self.log_target = TargetName::File;
-            self.log_file = Some(path.clone());
+    /// Decides whether to use color in auto mode.
+    ///
+    /// The first two arguments are the values of the `NO_COLOR` and
+    /// `CLICOLOR_FORCE` environment variables.
+    fn auto_color(
+        no_color: Option<OsString>,
+        force: Option<OsString>,
+        is_terminal: bool,
+    ) -> bool {
+        if no_color.is_some() {
+            return false
         }
-        else {
-            #[cfg(unix)]
-            if args.syslog {
-                self.log_target = TargetName::Syslog;
-            }
+        match force {
+            Some(force) if !force.is_empty() && force != "0" => true,
+            _ => is_terminal
         }
+    }
+}
 
-        #[cfg(unix)]
-        if let Some(facility) = args.syslog_facility {
-            self.syslog_facility = facility;
-        }
+impl From<ColorMode> for &'static str {
+    fn from(mode: ColorMode) -> Self {
+        mode.as_str()
     }
+}
 
-    /// Adds the configuration a config file
-    pub fn add_to_config_file(&self, config: &mut ConfigFile) {
-        config.insert_string("log-level", self.log_level.as_str());
-        config.insert_string("log", self.log_target.as_str());
-        #[cfg(unix)]
-        if !self.syslog_facility.is_default() {
-            config.insert_string(
-                "syslog-facility",
-                self.syslog_facility.as_str()
-            );
-        }
-        if let Some(path) = self.log_file.as_ref() {
-            config.insert_string(
-                "log-file", path
-            );
+impl TryFrom<String> for ColorMode {
+    type Error = &'static str;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Self::from_str(&s)
+    }
+}
+
+impl FromStr for ColorMode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorMode::Auto),
+            "always" => Ok(ColorMode::Always),
+            "never" => Ok(ColorMode::Never),
+            _ => Err("invalid color mode")
         }
     }
 }
 
+impl clap::ValueEnum for ColorMode {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[ColorMode::Auto, ColorMode::Always, ColorMode::Never]
+    }
 
-//------------ TargetName ----------------------------------------------------
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        Some(clap::builder::PossibleValue::new(self.as_str()))
+    }
+}
 
+
+//------------ SyslogFormat --------------------------------------------------
+
+/// The message format used when logging to syslog.
+#[cfg(unix)]
 #[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
 #[serde(try_from = "String", into = "&'static str")]
-enum TargetName {
+pub enum SyslogFormat {
+    /// The traditional BSD syslog format of RFC 3164.
     #[default]
-    Default,
+    Rfc3164,
 
-    #[cfg(unix)]
-    Syslog,
-    Stderr,
-    File
+    /// The format of RFC 5424 with full timestamps.
+    Rfc5424,
 }
 
-impl TargetName {
+#[cfg(unix)]
+impl SyslogFormat {
     fn as_str(self) -> &'static str {
         match self {
-            TargetName::Default => "default",
-            #[cfg(unix)]
-            TargetName::Syslog => "syslog",
-            TargetName::Stderr => "stderr",
-            TargetName::File => "file",
+            SyslogFormat::Rfc3164 => "rfc3164",
+            SyslogFormat::Rfc5424 => "rfc5424",
         }
     }
 }
 
-impl From<TargetName> for &'static str {
-    fn from(target: TargetName) -> Self {
-        target.as_str()
+#[cfg(unix)]
+impl From<SyslogFormat> for &'static str {
+    fn from(format: SyslogFormat) -> Self {
+        format.as_str()
     }
 }
 
-impl TryFrom<String> for TargetName {
+#[cfg(unix)]
+impl TryFrom<String> for SyslogFormat {
     type Error = &'static str;
 
     fn try_from(s: String) -> Result<Self, Self::Error> {
@@ -226,26 +1740,41 @@ impl TryFrom<String> for TargetName {
     }
 }
 
-impl FromStr for TargetName {
+#[cfg(unix)]
+impl FromStr for SyslogFormat {
     type Err = &'static str;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
-            "default" => Ok(TargetName::Default),
-            #[cfg(unix)]
-            "syslog" => Ok(TargetName::Syslog),
-            "stderr" => Ok(TargetName::Stderr),
-            "file" => Ok(TargetName::File),
-            _ => Err("invalid log target")
+            "rfc3164" => Ok(SyslogFormat::Rfc3164),
+            "rfc5424" => Ok(SyslogFormat::Rfc5424),
+            _ => Err("invalid syslog format")
         }
     }
 }
 
+#[cfg(unix)]
+impl clap::ValueEnum for SyslogFormat {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[SyslogFormat::Rfc3164, SyslogFormat::Rfc5424]
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        Some(clap::builder::PossibleValue::new(self.as_str()))
+    }
+}
+
 
 //------------ LevelName -----------------------------------------------------
 
+/// The log level as given in the config file.
+///
+/// The level can be given either by name or as a numeric verbosity. The
+/// numbers follow the counting of the `-v` command line option relative
+/// to the default level: 0 is error, 1 is warn, 2 is info, 3 is debug, and
+/// 4 is trace.
 #[derive(Clone, Copy, Debug, Deserialize, Serialize)]
-#[serde(try_from = "String", into = "&'static str")]
+#[serde(try_from = "LevelValue", into = "&'static str")]
 struct LevelName(LevelFilter);
 
 impl Default for LevelName {
@@ -255,6 +1784,52 @@ impl Default for LevelName {
 }
 
 impl LevelName {
+    /// Takes the level from the given key of a config file.
+    ///
+    /// Accepts both a string with a level name or a numeric verbosity and
+    /// an integer verbosity.
+    fn take_from_config(
+        file: &mut ConfigFile, key: &str
+    ) -> Result<Option<Self>, Failed> {
+        let res = match file.take_value(key)? {
+            Some(toml_edit::Value::String(value)) => {
+                Self::from_str(value.value()).ok()
+            }
+            Some(toml_edit::Value::Integer(value)) => {
+                u64::try_from(*value.value()).ok().and_then(
+                    Self::from_verbosity
+                )
+            }
+            Some(_) => None,
+            None => return Ok(None)
+        };
+        match res {
+            Some(res) => Ok(Some(res)),
+            None => {
+                error!(
+                    "Failed in config file {}: \
+                     '{}' expected to be a log level name or a number \
+                     between 0 and 4.",
+                    file.path().display(), file.section_key(key)
+                );
+                Err(Failed)
+            }
+        }
+    }
+
+    /// Converts a numeric verbosity into a level.
+    fn from_verbosity(value: u64) -> Option<Self> {
+        let level = match value {
+            0 => LevelFilter::Error,
+            1 => LevelFilter::Warn,
+            2 => LevelFilter::Info,
+            3 => LevelFilter::Debug,
+            4 => LevelFilter::Trace,
+            _ => return None
+        };
+        Some(LevelName(level))
+    }
+
     fn as_str(self) -> &'static str {
         match self.0 {
             LevelFilter::Off => "off",
@@ -273,11 +1848,16 @@ impl From<LevelName> for &'static str {
     }
 }
 
-impl TryFrom<String> for LevelName {
+impl TryFrom<LevelValue> for LevelName {
     type Error = &'static str;
 
-    fn try_from(s: String) -> Result<Self, Self::Error> {
-        Self::from_str(&s)
+    fn try_from(value: LevelValue) -> Result<Self, Self::Error> {
+        match value {
+            LevelValue::Number(value) => {
+                Self::from_verbosity(value).ok_or("invalid log level")
+            }
+            LevelValue::Name(value) => Self::from_str(&value)
+        }
     }
 }
 
@@ -285,11 +1865,25 @@ impl FromStr for LevelName {
     type Err = &'static str;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(value) = u64::from_str(s) {
+            return Self::from_verbosity(value).ok_or("invalid log level")
+        }
         LevelFilter::from_str(s).map(Self).map_err(|_| "invalid log level")
     }
 }
 
 
+//------------ LevelValue ----------------------------------------------------
+
+/// The serialized form of a log level: either a name or a number.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LevelValue {
+    Number(u64),
+    Name(String),
+}
+
+
 //------------ LogPath -------------------------------------------------------
 
 /// A path that is either "-" for stderr or an actual path.
@@ -378,6 +1972,23 @@ pub struct Args {
     #[cfg(unix)]
     #[arg(long, value_name = "FACILITY")]
     syslog_facility: Option<unix::FacilityArg>,
+
+    /// Message format to use for syslog logging
+    #[cfg(unix)]
+    #[arg(long, value_name = "FORMAT")]
+    syslog_format: Option<SyslogFormat>,
+
+    /// How to timestamp messages logged to a file or stderr
+    #[arg(long, value_name = "MODE")]
+    log_timestamps: Option<TimestampMode>,
+
+    /// The format of messages logged to a file or stderr
+    #[arg(long, value_name = "FORMAT")]
+    log_format: Option<LogFormat>,
+
+    /// Whether to color messages logged to stderr
+    #[arg(long, value_name = "WHEN")]
+    log_color: Option<ColorMode>,
 }
 
 impl Args {
@@ -417,6 +2028,14 @@ pub enum Target {
     #[default]
     Default,
 
+    /// Automatically pick the best target for the environment.
+    ///
+    /// If stderr is connected to the systemd journal, logs to `Journal`.
+    /// Otherwise, on Unix in daemon mode, logs to
+    /// `Syslog(Facility::LOG_DAEMON)` if syslog is available. In all other
+    /// cases logs to `Stderr`.
+    Auto,
+
     /// Syslog.
     ///
     /// The argument is the syslog facility to use.
@@ -426,44 +2045,295 @@ pub enum Target {
     /// Stderr.
     Stderr,
 
-    /// A file.
+    /// The systemd journal via stderr.
+    ///
+    /// If stderr is connected to the journal, as indicated by the
+    /// `JOURNAL_STREAM` environment variable, each line is prefixed with
+    /// the priority of the message so the journal can record it.
+    /// Otherwise behaves like `Stderr`.
+    #[cfg(unix)]
+    Journal,
+
+    /// A file.
+    ///
+    /// The argument is the file name.
+    File(PathBuf),
+
+    /// An inherited file descriptor.
+    ///
+    /// Messages are written in the same format as for `File`. The file
+    /// descriptor is owned by the caller and must stay open.
+    #[cfg(unix)]
+    Fd(RawFd),
+}
+
+
+//--- PartialEq and Eq
+
+impl PartialEq for Target {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Default, Self::Default) => true,
+            (Self::Auto, Self::Auto) => true,
+            #[cfg(unix)]
+            (&Self::Syslog(s), &Self::Syslog(o)) => {
+                (s as usize) == (o as usize)
+            }
+            (Self::Stderr, Self::Stderr) => true,
+            #[cfg(unix)]
+            (Self::Journal, Self::Journal) => true,
+            (Self::File(s), Self::File(o)) => {
+                s == o
+            }
+            #[cfg(unix)]
+            (Self::Fd(s), Self::Fd(o)) => s == o,
+            _ => false
+        }
+    }
+}
+
+impl Eq for Target { }
+
+
+//------------ Suppression ---------------------------------------------------
+
+/// Modules whose less important log messages are suppressed.
+///
+/// Each entry is a prefix of the module path. The default contains some
+/// dependencies that are rather chatty.
+#[derive(Clone, Debug)]
+struct Suppression {
+    /// Modules for which only info messages or above are logged.
+    ///
+    /// This only applies if the log level is below debug.
+    below_info: Vec<String>,
+
+    /// Modules for which only error messages are logged.
+    errors_only: Vec<String>,
+}
+
+impl Suppression {
+    /// Returns whether the record should be suppressed.
+    fn is_suppressed(&self, record: &log::Record, level: LevelFilter) -> bool {
+        let module = match record.module_path() {
+            Some(module) => module,
+            None => return false,
+        };
+
+        // log::Level sorts more important first.
+
+        if record.level() > log::Level::Error
+            && Self::matches(&self.errors_only, module)
+        {
+            return true
+        }
+        if level >= log::LevelFilter::Debug {
+            // Don’t filter anything else if we are in debug or trace.
+            return false
+        }
+        record.level() > log::Level::Info
+            && Self::matches(&self.below_info, module)
+    }
+
+    /// Returns whether the module starts with any of the prefixes.
+    fn matches(prefixes: &[String], module: &str) -> bool {
+        prefixes.iter().any(|prefix| module.starts_with(prefix.as_str()))
+    }
+}
+
+impl Default for Suppression {
+    fn default() -> Self {
+        Suppression {
+            below_info: vec![
+                "tokio_reactor".into(), "hyper".into(), "reqwest".into(),
+                "h2".into(),
+            ],
+            errors_only: vec!["rustls".into()],
+        }
+    }
+}
+
+
+//------------ Rotation ------------------------------------------------------
+
+/// Size-based rotation of a log file.
+#[derive(Clone, Copy, Debug)]
+struct Rotation {
+    /// The size in bytes at which the log file is rotated.
+    max_size: u64,
+
+    /// The number of rotated files to keep.
+    ///
+    /// The rotated files have a suffix from `.1` for the most recent one
+    /// up to this number appended to the path of the log file.
+    keep: usize,
+
+    /// The maximum combined size of the rotated files.
+    ///
+    /// If this is exceeded, the oldest rotated files are deleted.
+    max_total_size: Option<u64>,
+}
+
+impl Rotation {
+    /// The number of rotated files kept if nothing else is configured.
+    const DEFAULT_KEEP: usize = 5;
+
+    /// The largest number of rotated files that can be configured.
+    ///
+    /// Since every rotation renames all rotated files, a much larger number
+    /// would make rotation slow.
+    const MAX_KEEP: usize = 1000;
+
+    /// Rotates the log file at `path`.
+    ///
+    /// Moves all existing rotated files up by one, dropping the oldest,
+    /// and then renames the log file itself. If the rotated files then
+    /// exceed the total size limit, the oldest of them are deleted. The
+    /// caller needs to open a new log file afterwards.
+    fn rotate(self, path: &Path) -> Result<(), io::Error> {
+        if self.keep == 0 {
+            return Self::ignore_missing(fs::remove_file(path))
+        }
+        for idx in (1..self.keep).rev() {
+            Self::ignore_missing(fs::rename(
+                Self::rotated_path(path, idx),
+                Self::rotated_path(path, idx + 1)
+            ))?;
+        }
+        Self::ignore_missing(fs::rename(path, Self::rotated_path(path, 1)))?;
+        self.enforce_total_size(path)
+    }
+
+    /// Deletes the oldest rotated files exceeding the total size limit.
+    fn enforce_total_size(self, path: &Path) -> Result<(), io::Error> {
+        let max_total_size = match self.max_total_size {
+            Some(size) => size,
+            None => return Ok(())
+        };
+        let mut total = 0u64;
+        for idx in 1..=self.keep {
+            let rotated = Self::rotated_path(path, idx);
+            let size = match fs::metadata(&rotated) {
+                Ok(meta) => meta.len(),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    continue
+                }
+                Err(err) => return Err(err)
+            };
+            total = total.saturating_add(size);
+            if total > max_total_size {
+                Self::ignore_missing(fs::remove_file(rotated))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the path of the rotated file with the given index.
+    fn rotated_path(path: &Path, idx: usize) -> PathBuf {
+        let mut res = path.as_os_str().to_owned();
+        res.push(format!(".{}", idx));
+        res.into()
+    }
+
+    /// Converts a “not found” error into success.
+    fn ignore_missing(res: Result<(), io::Error>) -> Result<(), io::Error> {
+        match res {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            res => res
+        }
+    }
+}
+
+
+//------------ MemoryBuffer --------------------------------------------------
+
+/// A bounded buffer of recent log messages.
+struct MemoryBuffer {
+    /// The formatted messages, oldest first.
+    messages: VecDeque<String>,
+
+    /// The maximum number of messages.
+    capacity: usize,
+
+    /// How to format the messages.
+    format: FormatOptions,
+}
+
+impl MemoryBuffer {
+    /// The largest number of messages that can be configured.
+    const MAX_CAPACITY: usize = 100_000;
+
+    /// Creates a new, empty buffer.
     ///
-    /// The argument is the file name.
-    File(PathBuf)
-}
+    /// Space for the messages is only allocated as they arrive, so a large
+    /// capacity doesn’t cost anything until it is used.
+    fn new(capacity: usize, format: FormatOptions) -> Self {
+        MemoryBuffer {
+            messages: VecDeque::new(),
+            capacity,
+            format,
+        }
+    }
 
+    /// Takes over the messages of another buffer.
+    ///
+    /// If there are more messages than fit, the oldest are dropped.
+    fn with_messages(mut self, other: Self) -> Self {
+        self.messages = other.messages;
+        self.truncate();
+        self
+    }
 
-//--- PartialEq and Eq
+    /// Adds a record to the buffer, dropping the oldest if necessary.
+    fn push(&mut self, record: &log::Record) {
+        if self.capacity == 0 {
+            return
+        }
+        self.messages.push_back(format_record(record, &self.format));
+        self.truncate();
+    }
 
-impl PartialEq for Target {
-    fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Self::Default, Self::Default) => true,
-            #[cfg(unix)]
-            (&Self::Syslog(s), &Self::Syslog(o)) => {
-                (s as usize) == (o as usize)
-            }
-            (Self::Stderr, Self::Stderr) => true,
-            (Self::File(s), Self::File(o)) => {
-                s == o
-            }
-            _ => false
+    /// Drops the oldest messages until the buffer is within capacity.
+    fn truncate(&mut self) {
+        while self.messages.len() > self.capacity {
+            self.messages.pop_front();
         }
     }
 }
 
-impl Eq for Target { }
-
 
 //------------ Dispatch ------------------------------------------------------
 
 /// Format and write log messages.
 struct Dispatch {
     /// Where to write messages to.
-    target: Mutex<LogBackend>,
+    ///
+    /// Each target has an optional log level overriding `level`.
+    targets: Mutex<Vec<(LogBackend, Option<LevelFilter>)>>,
 
     /// The maximum log level.
-    level: LevelFilter,
+    ///
+    /// This is a `LevelFilter` converted into a `usize` so it can be
+    /// changed when reconfiguring.
+    level: AtomicUsize,
+
+    /// Whether the logger was created for a daemon.
+    daemon: bool,
+
+    /// The application name the logger was created with.
+    app_name: Option<String>,
+
+    /// Have we already reported a poisoned mutex?
+    poison_reported: AtomicBool,
+
+    /// Log levels for specific module path prefixes.
+    module_levels: RwLock<Vec<(String, LevelFilter)>>,
+
+    /// Modules whose less important messages are suppressed.
+    suppress: RwLock<Suppression>,
+
+    /// A buffer of recent messages kept in addition to the target.
+    memory: Mutex<Option<MemoryBuffer>>,
 }
 
 /// The actual target for logging
@@ -473,11 +2343,47 @@ enum LogBackend {
     File {
         file: fs::File,
         path: PathBuf,
+        format: FormatOptions,
+
+        /// The number of bytes in the file so far.
+        ///
+        /// This is only tracked approximately for size-based rotation.
+        written: u64,
+
+        rotation: Option<Rotation>,
+
+        /// Have we already reported a failed rotation?
+        rotation_failed: bool,
     },
     Stderr {
         stderr: io::Stderr,
-        timestamp: bool,
-    }
+        format: FormatOptions,
+        color: bool,
+    },
+    #[cfg(unix)]
+    Journal {
+        stderr: io::Stderr,
+    },
+    #[cfg(unix)]
+    Fd {
+        file: fs::File,
+        fd: RawFd,
+        format: FormatOptions,
+    },
+
+    /// A log file that hasn’t been opened yet.
+    ///
+    /// Formatted messages are kept in `pending` until the file is opened
+    /// by [`LogBackend::activate`].
+    DeferredFile {
+        path: PathBuf,
+        format: FormatOptions,
+        rotation: Option<Rotation>,
+        pending: Vec<String>,
+
+        /// The number of messages dropped because `pending` was full.
+        dropped: usize,
+    },
 }
 
 impl Dispatch {
@@ -485,37 +2391,141 @@ impl Dispatch {
     fn new(
         config: &Logger, daemon: bool,
     ) -> Result<Self, Failed> {
-        let target = match config.target {
+        let targets = config.targets.iter().map(|(target, level)| {
+            Ok((Self::new_target(target, config, daemon)?, *level))
+        }).collect::<Result<_, _>>()?;
+        Ok(Self {
+            targets: Mutex::new(targets),
+            level: AtomicUsize::new(config.level as usize),
+            daemon,
+            app_name: config.app_name.clone(),
+            poison_reported: AtomicBool::new(false),
+            module_levels: RwLock::new(config.module_levels.clone()),
+            suppress: RwLock::new(config.suppress.clone()),
+            memory: Mutex::new(config.memory_buffer.map(|capacity| {
+                MemoryBuffer::new(capacity, config.format)
+            })),
+        })
+    }
+
+    /// Creates the backend for a single target.
+    fn new_target(
+        target: &Target, config: &Logger, daemon: bool,
+    ) -> Result<LogBackend, Failed> {
+        let res = match *target {
             #[cfg(unix)]
             Target::Default => {
                 if daemon { 
                     Self::new_syslog_target(
-                        syslog::Facility::LOG_DAEMON, false,
+                        syslog::Facility::LOG_DAEMON, false, config,
                     )?
                 }
                 else {
-                    Self::new_stderr_target(false)
+                    Self::new_stderr_target(false, config)
                 }
             }
             #[cfg(not(unix))]
             Target::Default => {
-                Self::new_stderr_target(false)
+                Self::new_stderr_target(false, config)
+            }
+            #[cfg(unix)]
+            Target::Auto => {
+                if unix::stderr_is_journal() {
+                    Self::new_journal_target()
+                }
+                else if daemon {
+                    match unix::SyslogLogger::try_unix(
+                        syslog::Facility::LOG_DAEMON,
+                        config.syslog_format,
+                        config.app_name.as_deref(),
+                    ) {
+                        Some(logger) => LogBackend::Syslog(logger),
+                        None => {
+                            Self::new_stderr_target(true, config)
+                        }
+                    }
+                }
+                else {
+                    Self::new_stderr_target(false, config)
+                }
+            }
+            #[cfg(not(unix))]
+            Target::Auto => {
+                Self::new_stderr_target(daemon, config)
             }
             #[cfg(unix)]
             Target::Syslog(facility) => {
-                Self::new_syslog_target(facility, true)?
+                Self::new_syslog_target(facility, true, config)?
             }
             Target::File(ref path) => {
-                Self::new_file_target(path.clone())?
+                Self::new_file_target(path.clone(), config)?
             }
             Target::Stderr => {
-                Self::new_stderr_target(daemon)
+                Self::new_stderr_target(daemon, config)
+            }
+            #[cfg(unix)]
+            Target::Journal => {
+                if unix::stderr_is_journal() {
+                    Self::new_journal_target()
+                }
+                else {
+                    Self::new_stderr_target(daemon, config)
+                }
+            }
+            #[cfg(unix)]
+            Target::Fd(fd) => {
+                Self::new_fd_target(fd, config)?
             }
         };
-        Ok(Self {
-            target: Mutex::new(target),
-            level: config.level,
-        })
+        Ok(res)
+    }
+
+    /// Returns the maximum log level.
+    fn level(&self) -> LevelFilter {
+        match self.level.load(Ordering::Relaxed) {
+            0 => LevelFilter::Off,
+            1 => LevelFilter::Error,
+            2 => LevelFilter::Warn,
+            3 => LevelFilter::Info,
+            4 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    }
+
+    /// Replaces target and level with those of another logger.
+    fn replace(&self, other: Dispatch) {
+        let targets = match other.targets.into_inner() {
+            Ok(targets) => targets,
+            Err(err) => err.into_inner(),
+        };
+        *self.targets() = targets;
+        self.level.store(
+            other.level.load(Ordering::Relaxed), Ordering::Relaxed
+        );
+        let module_levels = match other.module_levels.into_inner() {
+            Ok(levels) => levels,
+            Err(err) => err.into_inner(),
+        };
+        *self.module_levels.write().unwrap_or_else(|err| {
+            err.into_inner()
+        }) = module_levels;
+        let suppress = match other.suppress.into_inner() {
+            Ok(suppress) => suppress,
+            Err(err) => err.into_inner(),
+        };
+        *self.suppress.write().unwrap_or_else(|err| {
+            err.into_inner()
+        }) = suppress;
+        let memory = match other.memory.into_inner() {
+            Ok(memory) => memory,
+            Err(err) => err.into_inner(),
+        };
+        let mut current = self.memory();
+        *current = match (current.take(), memory) {
+            // Keep the messages we already have.
+            (Some(old), Some(new)) => Some(new.with_messages(old)),
+            (_, memory) => memory
+        };
     }
 
     /// Creates a syslog target.
@@ -525,23 +2535,43 @@ impl Dispatch {
     fn new_syslog_target(
         facility: syslog::Facility,
         use_inet: bool,
+        config: &Logger,
     ) -> Result<LogBackend, Failed> {
-        unix::SyslogLogger::new(facility, use_inet).map(LogBackend::Syslog)
+        unix::SyslogLogger::new(
+            facility, config.syslog_format, use_inet,
+            config.app_name.as_deref(),
+        ).map(LogBackend::Syslog)
     }
 
-    fn new_file_target(path: PathBuf) -> Result<LogBackend, Failed> {
+    fn new_file_target(
+        path: PathBuf, config: &Logger,
+    ) -> Result<LogBackend, Failed> {
+        if config.defer_open {
+            return Ok(LogBackend::DeferredFile {
+                path,
+                format: config.format,
+                rotation: config.rotation,
+                pending: Vec::new(),
+                dropped: 0,
+            })
+        }
+        let file = match Self::open_log_file(&path) {
+            Ok(file) => file,
+            Err(err) => {
+                error!(
+                    "Failed to open log file '{}': {}",
+                    path.display(), err
+                );
+                return Err(Failed)
+            }
+        };
         Ok(LogBackend::File {
-            file: match Self::open_log_file(&path) {
-                Ok(file) => file,
-                Err(err) => {
-                    error!(
-                        "Failed to open log file '{}': {}",
-                        path.display(), err
-                    );
-                    return Err(Failed)
-                }
-            },
-            path
+            written: Self::log_file_size(&file),
+            file,
+            path,
+            format: config.format,
+            rotation: config.rotation,
+            rotation_failed: false,
         })
     }
 
@@ -550,66 +2580,377 @@ impl Dispatch {
         fs::OpenOptions::new().create(true).append(true).open(path)
     }
 
+    /// The maximum number of messages kept for a deferred log file.
+    const MAX_PENDING: usize = 10_000;
+
+    /// Returns the current size of an open log file.
+    fn log_file_size(file: &fs::File) -> u64 {
+        file.metadata().map(|meta| meta.len()).unwrap_or(0)
+    }
+
     /// Configures the stderr target.
-    fn new_stderr_target(timestamp: bool) -> LogBackend {
+    ///
+    /// If `timestamp` is `false`, no timestamps are added regardless of
+    /// the configured timestamp mode.
+    fn new_stderr_target(timestamp: bool, config: &Logger) -> LogBackend {
         LogBackend::Stderr {
             stderr: io::stderr(),
-            timestamp,
+            format: FormatOptions {
+                timestamps: if timestamp {
+                    config.format.timestamps
+                }
+                else {
+                    TimestampMode::None
+                },
+                format: config.format.format,
+            },
+            color: config.color.use_color(),
+        }
+    }
+
+    /// Configures the journal target.
+    #[cfg(unix)]
+    fn new_journal_target() -> LogBackend {
+        LogBackend::Journal {
+            stderr: io::stderr(),
+        }
+    }
+
+    /// Configures a target for an inherited file descriptor.
+    ///
+    /// The backend uses a duplicate of `fd`, so the logger can be switched
+    /// to the same descriptor more than once.
+    #[cfg(unix)]
+    fn new_fd_target(
+        fd: RawFd, config: &Logger
+    ) -> Result<LogBackend, Failed> {
+        match unix::dup_fd(fd) {
+            Ok(file) => {
+                Ok(LogBackend::Fd { file, fd, format: config.format })
+            }
+            Err(err) => {
+                error!(
+                    "Failed to use file descriptor {} for logging: {}",
+                    fd, err
+                );
+                Err(Failed)
+            }
         }
     }
 
-    /// Returns a mutex lock for the target
-    fn target(&self) -> MutexGuard<LogBackend> {
-        self.target.lock().expect("poisoned mutex")
+    /// Returns a mutex lock for the targets.
+    fn targets(
+        &self
+    ) -> MutexGuard<'_, Vec<(LogBackend, Option<LevelFilter>)>> {
+        self.targets.lock().unwrap_or_else(|err| {
+            let mut targets = err.into_inner();
+            self.report_poisoned(
+                targets.iter_mut().map(|(target, _)| target)
+            );
+            targets
+        })
+    }
+
+    /// Reports that a mutex has been poisoned.
+    ///
+    /// A mutex is poisoned if a thread panicked while holding it. Since the
+    /// log targets remain usable in this case, we just carry on. But we
+    /// report it once so the incident doesn’t go unnoticed. The report is
+    /// written directly to `targets` since the lock is held.
+    fn report_poisoned<'a>(
+        &self, targets: impl IntoIterator<Item = &'a mut LogBackend>
+    ) {
+        if self.poison_reported.swap(true, Ordering::Relaxed) {
+            return
+        }
+        for target in targets {
+            let _ = target.log(
+                &log::Record::builder()
+                    .level(log::Level::Warn)
+                    .target(module_path!())
+                    .args(format_args!(
+                        "A thread panicked while logging. Continuing anyway."
+                    ))
+                    .build()
+            );
+        }
     }
 
     /// Logs a message.
     ///
     /// This method may exit the whole process if logging fails.
     fn log(&self, record: &log::Record) {
-        if self.should_ignore(record) {
-            return;
+        let module_level = self.module_level(record);
+        let enabled = |level: LevelFilter| {
+            match module_level {
+                Some(level) => record.level() <= level,
+                None => {
+                    record.level() <= level
+                        && !self.should_ignore(record, level)
+                }
+            }
+        };
+
+        let mut targets = self.targets();
+        for (target, level) in targets.iter_mut() {
+            if enabled(level.unwrap_or_else(|| self.level())) {
+                if let Err(err) = target.log(record) {
+                    target.log_failure(err);
+                }
+            }
+        }
+        drop(targets);
+        if enabled(self.level()) {
+            if let Some(memory) = self.memory().as_mut() {
+                memory.push(record)
+            }
+        }
+    }
+
+    /// Returns a mutex lock for the memory buffer.
+    fn memory(&self) -> MutexGuard<'_, Option<MemoryBuffer>> {
+        self.memory.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Returns the messages in the memory buffer.
+    fn recent_logs(&self) -> Vec<String> {
+        match self.memory().as_ref() {
+            Some(memory) => memory.messages.iter().cloned().collect(),
+            None => Vec::new()
+        }
+    }
+
+    /// Flushes the logging backends.
+    fn flush(&self) {
+        for (target, _) in self.targets().iter_mut() {
+            target.flush()
+        }
+    }
+
+    /// Flushes the logging backends before exiting.
+    ///
+    /// Messages of deferred log files that haven’t been opened are
+    /// written to stderr.
+    ///
+    /// Another thread may still be logging while the process exits, so
+    /// targets that are currently locked are skipped rather than waiting
+    /// for them forever.
+    fn flush_at_exit(&self) {
+        if let Some(mut targets) = Self::try_lock(&self.targets) {
+            for (target, _) in targets.iter_mut() {
+                target.flush();
+                target.write_pending_to_stderr();
+            }
+        }
+    }
+
+    /// Tries to lock a mutex without blocking.
+    ///
+    /// Returns `None` if the mutex is currently locked. A poisoned mutex is
+    /// locked anyway.
+    fn try_lock<T>(mutex: &Mutex<T>) -> Option<MutexGuard<'_, T>> {
+        match mutex.try_lock() {
+            Ok(guard) => Some(guard),
+            Err(TryLockError::Poisoned(err)) => Some(err.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+
+    /// Returns the paths of all deferred log files.
+    fn deferred_paths(&self) -> Vec<PathBuf> {
+        let mut res = Vec::new();
+        for (target, _) in self.targets().iter() {
+            if let LogBackend::DeferredFile { path, .. } = target {
+                res.push(path.clone())
+            }
+        }
+        res
+    }
+
+    /// Returns the configured log level for the module of a record.
+    ///
+    /// Uses the entry with the longest module path prefix matching either
+    /// the record’s module or its target. Returns `None` if no entry
+    /// matches.
+    fn module_level(&self, record: &log::Record) -> Option<LevelFilter> {
+        fn matches(path: &str, prefix: &str) -> bool {
+            match path.strip_prefix(prefix) {
+                Some(rest) => rest.is_empty() || rest.starts_with("::"),
+                None => false,
+            }
+        }
+
+        let levels = self.module_levels.read().unwrap_or_else(|err| {
+            err.into_inner()
+        });
+        levels.iter().filter(|(prefix, _)| {
+            matches(record.target(), prefix)
+            || record.module_path().map(|module| {
+                matches(module, prefix)
+            }).unwrap_or(false)
+        }).max_by_key(|(prefix, _)| prefix.len()).map(|(_, level)| *level)
+    }
+
+    /// Determines whether a log record should be ignored.
+    ///
+    /// This is only used if there is no module level configured for the
+    /// record’s module. The configured suppression rules can thus be
+    /// overridden. The `level` is the log level of the target the record
+    /// is logged to.
+    fn should_ignore(&self, record: &log::Record, level: LevelFilter) -> bool {
+        self.suppress.read().unwrap_or_else(|err| {
+            err.into_inner()
+        }).is_suppressed(record, level)
+    }
+
+    /// Rotates the log targets if necessary.
+    ///
+    /// This method exits the whole process when rotating fails.
+    fn rotate(&self) -> Result<(), Failed> {
+        for (target, _) in self.targets().iter_mut() {
+            target.rotate()?;
+        }
+        Ok(())
+    }
+
+    /// Opens all deferred log files.
+    ///
+    /// The path of each file is passed through `adjust` first. This
+    /// happens before acquiring the locks for the targets, so `adjust` can
+    /// log.
+    fn activate(
+        &self,
+        adjust: &mut dyn FnMut(PathBuf) -> Result<PathBuf, String>,
+    ) -> Result<(), Failed> {
+        let mut paths = Vec::new();
+        for path in self.deferred_paths() {
+            match adjust(path.clone()) {
+                Ok(adjusted) => paths.push((path, adjusted)),
+                Err(err) => {
+                    error!("{}", err);
+                    return Err(Failed)
+                }
+            }
         }
 
-        if let Err(err) = self.try_log(record) {
-            self.log_failure(err);
+        let mut res = Ok(());
+        for (target, _) in self.targets().iter_mut() {
+            res = target.activate(&paths);
+            if res.is_err() {
+                break
+            }
         }
+
+        // The locks have been released, so we can log now.
+        res.map_err(|err| {
+            error!("{}", err);
+            Failed
+        })
     }
+}
 
+impl LogBackend {
     /// Tries logging a message and returns an error if there is one.
-    fn try_log(&self, record: &log::Record) -> Result<(), io::Error> {
-        match self.target().deref_mut() {
+    fn log(&mut self, record: &log::Record) -> Result<(), io::Error> {
+        match self {
             #[cfg(unix)]
             LogBackend::Syslog(ref mut logger) => logger.log(record),
-            LogBackend::File { ref mut file, .. } => {
-                writeln!(
-                    file, "[{}] [{}] {}",
-                    format_timestamp(),
-                    record.level(),
-                    record.args()
-                )
+            LogBackend::File {
+                ref mut file, ref path, ref format, ref mut written, rotation,
+                ref mut rotation_failed,
+            } => {
+                let line = format_line(record, format, false);
+                if let Some(rotation) = rotation {
+                    if *written > 0
+                        && *written + line.len() as u64 > rotation.max_size
+                    {
+                        // If rotating fails, we keep writing to the current
+                        // file and try again after another `max_size` bytes.
+                        let res = rotation.rotate(path).and_then(|_| {
+                            Dispatch::open_log_file(path)
+                        });
+                        *written = 0;
+                        match res {
+                            Ok(new_file) => *file = new_file,
+                            Err(err) => {
+                                if !*rotation_failed {
+                                    *rotation_failed = true;
+                                    Self::write_rotation_failure(
+                                        file, format, err
+                                    )?;
+                                }
+                            }
+                        }
+                    }
+                }
+                file.write_all(line.as_bytes())?;
+                *written += line.len() as u64;
+                Ok(())
             }
-            LogBackend::Stderr{ ref mut stderr, timestamp } => {
+            LogBackend::Stderr{ ref mut stderr, ref format, color } => {
+                let line = format_line(record, format, *color);
                 // We never fail when writing to stderr.
-                if *timestamp {
-                    let _ = writeln!(stderr, "[{}] [{}] {}",
-                        format_timestamp(), record.level(), record.args()
-                    );
+                let _ = stderr.write_all(line.as_bytes());
+                Ok(())
+            }
+            #[cfg(unix)]
+            LogBackend::Journal { ref mut stderr } => {
+                // Prefix every line with the priority so the journal
+                // records it correctly. The journal adds timestamps itself.
+                let priority = match record.level() {
+                    log::Level::Error => 3,
+                    log::Level::Warn => 4,
+                    log::Level::Info => 6,
+                    log::Level::Debug | log::Level::Trace => 7,
+                };
+                let msg = record.args().to_string();
+                let mut stderr = stderr.lock();
+                for line in msg.lines() {
+                    let _ = writeln!(stderr, "<{}>{}", priority, line);
+                }
+                Ok(())
+            }
+            #[cfg(unix)]
+            LogBackend::Fd { ref mut file, ref format, .. } => {
+                let line = format_line(record, format, false);
+                file.write_all(line.as_bytes())
+            }
+            LogBackend::DeferredFile {
+                ref format, ref mut pending, ref mut dropped, ..
+            } => {
+                if pending.len() < Dispatch::MAX_PENDING {
+                    pending.push(format_line(record, format, false));
                 }
                 else {
-                    let _ = writeln!(
-                        stderr, "[{}] {}", record.level(), record.args()
-                    );
+                    *dropped += 1;
                 }
                 Ok(())
             }
         }
     }
 
+    /// Writes a warning about a failed log file rotation to `file`.
+    fn write_rotation_failure(
+        file: &mut fs::File, format: &FormatOptions, err: io::Error
+    ) -> Result<(), io::Error> {
+        file.write_all(format_line(
+            &log::Record::builder()
+                .level(log::Level::Warn)
+                .target(module_path!())
+                .args(format_args!(
+                    "Failed to rotate log file: {}. Continuing with the \
+                     current file.",
+                    err
+                ))
+                .build(),
+            format, false
+        ).as_bytes())
+    }
+
     /// Handles an error that happened during logging.
     fn log_failure(&self, err: io::Error) -> ! {
         // We try to write a meaningful message to stderr and then abort.
-        match self.target().deref() {
+        match self {
             #[cfg(unix)]
             LogBackend::Syslog(_) => {
                 eprintln!("Logging to syslog failed: {}. Exiting.", err);
@@ -624,13 +2965,34 @@ impl Dispatch {
             LogBackend::Stderr { ..  } => {
                 // We never fail when writing to stderr.
             }
+            #[cfg(unix)]
+            LogBackend::Journal { .. } => {
+                // We never fail when writing to stderr.
+            }
+            #[cfg(unix)]
+            LogBackend::Fd { fd, .. } => {
+                eprintln!(
+                    "Logging to file descriptor {} failed: {}. Exiting.",
+                    fd, err
+                );
+            }
+            LogBackend::DeferredFile { .. } => {
+                // We never fail when keeping messages.
+            }
         }
-        std::process::exit(1)
+
+        // We are holding the lock on the target, so neither the exit
+        // callbacks nor the flush at exit must log anything. The flush
+        // after running the callbacks skips the locked targets.
+        with_suppressed(|| {
+            crate::process::run_exit_callbacks();
+            std::process::exit(1)
+        })
     }
 
     /// Flushes the logging backend.
-    fn flush(&self) {
-        match self.target().deref_mut() {
+    fn flush(&mut self) {
+        match self {
             #[cfg(unix)]
             LogBackend::Syslog(ref mut logger) => logger.flush(),
             LogBackend::File { ref mut file, .. } => {
@@ -639,51 +3001,54 @@ impl Dispatch {
             LogBackend::Stderr { ref mut stderr, .. } => {
                 let _  = stderr.lock().flush();
             }
+            #[cfg(unix)]
+            LogBackend::Journal { ref mut stderr } => {
+                let _  = stderr.lock().flush();
+            }
+            #[cfg(unix)]
+            LogBackend::Fd { ref mut file, .. } => {
+                let _ = file.flush();
+            }
+            LogBackend::DeferredFile { .. } => { }
         }
     }
 
-    /// Determines whether a log record should be ignored.
+    /// Writes the messages kept for a deferred log file to stderr.
     ///
-    /// This filters out messages by libraries that we don’t really want to
-    /// see.
-    fn should_ignore(&self, record: &log::Record) -> bool {
-        let module = match record.module_path() {
-            Some(module) => module,
-            None => return false,
-        };
-
-        // log::Level sorts more important first.
-
-        if record.level() > log::Level::Error {
-            // From rustls, only log errors.
-            if module.starts_with("rustls") {
-                return true
+    /// This is used if the file is never opened so the messages aren’t
+    /// lost. Does nothing for all other backends.
+    fn write_pending_to_stderr(&mut self) {
+        if let LogBackend::DeferredFile { path, pending, dropped, .. } = self {
+            if pending.is_empty() && *dropped == 0 {
+                return
+            }
+            let mut stderr = io::stderr().lock();
+            let _ = writeln!(
+                stderr, "Log file {} was never opened. Logged messages:",
+                path.display()
+            );
+            for line in pending.drain(..) {
+                let _ = stderr.write_all(line.as_bytes());
+            }
+            if *dropped > 0 {
+                let _ = writeln!(
+                    stderr, "{} further log messages were dropped.", dropped
+                );
+                *dropped = 0;
             }
         }
-        if self.level >= log::LevelFilter::Debug {
-            // Don’t filter anything else if we are in debug or trace.
-            return false
-        }
-
-        // Ignore these modules unless INFO or more important.
-        record.level() > log::Level::Info && (
-               module.starts_with("tokio_reactor")
-            || module.starts_with("hyper")
-            || module.starts_with("reqwest")
-            || module.starts_with("h2")
-        )
     }
 
     /// Rotates the log target if necessary.
     ///
-    /// This method exits the whole process when rotating fails.
-    fn rotate(&self) -> Result<(), Failed> {
+    /// This only does something for a log file which is re-opened.
+    fn rotate(&mut self) -> Result<(), Failed> {
         if let LogBackend::File {
-            ref mut file, ref path
-        } = self.target().deref_mut() {
+            ref mut file, ref path, ref mut written, ..
+        } = self {
             // This tries to open the file. If this fails, it writes a
             // message to both the old file and stderr and then exits.
-            *file = match Self::open_log_file(path) {
+            *file = match Dispatch::open_log_file(path) {
                 Ok(file) => file,
                 Err(err) => {
                     let _ = writeln!(file,
@@ -696,12 +3061,68 @@ impl Dispatch {
                     );
                     return Err(Failed)
                 }
-            }
+            };
+            *written = Dispatch::log_file_size(file);
+        }
+        Ok(())
+    }
+
+    /// Opens a deferred log file.
+    ///
+    /// The file is opened at the adjusted path given for its path in
+    /// `paths`. All pending messages are written to the file. Afterwards,
+    /// the backend is a regular file backend. Does nothing for all other
+    /// backends or if there is no adjusted path.
+    ///
+    /// Returns an error message if anything goes wrong.
+    fn activate(
+        &mut self, paths: &[(PathBuf, PathBuf)],
+    ) -> Result<(), String> {
+        let (path, format, rotation, pending, dropped) = match self {
+            LogBackend::DeferredFile {
+                path, format, rotation, pending, dropped
+            } => (path, *format, *rotation, pending, dropped),
+            _ => return Ok(())
+        };
+        let path = match paths.iter().find(|(orig, _)| orig == path) {
+            Some((_, adjusted)) => adjusted.clone(),
+            None => return Ok(())
+        };
+        let write_err = |err: io::Error| {
+            format!("Failed to write log file '{}': {}", path.display(), err)
+        };
+        let mut file = Dispatch::open_log_file(&path).map_err(|err| {
+            format!("Failed to open log file '{}': {}", path.display(), err)
+        })?;
+        for line in pending.drain(..) {
+            file.write_all(line.as_bytes()).map_err(write_err)?;
+        }
+        let dropped = mem::take(dropped);
+        if dropped > 0 {
+            writeln!(
+                file,
+                "{} log messages were dropped before opening the log file.",
+                dropped
+            ).map_err(write_err)?;
         }
+        *self = LogBackend::File {
+            written: Dispatch::log_file_size(&file),
+            file,
+            path,
+            format,
+            rotation,
+            rotation_failed: false,
+        };
         Ok(())
     }
 }
 
+impl Drop for LogBackend {
+    fn drop(&mut self) {
+        self.write_pending_to_stderr()
+    }
+}
+
 
 //------------ SyslogLogger --------------------------------------------------
 
@@ -715,62 +3136,143 @@ mod unix {
     /// This is essentially [`syslog::BasicLogger`] but that one keeps the
     /// logger behind a mutex – which we already do – and doesn’t return
     /// error – which we do want to see.
-    pub struct SyslogLogger(
-        syslog::Logger<syslog::LoggerBackend, syslog::Formatter3164>
-    );
+    pub struct SyslogLogger(SyslogBackend);
+
+    /// The actual syslog logger for each of the formats.
+    enum SyslogBackend {
+        Rfc3164(
+            syslog::Logger<syslog::LoggerBackend, syslog::Formatter3164>
+        ),
+        Rfc5424(
+            syslog::Logger<syslog::LoggerBackend, syslog::Formatter5424>
+        ),
+    }
 
     impl SyslogLogger {
         /// Creates a new syslog logger.
+        ///
+        /// If `app_name` is given, it is used as the process name.
+        /// Otherwise the name of the current executable or, failing that,
+        /// `"daemon"` is used.
         pub fn new(
             facility: syslog::Facility,
+            format: SyslogFormat,
             use_inet: bool,
+            app_name: Option<&str>,
         ) -> Result<Self, Failed> {
-            let process = std::env::current_exe().ok().and_then(|path|
-                path.file_name()
-                    .and_then(std::ffi::OsStr::to_str)
-                    .map(ToString::to_string)
-            ).unwrap_or_else(|| String::from("routinator"));
-            let formatter = syslog::Formatter3164 {
-                facility,
-                hostname: None,
-                process,
-                pid: std::process::id(),
-            };
+            match Self::connect(facility, format, use_inet, app_name) {
+                Ok(res) => Ok(res),
+                Err(err) => {
+                    error!("Cannot connect to syslog: {}", err);
+                    Err(Failed)
+                }
+            }
+        }
+
+        /// Tries to create a syslog logger using the local Unix socket.
+        ///
+        /// Returns `None` if that fails.
+        pub fn try_unix(
+            facility: syslog::Facility,
+            format: SyslogFormat,
+            app_name: Option<&str>,
+        ) -> Option<Self> {
+            Self::connect(facility, format, false, app_name).ok()
+        }
+
+        /// Connects to syslog using the given format.
+        fn connect(
+            facility: syslog::Facility,
+            format: SyslogFormat,
+            use_inet: bool,
+            app_name: Option<&str>,
+        ) -> Result<Self, syslog::Error> {
+            let process = Self::process_name(app_name);
+            let pid = std::process::id();
+            match format {
+                SyslogFormat::Rfc3164 => {
+                    Self::connect_backend(
+                        syslog::Formatter3164 {
+                            facility, hostname: None, process, pid
+                        },
+                        use_inet
+                    ).map(|logger| Self(SyslogBackend::Rfc3164(logger)))
+                }
+                SyslogFormat::Rfc5424 => {
+                    Self::connect_backend(
+                        syslog::Formatter5424 {
+                            facility, hostname: None, process, pid
+                        },
+                        use_inet
+                    ).map(|logger| Self(SyslogBackend::Rfc5424(logger)))
+                }
+            }
+        }
 
+        /// Connects to syslog with the given formatter.
+        ///
+        /// Tries the local Unix socket first and, if `use_inet` is `true`,
+        /// TCP and UDP on localhost after that.
+        fn connect_backend<F: Clone>(
+            formatter: F,
+            use_inet: bool,
+        ) -> Result<syslog::Logger<syslog::LoggerBackend, F>, syslog::Error> {
             match syslog::unix(formatter.clone()) {
-                Ok(logger) => return Ok(Self(logger)),
+                Ok(logger) => return Ok(logger),
                 Err(err) => {
                     if !use_inet {
-                        error!("Cannot connect to syslog: {}", err);
-                        return Err(Failed)
+                        return Err(err)
                     }
                 }
             }
 
-            let logger = syslog::tcp(
+            syslog::tcp(
                 formatter.clone(), ("127.0.0.1", 601)
             ).or_else(|_| {
                 syslog::udp(formatter, ("127.0.0.1", 0), ("127.0.0.1", 514))
-            });
-            match logger {
-                Ok(logger) => Ok(Self(logger)),
-                Err(err) => {
-                    error!("Cannot connect to syslog: {}", err);
-                    Err(Failed)
+            })
+        }
+
+        /// Determines the process name to use.
+        fn process_name(app_name: Option<&str>) -> String {
+            match app_name {
+                Some(name) => String::from(name),
+                None => {
+                    std::env::current_exe().ok().and_then(|path|
+                        path.file_name()
+                            .and_then(std::ffi::OsStr::to_str)
+                            .map(ToString::to_string)
+                    ).unwrap_or_else(|| String::from("daemon"))
                 }
             }
         }
 
         /// Tries logging.
         pub fn log(&mut self, record: &log::Record) -> Result<(), io::Error> {
-            match record.level() {
-                log::Level::Error => self.0.err(record.args()),
-                log::Level::Warn => self.0.warning(record.args()),
-                log::Level::Info => self.0.info(record.args()),
-                log::Level::Debug => self.0.debug(record.args()),
+            use syslog::{LogFormat, Severity};
+
+            let severity = match record.level() {
+                log::Level::Error => Severity::LOG_ERR,
+                log::Level::Warn => Severity::LOG_WARNING,
+                log::Level::Info => Severity::LOG_INFO,
+                log::Level::Debug => Severity::LOG_DEBUG,
                 log::Level::Trace => {
                     // Syslog doesn’t have trace, use debug instead.
-                    self.0.debug(record.args())
+                    Severity::LOG_DEBUG
+                }
+            };
+            match self.0 {
+                SyslogBackend::Rfc3164(ref mut logger) => {
+                    logger.formatter.format(
+                        &mut logger.backend, severity, record.args()
+                    )
+                }
+                SyslogBackend::Rfc5424(ref mut logger) => {
+                    // We don’t have a message ID or structured data.
+                    logger.formatter.format(
+                        &mut logger.backend, severity,
+                        (0, Default::default(), record.args())
+                    )
                 }
             }.map_err(|err| {
                 match err.0 {
@@ -789,16 +3291,148 @@ mod unix {
         ///
         /// Ignores any errors.
         pub fn flush(&mut self) {
-            let _ = self.0.backend.flush();
+            let _ = match self.0 {
+                SyslogBackend::Rfc3164(ref mut logger) => {
+                    logger.backend.flush()
+                }
+                SyslogBackend::Rfc5424(ref mut logger) => {
+                    logger.backend.flush()
+                }
+            };
+        }
+    }
+
+    /// Returns whether stderr is connected to the systemd journal.
+    ///
+    /// systemd sets the `JOURNAL_STREAM` environment variable to the device
+    /// and inode numbers of the stream it connects stdout and stderr to.
+    /// If these match those of our stderr, it is connected to the journal.
+    pub fn stderr_is_journal() -> bool {
+        use std::os::fd::{AsFd, AsRawFd};
+
+        let var = match std::env::var("JOURNAL_STREAM") {
+            Ok(var) => var,
+            Err(_) => return false,
+        };
+        let (dev, ino) = match var.split_once(':') {
+            Some((dev, ino)) => (dev, ino),
+            None => return false,
+        };
+        let (dev, ino) = match (u64::from_str(dev), u64::from_str(ino)) {
+            (Ok(dev), Ok(ino)) => (dev, ino),
+            _ => return false,
+        };
+        match nix::sys::stat::fstat(io::stderr().as_fd().as_raw_fd()) {
+            #[allow(clippy::useless_conversion)]
+            Ok(stat) => {
+                u64::try_from(stat.st_dev).ok() == Some(dev)
+                    && u64::try_from(stat.st_ino).ok() == Some(ino)
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Sets the close-on-exec flag of an inherited file descriptor.
+    pub fn set_cloexec(fd: RawFd) -> Result<(), Failed> {
+        use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+
+        let res = fcntl(fd, FcntlArg::F_GETFD).and_then(|flags| {
+            let flags = FdFlag::from_bits_truncate(flags) | FdFlag::FD_CLOEXEC;
+            fcntl(fd, FcntlArg::F_SETFD(flags))
+        });
+        if let Err(err) = res {
+            error!(
+                "Failed to use file descriptor {} for logging: {}",
+                fd, err
+            );
+            return Err(Failed)
         }
+        Ok(())
+    }
+
+    /// Duplicates a file descriptor into a file.
+    ///
+    /// The new file descriptor has the close-on-exec flag set.
+    pub fn dup_fd(fd: RawFd) -> Result<fs::File, io::Error> {
+        use std::os::fd::FromRawFd;
+        use nix::fcntl::{fcntl, FcntlArg};
+
+        let fd = fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(0))?;
+        // Safety: fcntl just gave us this new descriptor.
+        Ok(unsafe { fs::File::from_raw_fd(fd) })
     }
 
     /// Helper type to use the facility with a clap parser.
     #[derive(Clone, Copy, Debug, Deserialize, Serialize)]
-    #[serde(try_from = "String", into = "&'static str")]
+    #[serde(try_from = "FacilityValue", into = "&'static str")]
     pub struct FacilityArg(syslog::Facility);
 
     impl FacilityArg {
+        /// Takes the facility from the given key of a config file.
+        ///
+        /// Accepts both a string with a facility name or code and an
+        /// integer facility code.
+        pub fn take_from_config(
+            file: &mut ConfigFile, key: &str
+        ) -> Result<Option<Self>, Failed> {
+            let res = match file.take_value(key)? {
+                Some(toml_edit::Value::String(value)) => {
+                    Self::from_str(value.value()).ok()
+                }
+                Some(toml_edit::Value::Integer(value)) => {
+                    u64::try_from(*value.value()).ok().and_then(
+                        Self::from_code
+                    )
+                }
+                Some(_) => None,
+                None => return Ok(None)
+            };
+            match res {
+                Some(res) => Ok(Some(res)),
+                None => {
+                    error!(
+                        "Failed in config file {}: \
+                         '{}' expected to be a syslog facility name or code.",
+                        file.path().display(), file.section_key(key)
+                    );
+                    Err(Failed)
+                }
+            }
+        }
+
+        /// Converts a numeric syslog facility code into a facility.
+        ///
+        /// The codes are those of RFC 5424, i.e., 0 for kern, 3 for daemon,
+        /// and 16 to 23 for local0 to local7.
+        pub fn from_code(code: u64) -> Option<Self> {
+            use syslog::Facility::*;
+
+            let facility = match code {
+                0 => LOG_KERN,
+                1 => LOG_USER,
+                2 => LOG_MAIL,
+                3 => LOG_DAEMON,
+                4 => LOG_AUTH,
+                5 => LOG_SYSLOG,
+                6 => LOG_LPR,
+                7 => LOG_NEWS,
+                8 => LOG_UUCP,
+                9 => LOG_CRON,
+                10 => LOG_AUTHPRIV,
+                11 => LOG_FTP,
+                16 => LOG_LOCAL0,
+                17 => LOG_LOCAL1,
+                18 => LOG_LOCAL2,
+                19 => LOG_LOCAL3,
+                20 => LOG_LOCAL4,
+                21 => LOG_LOCAL5,
+                22 => LOG_LOCAL6,
+                23 => LOG_LOCAL7,
+                _ => return None
+            };
+            Some(Self(facility))
+        }
+
         pub fn is_default(self) -> bool {
             matches!(self.0, syslog::Facility::LOG_DAEMON)
         }
@@ -855,11 +3489,16 @@ mod unix {
         }
     }
 
-    impl TryFrom<String> for FacilityArg {
+    impl TryFrom<FacilityValue> for FacilityArg {
         type Error = &'static str;
 
-        fn try_from(s: String) -> Result<Self, Self::Error> {
-            Self::from_str(&s)
+        fn try_from(value: FacilityValue) -> Result<Self, Self::Error> {
+            match value {
+                FacilityValue::Code(code) => {
+                    Self::from_code(code).ok_or("invalid syslog facility")
+                }
+                FacilityValue::Name(name) => Self::from_str(&name)
+            }
         }
     }
 
@@ -867,6 +3506,9 @@ mod unix {
         type Err = &'static str;
 
         fn from_str(s: &str) -> Result<Self, Self::Err> {
+            if let Ok(code) = u64::from_str(s) {
+                return Self::from_code(code).ok_or("invalid syslog facility")
+            }
             syslog::Facility::from_str(s).map(Self).map_err(|_| {
                 "invalid syslog facility"
             })
@@ -903,6 +3545,79 @@ mod unix {
             Some(PossibleValue::new(self.as_str()))
         }
     }
+
+    /// The serialized form of a facility: either a name or a code.
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum FacilityValue {
+        Code(u64),
+        Name(String),
+    }
+}
+
+
+//------------ Flushing ------------------------------------------------------
+
+/// Flushes all log output.
+///
+/// When this function returns, all log records emitted before it was
+/// called have been handed to the operating system: log files and stderr
+/// have been flushed and syslog messages have been sent.
+///
+/// On Unix systems, this happens automatically when the process exits
+/// normally once [`Logger::init_logging`] has been called. Exiting via
+/// [`process::exit`][crate::process::exit] flushes the log output after
+/// running the registered exit callbacks on all systems. Otherwise, a
+/// daemon should call this as the very last step of a graceful shutdown,
+/// i.e., after all its workers have stopped and right before the process
+/// exits, so that no log records are lost.
+pub fn flush() {
+    log::Log::flush(&GLOBAL_LOGGER)
+}
+
+/// Flushes the log output when the process exits.
+///
+/// In addition to what [`flush`] does, messages kept for deferred log files
+/// that have never been opened are written to stderr so they aren’t lost.
+pub(crate) fn flush_at_exit() {
+    GLOBAL_LOGGER.flush_at_exit()
+}
+
+
+//------------ Suppressing Logging -------------------------------------------
+
+thread_local!(
+    /// The number of active suppressions for the current thread.
+    static SUPPRESSED: Cell<usize> = const { Cell::new(0) }
+);
+
+/// Runs a closure with all logging suppressed for the current thread.
+///
+/// While `op` runs, nothing logged on the current thread will be output,
+/// regardless of the log level. Logging on other threads is not affected.
+/// Calls can be nested. The previous state is restored once the closure
+/// returns or panics.
+///
+/// This is useful for code that handles secrets and must not log them,
+/// not even accidentally through some library.
+pub fn with_suppressed<T>(op: impl FnOnce() -> T) -> T {
+    /// Restores the previous state when dropped.
+    struct Guard;
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            SUPPRESSED.with(|count| count.set(count.get() - 1))
+        }
+    }
+
+    SUPPRESSED.with(|count| count.set(count.get() + 1));
+    let _guard = Guard;
+    op()
+}
+
+/// Returns whether logging is currently suppressed on this thread.
+fn is_suppressed() -> bool {
+    SUPPRESSED.with(|count| count.get() > 0)
 }
 
 
@@ -927,9 +3642,22 @@ impl GlobalLogger {
     }
 
     /// Switches to the proper logger.
+    ///
+    /// If a logger has been installed already, its target and level are
+    /// replaced by those of `logger`.
     fn switch(&self, logger: Dispatch) {
-        if self.inner.set(logger).is_err() {
-            panic!("Tried to switch logger more than once.")
+        if let Err(logger) = self.inner.set(logger) {
+            if let Some(current) = self.inner.get() {
+                current.replace(logger)
+            }
+        }
+    }
+
+    /// Returns the daemon flag and app name of the installed logger.
+    fn settings(&self) -> (bool, Option<String>) {
+        match self.inner.get() {
+            Some(logger) => (logger.daemon, logger.app_name.clone()),
+            None => (false, None),
         }
     }
 
@@ -940,15 +3668,44 @@ impl GlobalLogger {
             None => Ok(()),
         }
     }
+
+    /// Returns the messages kept in memory.
+    fn recent_logs(&self) -> Vec<String> {
+        match self.inner.get() {
+            Some(logger) => logger.recent_logs(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Opens all deferred log files.
+    fn activate(
+        &self,
+        adjust: &mut dyn FnMut(PathBuf) -> Result<PathBuf, String>,
+    ) -> Result<(), Failed> {
+        match self.inner.get() {
+            Some(logger) => logger.activate(adjust),
+            None => Ok(()),
+        }
+    }
+
+    /// Flushes the logger before exiting.
+    fn flush_at_exit(&self) {
+        if let Some(logger) = self.inner.get() {
+            logger.flush_at_exit()
+        }
+    }
 }
 
 
 impl log::Log for GlobalLogger {
     fn enabled(&self, _: &log::Metadata<'_>) -> bool {
-        true
+        !is_suppressed()
     }
 
     fn log(&self, record: &log::Record<'_>) {
+        if is_suppressed() {
+            return
+        }
         match self.inner.get() {
             Some(logger) => logger.log(record),
             None => {
@@ -968,13 +3725,135 @@ impl log::Log for GlobalLogger {
 }
 
 
-//------------ Formatting dates ----------------------------------------------
+//------------ Formatting records --------------------------------------------
+
+/// Options for formatting a log record.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FormatOptions {
+    /// How to timestamp the record.
+    pub timestamps: TimestampMode,
+
+    /// The format of the record.
+    pub format: LogFormat,
+}
+
+/// Formats a log record the way the file and stderr targets do.
+///
+/// With [`LogFormat::Human`], the record is formatted as
+/// `[timestamp] [level] message`, where the timestamp is left out with
+/// [`TimestampMode::None`]. With [`LogFormat::Json`], the record is
+/// formatted as a JSON object. The returned string does not contain a
+/// trailing line feed.
+pub fn format_record(record: &log::Record, opts: &FormatOptions) -> String {
+    let mut res = String::new();
+    write_record(&mut res, record, opts, false);
+    res
+}
+
+/// Formats a log record as a line including the trailing line feed.
+fn format_line(
+    record: &log::Record, opts: &FormatOptions, color: bool
+) -> String {
+    let mut res = String::new();
+    write_record(&mut res, record, opts, color);
+    res.push('\n');
+    res
+}
+
+/// Appends a formatted log record to `buf`, optionally coloring the level.
+///
+/// Color is only ever used with [`LogFormat::Human`]. No line feed is
+/// added.
+fn write_record(
+    buf: &mut String, record: &log::Record, opts: &FormatOptions,
+    color: bool,
+) {
+    use std::fmt::Write;
+
+    // Writing to a string never fails, so we can ignore the results.
+    let level = LevelToken { level: record.level(), color };
+    match (opts.format, opts.timestamps) {
+        (LogFormat::Json, timestamps) => {
+            buf.push('{');
+            if !matches!(timestamps, TimestampMode::None) {
+                let _ = write!(
+                    buf, "\"timestamp\":\"{}\",",
+                    format_timestamp(timestamps)
+                );
+            }
+            let _ = write!(
+                buf, "\"level\":\"{}\",\"target\":\"{}\",",
+                record.level(), JsonEscape(record.target())
+            );
+            if let Some(path) = record.module_path() {
+                let _ = write!(
+                    buf, "\"module_path\":\"{}\",", JsonEscape(path)
+                );
+            }
+            buf.push_str("\"message\":\"");
+            let _ = write!(JsonEscapeWriter(buf), "{}", record.args());
+            buf.push_str("\"}");
+        }
+        (LogFormat::Human, TimestampMode::None) => {
+            let _ = write!(buf, "{} {}", level, record.args());
+        }
+        (LogFormat::Human, timestamps) => {
+            let _ = write!(
+                buf, "[{}] {} {}",
+                format_timestamp(timestamps), level, record.args()
+            );
+        }
+    }
+}
+
+/// A writer that escapes everything written for use in a JSON string.
+struct JsonEscapeWriter<'a>(&'a mut String);
+
+impl fmt::Write for JsonEscapeWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write!(self.0, "{}", JsonEscape(s))
+    }
+}
+
+/// The bracketed level of a human-readable log message.
+struct LevelToken {
+    /// The level of the message.
+    level: log::Level,
+
+    /// Whether to color the token using ANSI escape sequences.
+    color: bool,
+}
+
+impl fmt::Display for LevelToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.color {
+            return write!(f, "[{}]", self.level)
+        }
+        let code = match self.level {
+            log::Level::Error => "31",
+            log::Level::Warn => "33",
+            log::Level::Info => "32",
+            log::Level::Debug => "34",
+            log::Level::Trace => "35",
+        };
+        write!(f, "\x1b[{}m[{}]\x1b[0m", code, self.level)
+    }
+}
+
 
-pub fn format_timestamp() -> impl fmt::Display {
-    use chrono::Local;
-    use chrono::format::{Item, Numeric, Pad};
+//------------ Formatting dates ----------------------------------------------
 
-    const LOCAL_ISO_DATE: &[Item<'static>] = &[
+/// Returns the current time formatted for a log message.
+///
+/// The time is formatted as an ISO 8601 date and time without a time zone
+/// either in local time or UTC, depending on `mode`. If `mode` is
+/// [`TimestampMode::None`], the result is empty.
+pub fn format_timestamp(mode: TimestampMode) -> impl fmt::Display {
+    use chrono::{Local, Utc};
+    use chrono::format::{DelayedFormat, Item, Numeric, Pad};
+    use std::slice::Iter;
+
+    const ISO_DATE: &[Item<'static>] = &[
         Item::Numeric(Numeric::Year, Pad::Zero),
         Item::Literal("-"),
         Item::Numeric(Numeric::Month, Pad::Zero),
@@ -988,6 +3867,504 @@ pub fn format_timestamp() -> impl fmt::Display {
         Item::Numeric(Numeric::Second, Pad::Zero),
     ];
 
-    Local::now().format_with_items(LOCAL_ISO_DATE.iter())
+    /// The formatted timestamp, if any.
+    struct Timestamp(Option<DelayedFormat<Iter<'static, Item<'static>>>>);
+
+    impl fmt::Display for Timestamp {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self.0 {
+                Some(ref inner) => inner.fmt(f),
+                None => Ok(())
+            }
+        }
+    }
+
+    Timestamp(match mode {
+        TimestampMode::Local => {
+            Some(Local::now().format_with_items(ISO_DATE.iter()))
+        }
+        TimestampMode::Utc => {
+            Some(Utc::now().format_with_items(ISO_DATE.iter()))
+        }
+        TimestampMode::None => None,
+    })
 }
 
+
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Returns a fresh, empty directory for a test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "daemonbase-test-{}-{}", std::process::id(), name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn config(content: &str) -> Result<Config, Failed> {
+        Config::from_config_file(
+            &mut ConfigFile::parse(content, Path::new("/test.conf")).unwrap()
+        )
+    }
+
+    fn log_info(backend: &mut LogBackend, msg: &str) {
+        backend.log(
+            &log::Record::builder()
+                .level(log::Level::Info)
+                .args(format_args!("{}", msg))
+                .build()
+        ).unwrap()
+    }
+
+    /// Runs `op` in a forked child which then exits via `exit(3)`.
+    ///
+    /// Returns the exit code of the child. Because the child exits
+    /// normally, handlers registered with `atexit` are run.
+    #[cfg(unix)]
+    fn exit_code_of_child(op: impl FnOnce()) -> i32 {
+        use nix::sys::wait::{waitpid, WaitStatus};
+        use nix::unistd::{fork, ForkResult};
+
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                // Don’t unwind into the test harness of the child.
+                let res = std::panic::catch_unwind(
+                    std::panic::AssertUnwindSafe(op)
+                );
+                unsafe {
+                    if res.is_err() {
+                        libc::_exit(101)
+                    }
+                    libc::exit(0)
+                }
+            }
+            ForkResult::Parent { child } => {
+                match waitpid(child, None).unwrap() {
+                    WaitStatus::Exited(_, code) => code,
+                    status => panic!("child didn’t exit: {:?}", status),
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn init_logging_exits_cleanly() {
+        assert_eq!(
+            exit_code_of_child(|| {
+                Logger::init_logging().unwrap();
+            }),
+            0
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn deferred_messages_written_at_exit() {
+        use std::os::fd::AsRawFd;
+
+        let dir = test_dir("deferred-exit");
+        let stderr_path = dir.join("stderr");
+        let log_path = dir.join("never.log");
+        let code = exit_code_of_child(|| {
+            let stderr = fs::File::create(&stderr_path).unwrap();
+            nix::unistd::dup2(stderr.as_raw_fd(), 2).unwrap();
+            Logger::init_logging().unwrap();
+            let mut logger = Logger::from_config(&config(&format!(
+                "log = \"file\"\nlog-file = \"{}\"\n",
+                log_path.display()
+            )).unwrap()).unwrap();
+            logger.set_defer_open(true);
+            logger.switch_logging(false).unwrap();
+            log::error!("deferred message");
+        });
+        assert_eq!(code, 0);
+        let stderr = fs::read_to_string(&stderr_path).unwrap();
+        assert!(stderr.contains("was never opened"));
+        assert!(stderr.contains("deferred message"));
+        assert!(!log_path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn auto_color() {
+        let var = |s: &str| Some(OsString::from(s));
+
+        assert!(ColorMode::auto_color(None, None, true));
+        assert!(!ColorMode::auto_color(None, None, false));
+
+        // NO_COLOR disables color regardless of its value.
+        assert!(!ColorMode::auto_color(var("1"), None, true));
+        assert!(!ColorMode::auto_color(var(""), None, true));
+        assert!(!ColorMode::auto_color(var("1"), var("1"), true));
+
+        // CLICOLOR_FORCE enables color unless it is empty or 0.
+        assert!(ColorMode::auto_color(None, var("1"), false));
+        assert!(!ColorMode::auto_color(None, var("0"), false));
+        assert!(!ColorMode::auto_color(None, var(""), false));
+        assert!(ColorMode::auto_color(None, var("0"), true));
+    }
+
+    #[test]
+    fn memory_buffer_drops_oldest() {
+        let mut buffer = MemoryBuffer::new(2, FormatOptions {
+            timestamps: TimestampMode::None,
+            format: LogFormat::Human,
+        });
+        assert_eq!(buffer.messages.capacity(), 0);
+        for msg in ["one", "two", "three"] {
+            buffer.push(
+                &log::Record::builder()
+                    .level(log::Level::Info)
+                    .args(format_args!("{}", msg))
+                    .build()
+            );
+        }
+        assert_eq!(buffer.messages.len(), 2);
+        assert!(buffer.messages[0].ends_with("two"));
+        assert!(buffer.messages[1].ends_with("three"));
+    }
+
+    #[test]
+    fn try_lock_skips_busy() {
+        let mutex = Mutex::new(0);
+        {
+            let _guard = mutex.lock().unwrap();
+            assert!(Dispatch::try_lock(&mutex).is_none());
+        }
+        assert!(Dispatch::try_lock(&mutex).is_some());
+
+        let _ = std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let _guard = mutex.lock().unwrap();
+                panic!("poisoning the mutex");
+            }).join()
+        });
+        assert!(mutex.is_poisoned());
+        assert!(Dispatch::try_lock(&mutex).is_some());
+    }
+
+    #[test]
+    fn activate_deferred_file() {
+        let dir = test_dir("activate-deferred");
+        let path = dir.join("log");
+        let mut backend = LogBackend::DeferredFile {
+            path: PathBuf::from("/nonexistent/log"),
+            format: FormatOptions {
+                timestamps: TimestampMode::None,
+                format: LogFormat::Human,
+            },
+            rotation: None,
+            pending: Vec::new(),
+            dropped: 2,
+        };
+        log_info(&mut backend, "before activating");
+
+        // Without an adjusted path, the file stays deferred.
+        backend.activate(&[]).unwrap();
+        assert!(matches!(backend, LogBackend::DeferredFile { .. }));
+
+        backend.activate(
+            &[(PathBuf::from("/nonexistent/log"), path.clone())]
+        ).unwrap();
+        assert!(matches!(backend, LogBackend::File { .. }));
+        log_info(&mut backend, "after activating");
+
+        let content = fs::read_to_string(&path).unwrap();
+        let before = content.find("before activating").unwrap();
+        let dropped = content.find("2 log messages were dropped").unwrap();
+        let after = content.find("after activating").unwrap();
+        assert!(before < dropped && dropped < after);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn log_target_tables() {
+        let res = config(
+            "[[log]]\n\
+             target = \"stderr\"\n\
+             level = \"debug\"\n\
+             [[log]]\n\
+             target = \"file\"\n\
+             path = \"/var/log/test.log\"\n\
+             level = 0\n\
+             [[log]]\n\
+             target = \"file\"\n\
+             path = \"/var/log/other.log\"\n"
+        ).unwrap();
+        let targets = res.log_targets;
+        assert_eq!(targets.len(), 3);
+        assert!(matches!(targets[0].target, TargetName::Stderr));
+        assert!(targets[0].path.is_none());
+        assert_eq!(targets[0].level.unwrap().0, LevelFilter::Debug);
+        assert!(matches!(targets[1].target, TargetName::File));
+        assert!(matches!(
+            targets[1].path.as_ref(),
+            Some(LogPath::Path(path))
+                if path.as_ref() == Path::new("/var/log/test.log")
+        ));
+        assert_eq!(targets[1].level.unwrap().0, LevelFilter::Error);
+        assert!(targets[2].level.is_none());
+
+        // Inline tables work, too.
+        let res = config(
+            "log = [{ target = \"stderr\" }]\n"
+        ).unwrap();
+        assert!(matches!(res.log_targets[0].target, TargetName::Stderr));
+
+        for content in [
+            // Missing target.
+            "[[log]]\nlevel = \"info\"\n",
+            // Unknown target.
+            "[[log]]\ntarget = \"nowhere\"\n",
+            // File target without path.
+            "[[log]]\ntarget = \"file\"\n",
+            // Path for a target other than file.
+            "[[log]]\ntarget = \"stderr\"\npath = \"/tmp/log\"\n",
+            // Invalid level.
+            "[[log]]\ntarget = \"stderr\"\nlevel = 5\n",
+            // Unknown setting.
+            "[[log]]\ntarget = \"stderr\"\ncolor = true\n",
+            // Duplicate target.
+            "[[log]]\ntarget = \"stderr\"\n\
+             [[log]]\ntarget = \"stderr\"\n",
+        ] {
+            assert!(config(content).is_err(), "{}", content);
+        }
+    }
+
+    #[test]
+    fn error_log_target() {
+        let logger = |content: &str| {
+            Logger::from_config(&config(content).unwrap()).unwrap()
+        };
+        let error_log = Target::File(PathBuf::from("/var/log/error.log"));
+
+        let res = logger(
+            "log = \"stderr\"\n\
+             error-log-file = \"/var/log/error.log\"\n"
+        );
+        assert_eq!(
+            res.targets,
+            [
+                (Target::Stderr, None),
+                (error_log.clone(), Some(LevelFilter::Warn)),
+            ]
+        );
+
+        let res = logger(
+            "error-log-file = \"/var/log/error.log\"\n\
+             error-log-level = \"error\"\n"
+        );
+        assert_eq!(
+            res.targets,
+            [
+                (Target::Default, None),
+                (error_log, Some(LevelFilter::Error)),
+            ]
+        );
+    }
+
+    #[test]
+    fn adjust_paths() {
+        let mut adjust = |path: PathBuf| {
+            path.strip_prefix("/chroot").map(|path| {
+                Path::new("/").join(path)
+            })
+        };
+
+        let mut res = config(
+            "log = \"file\"\n\
+             log-file = \"/chroot/log/daemon.log\"\n\
+             error-log-file = \"/chroot/log/error.log\"\n"
+        ).unwrap();
+        res.adjust_paths(&mut adjust).unwrap();
+        assert_eq!(
+            res.file_paths(),
+            [
+                ("log-file", Path::new("/log/daemon.log")),
+                ("error-log-file", Path::new("/log/error.log")),
+            ]
+        );
+
+        let mut res = config(
+            "log = \"file\"\n\
+             log-file = \"/var/log/daemon.log\"\n"
+        ).unwrap();
+        assert!(res.adjust_paths(&mut adjust).is_err());
+    }
+
+    #[test]
+    fn settings_match_config_file() {
+        let mut content = String::from(
+            "log-level = \"debug\"\n\
+             log = \"file\"\n\
+             log-file = \"/var/log/daemon.log\"\n\
+             error-log-file = \"/var/log/error.log\"\n\
+             error-log-level = \"error\"\n\
+             log-timestamps = \"utc\"\n\
+             log-format = \"json\"\n\
+             log-color = \"never\"\n\
+             log-module-levels = [[\"hyper\", \"warn\"]]\n\
+             log-suppress-below-info = [\"rustls\"]\n\
+             log-errors-only = [\"tokio\"]\n\
+             log-file-max-size = 1000000\n\
+             log-file-keep = 3\n\
+             log-file-max-total-size = 10000000\n\
+             log-memory-buffer = 100\n"
+        );
+        if cfg!(unix) {
+            content.push_str(
+                "syslog-facility = \"local0\"\n\
+                 syslog-format = \"rfc5424\"\n"
+            );
+        }
+        let mut file = ConfigFile::parse(
+            &content, Path::new("/test.conf")
+        ).unwrap();
+        let mut keys: Vec<_> = file.remaining_keys().map(
+            String::from
+        ).collect();
+        let config = Config::from_config_file(&mut file).unwrap();
+        assert!(file.check_exhausted().is_ok());
+
+        let mut logged = Vec::new();
+        config.visit_settings(&mut |key, value| {
+            assert!(value.is_some(), "{} not set", key);
+            logged.push(String::from(key));
+        });
+        keys.sort();
+        logged.sort();
+        assert_eq!(keys, logged);
+    }
+
+    #[test]
+    fn format_json() {
+        let opts = FormatOptions {
+            timestamps: TimestampMode::None, format: LogFormat::Json
+        };
+        assert_eq!(
+            format_record(
+                &log::Record::builder()
+                    .level(log::Level::Info)
+                    .target("daemon")
+                    .module_path(Some("daemon::net"))
+                    .args(format_args!("say \"{}\"\n", "hi"))
+                    .build(),
+                &opts
+            ),
+            "{\"level\":\"INFO\",\"target\":\"daemon\",\
+             \"module_path\":\"daemon::net\",\
+             \"message\":\"say \\\"hi\\\"\\n\"}"
+        );
+
+        let opts = FormatOptions {
+            timestamps: TimestampMode::None, format: LogFormat::Human
+        };
+        assert_eq!(
+            format_line(
+                &log::Record::builder()
+                    .level(log::Level::Warn)
+                    .args(format_args!("careful"))
+                    .build(),
+                &opts, false
+            ),
+            "[WARN] careful\n"
+        );
+    }
+
+    #[test]
+    fn rotate() {
+        let dir = test_dir("rotate");
+        let path = dir.join("log");
+        let rotation = Rotation { max_size: 1, keep: 2, max_total_size: None };
+
+        fs::write(&path, "first").unwrap();
+        rotation.rotate(&path).unwrap();
+        assert!(!path.exists());
+        fs::write(&path, "second").unwrap();
+        rotation.rotate(&path).unwrap();
+        fs::write(&path, "third").unwrap();
+        rotation.rotate(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(Rotation::rotated_path(&path, 1)).unwrap(),
+            "third"
+        );
+        assert_eq!(
+            fs::read_to_string(Rotation::rotated_path(&path, 2)).unwrap(),
+            "second"
+        );
+        assert!(!Rotation::rotated_path(&path, 3).exists());
+
+        // The oldest rotated files exceeding the total size are deleted.
+        let rotation = Rotation { max_total_size: Some(8), ..rotation };
+        fs::write(&path, "fourth").unwrap();
+        rotation.rotate(&path).unwrap();
+        assert!(Rotation::rotated_path(&path, 1).exists());
+        assert!(!Rotation::rotated_path(&path, 2).exists());
+
+        // Without any rotated files to keep, the log file is removed.
+        let rotation = Rotation { keep: 0, ..rotation };
+        fs::write(&path, "fifth").unwrap();
+        rotation.rotate(&path).unwrap();
+        assert!(!path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotation_config() {
+        let res = config(
+            "log-file-max-size = 1024\n\
+             log-file-keep = 3\n\
+             log-file-max-total-size = 4096\n"
+        ).unwrap();
+        assert_eq!(res.log_file_max_size, Some(1024));
+        assert_eq!(res.log_file_keep, Some(3));
+        assert_eq!(res.log_file_max_total_size, Some(4096));
+
+        assert!(config("log-file-max-size = 0\n").is_err());
+        assert!(config("log-file-max-size = -1\n").is_err());
+        assert!(config("log-file-keep = 1000\n").is_ok());
+        assert!(config("log-file-keep = 1001\n").is_err());
+    }
+
+    #[test]
+    fn rotation_failure_keeps_logging() {
+        let dir = test_dir("rotation-failure");
+        let path = dir.join("log");
+
+        // A non-empty directory in place of the rotated file makes
+        // renaming the log file fail.
+        fs::create_dir_all(dir.join("log.1").join("blocker")).unwrap();
+
+        let mut backend = LogBackend::File {
+            file: Dispatch::open_log_file(&path).unwrap(),
+            path: path.clone(),
+            format: FormatOptions {
+                timestamps: TimestampMode::None,
+                format: LogFormat::Human,
+            },
+            written: 0,
+            rotation: Some(Rotation {
+                max_size: 16, keep: 1, max_total_size: None
+            }),
+            rotation_failed: false,
+        };
+        for msg in ["first message", "second message", "third message"] {
+            log_info(&mut backend, msg);
+        }
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.contains("first message"));
+        assert!(content.contains("third message"));
+        assert_eq!(content.matches("Failed to rotate log file").count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,10 +1,455 @@
 //! Process management.
 
 #[cfg(unix)]
-pub use self::unix::{Args, Config, Process};
+pub use self::unix::{
+    Args, Config, EnvSockets, LimitsReport, ListenerHandle, Notify, Process,
+    ResourceLimit, Signals, Watchdog, current_limits, set_keepalive,
+    set_nonblocking, set_recv_buffer_size, set_send_buffer_size,
+    set_tcp_nodelay,
+};
+
+#[cfg(all(unix, feature = "tokio"))]
+pub use self::unix::AsyncSignals;
 
 #[cfg(not(unix))]
-pub use self::noop::{Args, Config, Process};
+pub use self::noop::{
+    Args, Config, EnvSockets, LimitsReport, ListenerHandle, Notify, Process,
+    ResourceLimit, Signals, Watchdog, current_limits,
+};
+
+#[cfg(all(not(unix), feature = "tokio"))]
+pub use self::noop::AsyncSignals;
+
+use std::{fs, thread};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+use log::{debug, error, info, warn};
+use crate::config::is_secret_name;
+use crate::error::Failed;
+
+
+//============ ResourceMonitor ===============================================
+
+/// Periodically logs the resource usage of the process.
+///
+/// The monitor runs in a thread of its own and logs the resident set size
+/// and number of open file descriptors of the process at info level each
+/// time the interval has passed. This currently only works on Linux where
+/// the information is taken from the `/proc` file system. On other systems,
+/// the monitor does nothing.
+///
+/// The value returned by [`spawn`][Self::spawn] serves as a handle to the
+/// monitor. When it is dropped, the monitor thread is stopped and joined.
+pub struct ResourceMonitor {
+    /// The handle of the thread running the monitor.
+    handle: Option<thread::JoinHandle<()>>,
+
+    /// The flag telling the thread to stop.
+    ///
+    /// The condition variable is used to wake up the thread early.
+    stop: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl ResourceMonitor {
+    /// Spawns a new resource monitor logging every `interval`.
+    pub fn spawn(interval: Duration) -> Self {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        if !ResourceUsage::is_supported() {
+            return Self { handle: None, stop }
+        }
+        let thread_stop = stop.clone();
+        let handle = thread::Builder::new().name(
+            "resource-monitor".into()
+        ).spawn(move || {
+            let (flag, cvar) = &*thread_stop;
+            let mut stopped = match flag.lock() {
+                Ok(stopped) => stopped,
+                Err(err) => err.into_inner(),
+            };
+            while !*stopped {
+                ResourceUsage::current().log();
+                stopped = match cvar.wait_timeout(stopped, interval) {
+                    Ok((stopped, _)) => stopped,
+                    Err(err) => err.into_inner().0,
+                };
+            }
+        });
+        match handle {
+            Ok(handle) => Self { handle: Some(handle), stop },
+            Err(err) => {
+                warn!("Failed to start resource monitor: {}", err);
+                Self { handle: None, stop }
+            }
+        }
+    }
+
+    /// Returns whether the monitor is actually running.
+    pub fn is_running(&self) -> bool {
+        self.handle.is_some()
+    }
+}
+
+impl Drop for ResourceMonitor {
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            let (flag, cvar) = &*self.stop;
+            match flag.lock() {
+                Ok(mut stopped) => *stopped = true,
+                Err(err) => *err.into_inner() = true,
+            }
+            cvar.notify_all();
+            let _ = handle.join();
+        }
+    }
+}
+
+
+//------------ ResourceUsage -------------------------------------------------
+
+/// A snapshot of the resource usage of the process.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ResourceUsage {
+    /// The resident set size in bytes, if available.
+    pub rss: Option<u64>,
+
+    /// The number of open file descriptors, if available.
+    pub open_fds: Option<usize>,
+}
+
+impl ResourceUsage {
+    /// Returns whether determining the resource usage is supported.
+    pub fn is_supported() -> bool {
+        cfg!(target_os = "linux")
+    }
+
+    /// Determines the current resource usage.
+    pub fn current() -> Self {
+        Self {
+            rss: Self::read_rss(),
+            open_fds: Self::count_open_fds(),
+        }
+    }
+
+    /// Logs the resource usage at info level.
+    pub fn log(&self) {
+        match (self.rss, self.open_fds) {
+            (Some(rss), Some(fds)) => {
+                info!(
+                    "Resource usage: {} kBytes resident, \
+                     {} open file descriptors.",
+                    rss / 1024, fds
+                );
+            }
+            (Some(rss), None) => {
+                info!("Resource usage: {} kBytes resident.", rss / 1024);
+            }
+            (None, Some(fds)) => {
+                info!("Resource usage: {} open file descriptors.", fds);
+            }
+            (None, None) => { }
+        }
+    }
+
+    /// Reads the resident set size from `/proc/self/statm`.
+    #[cfg(target_os = "linux")]
+    fn read_rss() -> Option<u64> {
+        use std::fs;
+        use nix::unistd::{sysconf, SysconfVar};
+
+        let statm = fs::read_to_string("/proc/self/statm").ok()?;
+        let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+        let page_size = sysconf(SysconfVar::PAGE_SIZE).ok()??;
+        Some(pages * u64::try_from(page_size).ok()?)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn read_rss() -> Option<u64> {
+        None
+    }
+
+    /// Counts the entries in `/proc/self/fd`.
+    #[cfg(target_os = "linux")]
+    fn count_open_fds() -> Option<usize> {
+        use std::fs;
+
+        // Reading the directory opens a file descriptor itself which we
+        // shouldn’t count.
+        Some(fs::read_dir("/proc/self/fd").ok()?.count().saturating_sub(1))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn count_open_fds() -> Option<usize> {
+        None
+    }
+}
+
+
+//============ Cgroups =======================================================
+
+/// Returns the path of the control group the process belongs to.
+///
+/// The path is that of the cgroup’s directory in the unified cgroup v2
+/// hierarchy under `/sys/fs/cgroup`. If the service manager delegated the
+/// cgroup to the process – e.g., via `Delegate=yes` in a systemd unit – the
+/// process can create child cgroups under it via [`create_child_cgroup`].
+///
+/// Returns `None` if the path cannot be determined. This is always the case
+/// on systems other than Linux and if the legacy cgroup v1 hierarchy is
+/// used.
+pub fn current_cgroup() -> Option<PathBuf> {
+    #[cfg(target_os = "linux")]
+    {
+        // In cgroup v2, /proc/self/cgroup contains a single line of the
+        // form "0::<path>".
+        let content = fs::read_to_string("/proc/self/cgroup").ok()?;
+        let path = content.lines().find_map(|line| {
+            line.strip_prefix("0::")
+        })?;
+        let path = path.trim().trim_start_matches('/');
+        let mut res = PathBuf::from("/sys/fs/cgroup");
+        if !path.is_empty() {
+            res.push(path)
+        }
+
+        // If cgroup v2 isn’t mounted at the standard location, we can’t
+        // find the cgroup.
+        res.join("cgroup.procs").is_file().then_some(res)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        None
+    }
+}
+
+/// Creates a child cgroup under the cgroup of the process.
+///
+/// The new cgroup is called `name` and is created under the cgroup
+/// returned by [`current_cgroup`]. If it exists already, it is reused.
+/// Returns the path of the new cgroup which can be passed to
+/// [`move_to_cgroup`].
+///
+/// Note that in cgroup v2, processes can only be placed in leaf cgroups
+/// if controllers are enabled for the children. A supervisor should thus
+/// move itself into a child cgroup, too, before placing workers in their
+/// own child cgroups.
+pub fn create_child_cgroup(name: &str) -> Result<PathBuf, Failed> {
+    let parent = match current_cgroup() {
+        Some(parent) => parent,
+        None => {
+            error!("Fatal: cannot determine the current cgroup.");
+            return Err(Failed)
+        }
+    };
+    if name.is_empty() || name.contains('/') || name.starts_with('.') {
+        error!("Fatal: invalid cgroup name '{}'.", name);
+        return Err(Failed)
+    }
+    let path = parent.join(name);
+    if let Err(err) = fs::create_dir(&path) {
+        if err.kind() != std::io::ErrorKind::AlreadyExists {
+            error!(
+                "Fatal: failed to create cgroup {}: {}",
+                path.display(), err
+            );
+            return Err(Failed)
+        }
+    }
+    Ok(path)
+}
+
+/// Moves the process with the given PID into a cgroup.
+///
+/// The `cgroup` is the path of the cgroup’s directory, e.g., as returned
+/// by [`create_child_cgroup`]. A forked worker can be moved by passing the
+/// PID of the worker.
+pub fn move_to_cgroup(cgroup: &Path, pid: u32) -> Result<(), Failed> {
+    let procs = cgroup.join("cgroup.procs");
+    if let Err(err) = fs::write(&procs, format!("{}\n", pid)) {
+        error!(
+            "Fatal: failed to move process {} into cgroup {}: {}",
+            pid, cgroup.display(), err
+        );
+        return Err(Failed)
+    }
+    Ok(())
+}
+
+
+//============ Signal ========================================================
+
+/// A signal received by the process.
+///
+/// This is returned by [`Signals::wait`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Signal {
+    /// `SIGHUP`, typically a request to re-read config or rotate logs.
+    Hup,
+
+    /// `SIGTERM`, a request to shut down.
+    Term,
+
+    /// `SIGINT`, an interrupt from the terminal.
+    Int,
+
+    /// `SIGUSR1`, for application-specific use.
+    User1,
+
+    /// `SIGUSR2`, for application-specific use.
+    User2,
+}
+
+impl Signal {
+    /// Returns whether the signal asks the daemon to shut down.
+    ///
+    /// This is the case for [`Term`][Self::Term] and [`Int`][Self::Int].
+    pub fn is_shutdown(self) -> bool {
+        matches!(self, Signal::Term | Signal::Int)
+    }
+}
+
+
+//============ Lifecycle Messages ============================================
+
+/// The log target used for the crate’s own lifecycle messages.
+///
+/// Messages the crate logs about the state of the process – such as the
+/// command line it was started with, the effective configuration, and the
+/// privileges it is running with after dropping them – use this target
+/// rather than their module path. Applications using their own logger can
+/// filter on it and the logger provided by [`logging`][crate::logging]
+/// treats it like a module path in the `log-module-levels` setting.
+pub const LIFECYCLE_TARGET: &str = "daemonbase::lifecycle";
+
+
+//============ Invocation ====================================================
+
+/// Logs the command line and environment of the process.
+///
+/// The command line is logged at info level, the environment at debug
+/// level. Values of options and environment variables that look like they
+/// contain secrets are left out.
+fn log_invocation() {
+    let args = redact_args(std::env::args_os());
+    info!(target: LIFECYCLE_TARGET, "Started as: {}", args.join(" "));
+
+    let mut vars: Vec<_> = std::env::vars_os().filter_map(|(key, value)| {
+        let key = key.to_string_lossy().into_owned();
+        if is_secret_name(&key) {
+            None
+        }
+        else {
+            Some((key, value))
+        }
+    }).collect();
+    vars.sort_by(|left, right| left.0.cmp(&right.0));
+    for (key, value) in vars {
+        debug!(
+            target: LIFECYCLE_TARGET, "Environment: {}={:?}", key, value
+        );
+    }
+}
+
+/// Formats command line arguments for logging.
+///
+/// If the name of an option suggests a secret, its value is replaced with
+/// `<redacted>`. This covers both the `--name=value` form and the value
+/// given as the next argument.
+fn redact_args(
+    args: impl IntoIterator<Item = std::ffi::OsString>
+) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut redact_next = false;
+    for arg in args {
+        let lossy = arg.to_string_lossy();
+        let is_option = lossy.starts_with('-');
+        if redact_next && !is_option {
+            res.push("<redacted>".into());
+            redact_next = false;
+            continue
+        }
+        redact_next = false;
+        if !is_option {
+            res.push(format!("{:?}", arg));
+            continue
+        }
+        match lossy.split_once('=') {
+            Some((name, _)) if is_secret_name(name) => {
+                res.push(format!("{:?}", format!("{}=<redacted>", name)));
+            }
+            Some(_) => res.push(format!("{:?}", arg)),
+            None => {
+                redact_next = is_secret_name(&lossy);
+                res.push(format!("{:?}", arg));
+            }
+        }
+    }
+    res
+}
+
+//============ Exit Handling =================================================
+
+/// A cleanup callback registered via [`on_exit`].
+type ExitCallback = Box<dyn FnOnce() + Send>;
+
+/// The cleanup callbacks registered via [`on_exit`].
+static EXIT_CALLBACKS: Mutex<Vec<ExitCallback>> = Mutex::new(Vec::new());
+
+/// Registers a callback to be run before the process exits.
+///
+/// The callbacks are run by [`exit`] in reverse order of registration. All
+/// places where the crate itself terminates the process – such as
+/// [`ExitError::exit`][crate::error::ExitError::exit] or a failure to
+/// write to the log target – go through [`exit`], so the callbacks are run
+/// on these paths, too. They are not run if the process is terminated by a
+/// signal or by calling [`std::process::exit`] directly.
+///
+/// The callbacks are not run when the original process exits after
+/// forking into the background since the daemon continues in the child
+/// process. Worker processes started via `Process::fork_workers` start
+/// with an empty list of callbacks.
+pub fn on_exit(op: impl FnOnce() + Send + 'static) {
+    EXIT_CALLBACKS.lock().unwrap_or_else(
+        |err| err.into_inner()
+    ).push(Box::new(op))
+}
+
+/// Runs all registered cleanup callbacks and exits the process.
+///
+/// The process exits with the given exit code after the log output has
+/// been flushed. See [`on_exit`] for registering callbacks.
+pub fn exit(code: i32) -> ! {
+    run_exit_callbacks();
+    std::process::exit(code)
+}
+
+/// Runs all registered cleanup callbacks and flushes the log output.
+///
+/// Each callback is only ever run once. Callbacks registered while running
+/// the callbacks are run, too. The log output is flushed afterwards so
+/// that anything the callbacks logged isn’t lost. Log targets that are
+/// currently in use, e.g., because we are exiting due to a failure to
+/// write to one of them, are skipped.
+pub(crate) fn run_exit_callbacks() {
+    loop {
+        // Don’t hold the lock while running the callback so it can
+        // register further callbacks.
+        let op = EXIT_CALLBACKS.lock().unwrap_or_else(
+            |err| err.into_inner()
+        ).pop();
+        match op {
+            Some(op) => op(),
+            None => break
+        }
+    }
+    crate::logging::flush_at_exit();
+}
+
+/// Drops all registered cleanup callbacks without running them.
+#[cfg(unix)]
+fn clear_exit_callbacks() {
+    EXIT_CALLBACKS.lock().unwrap_or_else(|err| err.into_inner()).clear()
+}
 
 
 //============ unix ==========================================================
@@ -13,24 +458,51 @@ pub use self::noop::{Args, Config, Process};
 ///
 #[cfg(unix)]
 mod unix {
-    use std::io;
+    use std::{env, fmt, fs, io, mem, thread};
+    use std::io::{Read, Write};
+    use std::collections::HashMap;
+    use std::ffi::OsStr;
     use std::env::set_current_dir;
-    use std::os::fd::{AsFd, AsRawFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::net::{
+        Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6,
+        TcpListener, UdpSocket,
+    };
+    use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::{UnixDatagram, UnixListener, UnixStream};
     use std::os::unix::io::RawFd;
-    use std::path::{Path, PathBuf, StripPrefixError};
+    use std::path::{Component, Path, PathBuf, StripPrefixError};
     use std::str::FromStr;
-    use log::error;
-    use nix::fcntl::{flock, open, FlockArg, OFlag};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+    use log::{error, info, warn};
+    use nix::errno::Errno;
+    use nix::fcntl::{
+        fcntl, flock, open, FcntlArg, FdFlag, FlockArg, OFlag,
+    };
     use nix::sys::stat::Mode;
+    use nix::sys::resource::{getrlimit, setrlimit, Resource};
+    use nix::sys::signal::{signal, SigHandler, SigSet, Signal};
     use nix::sys::stat::umask;
-    use nix::unistd::{Gid, Group, Uid, User};
+    use nix::sys::time::TimeValLike;
+    use nix::time::{clock_gettime, ClockId};
+    use nix::sys::wait::{waitpid, WaitStatus};
+    use nix::unistd::{AccessFlags, ForkResult, Gid, Group, Pid, Uid, User};
     use nix::unistd::{
-        close, chown, chroot, dup2, fork, getpid, setgid, setsid, setuid,
-        write,
+        access, chown, chroot, dup2, fchown, fork, getegid, geteuid,
+        getgid, getpid, getuid, setgid, setsid, setuid, write,
     };
+    #[cfg(target_os = "linux")]
+    use nix::unistd::{setfsgid, setfsuid};
     use serde::{Deserialize, Serialize};
-    use crate::config::{ConfigFile, ConfigPath};
+    use socket2::{Domain, SockRef, Socket, Type};
+    use crate::config::{
+        ConfigFile, ConfigPath, ValidationReport, log_opt_setting, log_setting,
+        parent_dir,
+    };
     use crate::error::Failed;
+    use crate::logging;
 
 
     //-------- Process -------------------------------------------------------
@@ -41,12 +513,123 @@ mod unix {
 
         /// The file descriptor of the pid file if requested.
         pid_file: Option<RawFd>,
+
+        /// Have we changed the file system root already?
+        chrooted: bool,
+
+        /// The hooks to run in the child after forking.
+        fork_hooks: Vec<Box<dyn FnMut() + Send>>,
+
+        /// Keep stderr open until privileges have been dropped?
+        keep_stderr: bool,
+
+        /// /dev/null for redirecting stderr to once privileges are dropped.
+        ///
+        /// This is opened together with the other standard streams since
+        /// /dev/null may not be available after changing root.
+        stderr_pending: Option<OwnedFd>,
+
+        /// The read end of the pipe the original process waits on.
+        ready_reader: Option<OwnedFd>,
+
+        /// The write end of the pipe to signal readiness to the parent.
+        ready_writer: Option<OwnedFd>,
+
+        /// The umask the process had before it was changed during setup.
+        original_umask: Option<u32>,
+
+        /// Don’t log the state of the process after dropping privileges?
+        quiet_lifecycle: bool,
+
+        /// The logging configuration used by the daemon, if known.
+        log_config: Option<logging::Config>,
     }
 
     impl Process {
         /// Creates the process from a config struct.
         pub fn from_config(config: Config) -> Self {
-            Self { config, pid_file: None }
+            Self {
+                config,
+                pid_file: None,
+                chrooted: false,
+                fork_hooks: Vec::new(),
+                keep_stderr: false,
+                stderr_pending: None,
+                ready_reader: None,
+                ready_writer: None,
+                original_umask: None,
+                quiet_lifecycle: false,
+                log_config: None,
+            }
+        }
+
+        /// Sets whether to keep stderr open until the daemon is ready.
+        ///
+        /// Normally, [`setup_daemon`][Self::setup_daemon] redirects all
+        /// three standard streams to `/dev/null` when detaching into the
+        /// background. If `keep` is `true`, stderr is left open until
+        /// [`drop_privileges`][Self::drop_privileges] has completed, so
+        /// that errors during startup still reach the terminal or journal
+        /// the process was started from.
+        ///
+        /// The default is `false`.
+        pub fn keep_stderr_until_ready(&mut self, keep: bool) {
+            self.keep_stderr = keep
+        }
+
+        /// Signals the original process that the daemon is ready.
+        ///
+        /// When forking into the background, the original process waits
+        /// until this method is called before it exits. This happens in
+        /// [`drop_privileges`][Self::drop_privileges] once the PID file has
+        /// been written. If you don’t call that method, you have to call
+        /// this method instead or the original process waits until the
+        /// daemon exits.
+        ///
+        /// Calling the method more than once or when not running in the
+        /// background does nothing.
+        pub fn ready(&mut self) {
+            if let Some(writer) = self.ready_writer.take() {
+                let _ = fs::File::from(writer).write_all(b"ready");
+            }
+        }
+
+        /// Sets whether to suppress the crate’s own lifecycle messages.
+        ///
+        /// Normally, [`drop_privileges`][Self::drop_privileges] logs the
+        /// resulting user and group IDs, the chroot and working
+        /// directories, and the resource limits at info level. If `quiet`
+        /// is `true`, these messages are left out. Lifecycle messages that
+        /// are only logged when explicitly requested, such as via
+        /// [`log_invocation`][Self::log_invocation], are not affected.
+        ///
+        /// All lifecycle messages use the
+        /// [`LIFECYCLE_TARGET`][super::LIFECYCLE_TARGET] log target and can
+        /// thus also be filtered by the logger.
+        ///
+        /// The default is `false`.
+        pub fn quiet_lifecycle(&mut self, quiet: bool) {
+            self.quiet_lifecycle = quiet
+        }
+
+        /// Registers a hook to be run in the child process after forking.
+        ///
+        /// The hooks are run in the order they were registered in the
+        /// child process after each fork performed by
+        /// [`setup_daemon`][Self::setup_daemon] or
+        /// [`fork_workers`][Self::fork_workers]. They can be used to
+        /// re-seed random number generators or reset any other state that
+        /// must not be shared between parent and child or that depends on
+        /// the process ID.
+        pub fn on_fork(&mut self, hook: impl FnMut() + Send + 'static) {
+            self.fork_hooks.push(Box::new(hook))
+        }
+
+        /// Runs all the fork hooks.
+        fn run_fork_hooks(&mut self) {
+            for hook in &mut self.fork_hooks {
+                hook()
+            }
         }
 
         /// Adjusts a path for use after dropping privileges.
@@ -70,6 +653,60 @@ mod unix {
             }
         }
 
+        /// Adjusts a path for use right now.
+        ///
+        /// Unlike [`adjust_path`][Self::adjust_path], this only adjusts the
+        /// path if the file system root has already been changed, either via
+        /// [`enter_chroot`][Self::enter_chroot] or
+        /// [`drop_privileges`][Self::drop_privileges]. Otherwise, the path
+        /// is returned unchanged.
+        pub fn current_path(
+            &self, path: PathBuf
+        ) -> Result<PathBuf, StripPrefixError> {
+            if self.chrooted {
+                self.adjust_path(path)
+            }
+            else {
+                Ok(path)
+            }
+        }
+
+        /// Logs the command line and environment of the process.
+        ///
+        /// The command line arguments are logged at info level, the
+        /// environment variables at debug level. Variables whose name
+        /// suggests that they contain secrets, such as passwords or tokens,
+        /// are left out. Likewise, the values of such options are replaced
+        /// with `<redacted>`.
+        ///
+        /// This should be called after logging has been switched to its
+        /// final target.
+        pub fn log_invocation(&self) {
+            super::log_invocation()
+        }
+
+        /// Logs the effective configuration at debug level.
+        ///
+        /// Both the process configuration and the logging configuration
+        /// given via `log` are logged with one record per setting. Values
+        /// of settings whose name suggests a secret are redacted.
+        ///
+        /// This should be called after logging has been switched to its
+        /// final target.
+        pub fn log_effective_config(&self, log: &logging::Config) {
+            log.log_settings();
+            self.config.log_settings();
+        }
+
+        /// Sets the logging configuration used by the daemon.
+        ///
+        /// If this is set, [`setup_daemon`][Self::setup_daemon] and
+        /// [`validate`][Self::validate] also check that none of the log
+        /// files uses the same path as the PID file or admin socket.
+        pub fn set_log_config(&mut self, log: &logging::Config) {
+            self.log_config = Some(log.clone())
+        }
+
         /// Sets up the process as a daemon.
         ///
         /// If `background` is `true`, the daemon will be set up to run in
@@ -86,12 +723,37 @@ mod unix {
         /// method, it uses the logging facilities for any diagnostic output.
         /// You should therefore have set up your logging system prioir to
         /// calling this method.
+        ///
+        /// When forking into the background, the original process only exits
+        /// once the final process has written its PID file in
+        /// [`drop_privileges`][Self::drop_privileges] or has called
+        /// [`ready`][Self::ready]. If the final process exits before that,
+        /// the original process exits with exit code 1.
+        ///
+        /// When running in the background, the umask is set to 0 and stays
+        /// that way for the rest of the daemon’s life. The previous umask is
+        /// available via [`original_umask`][Self::original_umask]. Use
+        /// [`with_umask`][Self::with_umask] to create files with a more
+        /// restrictive umask.
         pub fn setup_daemon(
             &mut self, background: bool
         ) -> Result<(), Failed> {
+            self.config.check_consistency()?;
+            if let Some(log) = self.log_config.as_ref() {
+                self.config.check_path_collisions(log)?;
+            }
+            self.config.rlimits.apply()?;
+            if let Some(path) = self.config.netns_path() {
+                self.enter_netns(&path)?;
+            }
             self.create_pid_file()?;
+            self.setup_sigpipe()?;
             
             if background {
+                // Create a pipe so the original process can wait for the
+                // final process to have written its PID file.
+                self.create_ready_pipe()?;
+
                 // Fork to detach from terminal.
                 self.perform_fork()?;
 
@@ -105,6 +767,9 @@ mod unix {
                 // acquire a controlling terminal on SVR4.
                 self.perform_fork()?;
 
+                // Tell systemd which process it should be watching now.
+                Notify::from_env().notify_main_pid(None);
+
                 // Change the working directory to either what’s configured
                 // or / (so we don’t block a file system from being umounted).
                 self.change_working_dir(true)?;
@@ -112,7 +777,9 @@ mod unix {
                 // Set umask to 0 -- the mask is used “inverted,” that is,
                 // everything set in the mask is removed from the actual
                 // mode of a created file. Setting it to 0 allows everything.
-                umask(Mode::empty());
+                #[allow(clippy::useless_conversion)]
+                let old_mask = u32::from(umask(Mode::empty()).bits());
+                self.original_umask = Some(old_mask);
 
                 // Redirect the three standard streams to /dev/null.
                 self.redirect_stdio()?;
@@ -121,392 +788,4141 @@ mod unix {
                 self.change_working_dir(false)?;
             }
 
-            // chown_pid_file
+            self.chown_pid_file();
 
             Ok(())
         }
 
+        /// Returns the umask the process had before it was set up.
+        ///
+        /// Returns `None` if [`setup_daemon`][Self::setup_daemon] hasn’t
+        /// changed the umask.
+        pub fn original_umask(&self) -> Option<u32> {
+            self.original_umask
+        }
 
-        /// Drops privileges.
+        /// Runs a closure with the given umask.
         ///
-        /// If requested via the config, this method will drop all potentially
-        /// elevated privileges. This may include loosing root or system
-        /// administrator permissions and change the file system root.
-        pub fn drop_privileges(&mut self) -> Result<(), Failed> {
-            if let Some(path) = self.config.chroot.as_ref() {
-                if let Err(err) = chroot(path.as_path()) {
-                    error!("Fatal: cannot chroot to '{}': {}'",
-                        path.display(), err
-                    );
-                    return Err(Failed)
+        /// The umask is set to `mask` before running `op` and restored to
+        /// its previous value afterwards. Note that the umask applies to the
+        /// whole process, so files created by other threads while `op`
+        /// runs are affected, too.
+        pub fn with_umask<T>(mask: u32, op: impl FnOnce() -> T) -> T {
+            /// Restores the previous umask when dropped.
+            struct Guard(Mode);
+
+            impl Drop for Guard {
+                fn drop(&mut self) {
+                    umask(self.0);
                 }
             }
 
-            if let Some(user) = self.config.user.as_ref() {
-                if let Err(err) = setuid(user.uid) {
+            let _guard = Guard(umask(Mode::from_bits_truncate(mask as _)));
+            op()
+        }
+
+
+        /// Moves the process into a network namespace.
+        ///
+        /// The namespace is given by the `path` of a file referring to it,
+        /// such as `/proc/<pid>/ns/net` or a file in `/var/run/netns`
+        /// created by `ip netns add`. Afterwards, all sockets created by the
+        /// process live in this namespace and it only sees the network
+        /// interfaces present there.
+        ///
+        /// If the `netns` config option is set, this happens during
+        /// [`setup_daemon`][Self::setup_daemon], so any sockets should only
+        /// be bound after that. Entering a network namespace requires the
+        /// `CAP_SYS_ADMIN` capability, so it has to happen before dropping
+        /// privileges. Network namespaces are only available on Linux.
+        pub fn enter_netns(&self, path: &Path) -> Result<(), Failed> {
+            #[cfg(target_os = "linux")]
+            {
+                use nix::sched::{setns, CloneFlags};
+
+                let file = match fs::File::open(path) {
+                    Ok(file) => file,
+                    Err(err) => {
+                        error!(
+                            "Fatal: failed to open network namespace {}: {}",
+                            path.display(), err
+                        );
+                        return Err(Failed)
+                    }
+                };
+                if let Err(err) = setns(&file, CloneFlags::CLONE_NEWNET) {
                     error!(
-                        "Fatal: failed to set user '{}': {}",
-                        user.name, err
+                        "Fatal: failed to enter network namespace {}: {}",
+                        path.display(), err
                     );
                     return Err(Failed)
                 }
+                Ok(())
             }
+            #[cfg(not(target_os = "linux"))]
+            {
+                error!(
+                    "Fatal: cannot enter network namespace {}: \
+                     not supported on this system.",
+                    path.display()
+                );
+                Err(Failed)
+            }
+        }
 
-            if let Some(group) = self.config.group.as_ref() {
-                if let Err(err) = setgid(group.gid) {
-                    error!(
-                        "Fatal: failed to set group '{}': {}",
-                        group.name, err
+        /// Changes the file system root if requested.
+        ///
+        /// Normally, the root is changed as the first step of
+        /// [`drop_privileges`][Self::drop_privileges]. By calling this method
+        /// before that, a daemon can perform work inside the new root while
+        /// still having its original privileges. This results in the
+        /// following sequence of steps:
+        ///
+        /// 1. [`setup_daemon`][Self::setup_daemon]. Afterwards, resources
+        ///    outside the new root can be loaded with elevated privileges,
+        ///    e.g., via [`preopen`][Self::preopen].
+        /// 2. `enter_chroot`. Afterwards, resources inside the new root can
+        ///    be loaded with elevated privileges using paths adjusted via
+        ///    [`current_path`][Self::current_path].
+        /// 3. [`drop_privileges`][Self::drop_privileges] which will not
+        ///    change the root again.
+        ///
+        /// Before changing the root, the method checks that the new root
+        /// directory is neither owned by the user the process will run as
+        /// nor writable by its group or by everyone, since this would
+        /// defeat the purpose of the chroot. If it is, a warning is logged or, if the
+        /// `strict-chroot` option is set, an error is returned.
+        ///
+        /// Calling the method more than once has no further effect.
+        pub fn enter_chroot(&mut self) -> Result<(), Failed> {
+            if self.chrooted {
+                return Ok(())
+            }
+            if let Some(path) = self.config.chroot.as_ref() {
+                self.check_chroot_dir(path)?;
+                if let Err(err) = chroot(path.as_path()) {
+                    error!("Fatal: cannot chroot to '{}': {}'",
+                        path.display(), err
                     );
                     return Err(Failed)
                 }
+                self.chrooted = true;
             }
-
-            self.write_pid_file()?;
-
             Ok(())
         }
 
-        /// Creates the pid file if requested.
-        fn create_pid_file(&mut self) -> Result<(), Failed> {
-            let path = match self.config.working_dir.as_ref() {
-                Some(path) => path,
-                None => return Ok(())
-            };
+        /// Checks that the chroot directory can’t be modified by the daemon.
+        fn check_chroot_dir(&self, path: &Path) -> Result<(), Failed> {
+            use std::os::unix::fs::MetadataExt;
 
-            let fd = match open(
-                path.as_path(),
-                OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_TRUNC,
-                Mode::from_bits_truncate(0o666)
-            ) {
-                Ok(fd) => fd,
+            let meta = match fs::metadata(path) {
+                Ok(meta) => meta,
                 Err(err) => {
-                    error!("Fatal: failed to create PID file {}: {}",
+                    error!(
+                        "Fatal: cannot access chroot directory {}: {}",
                         path.display(), err
                     );
                     return Err(Failed)
                 }
             };
-
-            if self.config.user.is_some() || self.config.group.is_some() {
-                if let Err(err) = chown(
-                    path.as_path(),
-                    self.config.user.as_ref().map(|user| user.uid),
-                    self.config.group.as_ref().map(|group| group.gid),
-                ) {
+            let mode = meta.mode();
+            // The owner can always change the mode, so it doesn’t matter
+            // whether the directory is currently writable by them.
+            let reason = if self.config.user.as_ref().map(
+                |user| user.uid.as_raw() == meta.uid()
+            ).unwrap_or(false) {
+                "is owned by the daemon user"
+            }
+            else if mode & 0o002 != 0 {
+                "is world-writable"
+            }
+            else if mode & 0o020 != 0 && self.config.group.as_ref().map(
+                |group| group.gid.as_raw() == meta.gid()
+            ).unwrap_or(false) {
+                "is writable by the daemon group"
+            }
+            else {
+                return Ok(())
+            };
+            if self.config.strict_chroot.unwrap_or(false) {
+                error!(
+                    "Fatal: chroot directory {} {}.", path.display(), reason
+                );
+                Err(Failed)
+            }
+            else {
+                warn!(
+                    "Chroot directory {} {}. This weakens the protection \
+                     provided by the chroot.",
+                    path.display(), reason
+                );
+                Ok(())
+            }
+        }
+
+        /// Opens files for reading for later use.
+        ///
+        /// Opens all files in `paths` for reading and returns them in the
+        /// same order. Since open files remain usable after the file system
+        /// root or the user has changed, this can be used to access files
+        /// that won’t be reachable after [`drop_privileges`] anymore.
+        ///
+        /// If the file system root has already been changed via
+        /// [`enter_chroot`], the paths are adjusted accordingly. This
+        /// method should therefore be called before that.
+        ///
+        /// If opening any of the files fails, logs an error naming the file
+        /// and returns an error.
+        ///
+        /// [`drop_privileges`]: Self::drop_privileges
+        /// [`enter_chroot`]: Self::enter_chroot
+        pub fn preopen(
+            &self, paths: &[&Path]
+        ) -> Result<Vec<fs::File>, Failed> {
+            paths.iter().map(|path| {
+                let path = match self.current_path(path.to_path_buf()) {
+                    Ok(path) => path,
+                    Err(_) => {
+                        error!(
+                            "Fatal: file {} is outside of chroot.",
+                            path.display()
+                        );
+                        return Err(Failed)
+                    }
+                };
+                fs::File::open(&path).map_err(|err| {
+                    error!(
+                        "Fatal: failed to open {}: {}", path.display(), err
+                    );
+                    Failed
+                })
+            }).collect()
+        }
+
+        /// Checks that the daemon could be set up without actually doing so.
+        ///
+        /// Validates the configuration via [`Config::validate`], checks that
+        /// the log files don’t collide with other paths if the logging
+        /// configuration has been set via
+        /// [`set_log_config`][Self::set_log_config], that the chroot
+        /// directory is suitable, and that all configured paths are
+        /// accessible as the target user via
+        /// [`check_paths_accessible_as_target`]. Logs an error for every
+        /// problem found and returns an error if there was at least one.
+        ///
+        /// This can be used to implement an option that checks the
+        /// configuration and exits without starting the daemon.
+        ///
+        /// [`check_paths_accessible_as_target`]:
+        ///     Self::check_paths_accessible_as_target
+        pub fn validate(&self) -> Result<(), Failed> {
+            let mut report = ValidationReport::new();
+            self.config.validate(&mut report);
+            let mut ok = report.is_ok();
+            for item in report.items() {
+                if !item.passed {
+                    error!("Fatal: {}: {}", item.name, item.message);
+                }
+            }
+            if let Some(log) = self.log_config.as_ref() {
+                ok &= self.config.check_path_collisions(log).is_ok();
+            }
+            if let Some(path) = self.config.chroot.as_ref() {
+                ok &= self.check_chroot_dir(path).is_ok();
+            }
+            ok &= self.check_paths_accessible_as_target().is_ok();
+            if ok { Ok(()) } else { Err(Failed) }
+        }
+
+        /// Checks that configured paths are accessible as the target user.
+        ///
+        /// Checks that the directory of the PID file, the chroot directory,
+        /// and the working directory can be entered and that the directory
+        /// of the admin socket can be written to by the user and group
+        /// the process will run as after
+        /// [`drop_privileges`][Self::drop_privileges]. If a logging
+        /// configuration has been given via
+        /// [`set_log_config`][Self::set_log_config], it also checks that
+        /// all log files can be written to so they can be reopened after
+        /// rotation. The check is based on the permission bits of the paths
+        /// and all their parent directories.
+        ///
+        /// Logs an error for each path that isn’t accessible and returns an
+        /// error if there was at least one such path.
+        pub fn check_paths_accessible_as_target(&self) -> Result<(), Failed> {
+            let mut ok = true;
+            if let Some(path) = self.config.pid_file.as_ref() {
+                if let Some(dir) = parent_dir(path) {
+                    ok &= self.check_path_as_target(dir, false).is_ok();
+                }
+            }
+            if let Some(path) = self.config.chroot.as_ref() {
+                ok &= self.check_path_as_target(path, false).is_ok();
+            }
+            match self.config.expand_working_dir() {
+                Ok(Some(path)) => {
+                    ok &= self.check_path_as_target(&path, false).is_ok();
+                }
+                Ok(None) => { }
+                Err(err) => {
+                    error!("Fatal: {}", err);
+                    ok = false;
+                }
+            }
+            let admin_socket = self.config.admin_socket.as_ref().and_then(
+                AdminSocket::path
+            );
+            if let Some(path) = admin_socket {
+                if let Some(dir) = parent_dir(path) {
+                    ok &= self.check_path_as_target(dir, true).is_ok();
+                }
+            }
+            if let Some(log) = self.log_config.as_ref() {
+                for (_, path) in log.file_paths() {
+                    ok &= self.check_path_as_target(path, true).is_ok();
+                }
+            }
+            if ok { Ok(()) } else { Err(Failed) }
+        }
+
+        /// Checks that a path is accessible as the target user.
+        ///
+        /// All parent directories of `path` need to be searchable. If `path`
+        /// is a directory, it needs to be searchable, too. Otherwise it
+        /// needs to be readable. If `write` is `true`, it also needs to be
+        /// writable. If `path` does not exist but `write` is `true`, its
+        /// parent directory needs to be writable so it can be created.
+        ///
+        /// Logs an error and returns an error if the path is not accessible.
+        pub fn check_path_as_target(
+            &self, path: &Path, write: bool
+        ) -> Result<(), Failed> {
+            let (uid, gids) = self.target_ids();
+            let user = self.config.user.as_ref().map(|user| {
+                user.name.clone()
+            }).unwrap_or_else(|| format!("UID {}", uid));
+
+            let fail = |what: &str| {
+                error!(
+                    "Fatal: {} is not {} for user {}.",
+                    path.display(), what, user
+                );
+                Err(Failed)
+            };
+
+            for dir in path.ancestors().skip(1) {
+                if let Ok(meta) = fs::metadata(dir) {
+                    if !Self::has_access(&meta, uid, &gids, 0o1) {
+                        error!(
+                            "Fatal: directory {} is not searchable for \
+                             user {}, needed for {}.",
+                            dir.display(), user, path.display()
+                        );
+                        return Err(Failed)
+                    }
+                }
+            }
+
+            let want_write = if write { 0o2 } else { 0 };
+            match fs::metadata(path) {
+                Ok(meta) if meta.is_dir() => {
+                    if !Self::has_access(&meta, uid, &gids, 0o1 | want_write) {
+                        return fail("accessible")
+                    }
+                }
+                Ok(meta) => {
+                    if !Self::has_access(&meta, uid, &gids, 0o4 | want_write) {
+                        return fail("accessible")
+                    }
+                }
+                Err(_) if write => {
+                    let parent = parent_dir(path).and_then(|parent| {
+                        fs::metadata(parent).ok()
+                    });
+                    match parent {
+                        Some(meta) if Self::has_access(
+                            &meta, uid, &gids, 0o3
+                        ) => { }
+                        _ => return fail("creatable"),
+                    }
+                }
+                Err(_) => return fail("existing"),
+            }
+            Ok(())
+        }
+
+        /// Returns the user and group IDs the process will run as.
+        fn target_ids(&self) -> (Uid, Vec<Gid>) {
+            let uid = match self.config.user.as_ref() {
+                Some(user) => user.uid,
+                None => Uid::effective(),
+            };
+            let user = User::from_uid(uid).ok().flatten();
+            let gid = match (self.config.group.as_ref(), user.as_ref()) {
+                (Some(group), _) => group.gid,
+                (None, Some(user)) if self.config.user.is_some() => user.gid,
+                _ => Gid::effective(),
+            };
+            #[allow(unused_mut)]
+            let mut gids = vec![gid];
+            #[cfg(target_os = "linux")]
+            if let Some(user) = user {
+                if let Ok(name) = std::ffi::CString::new(user.name) {
+                    if let Ok(list) = nix::unistd::getgrouplist(&name, gid) {
+                        gids.extend(list)
+                    }
+                }
+            }
+            (uid, gids)
+        }
+
+        /// Returns whether the permission bits in `want` are granted.
+        ///
+        /// The bits are given as `0o4` for read, `0o2` for write, and
+        /// `0o1` for execute or search.
+        fn has_access(
+            meta: &fs::Metadata, uid: Uid, gids: &[Gid], want: u32
+        ) -> bool {
+            use std::os::unix::fs::MetadataExt;
+
+            if uid.is_root() {
+                return true
+            }
+            let mode = meta.mode();
+            let granted = if meta.uid() == uid.as_raw() {
+                mode >> 6
+            }
+            else if gids.iter().any(|gid| gid.as_raw() == meta.gid()) {
+                mode >> 3
+            }
+            else {
+                mode
+            };
+            granted & want == want
+        }
+
+        /// Drops privileges.
+        ///
+        /// If requested via the config, this method will drop all potentially
+        /// elevated privileges. This may include loosing root or system
+        /// administrator permissions and change the file system root.
+        ///
+        /// If the file system root has already been changed via
+        /// [`enter_chroot`][Self::enter_chroot], it is not changed again.
+        ///
+        /// If capabilities are configured, they are retained when changing
+        /// the user and then set to exactly the configured ones. This
+        /// requires the `caps` feature and is only available on Linux.
+        ///
+        /// If configured, core dumps are enabled or disabled after the user
+        /// and group have been changed.
+        ///
+        /// Finally, the resulting user and group IDs, the chroot directory,
+        /// and the working directory are logged at info level unless
+        /// disabled via [`quiet_lifecycle`][Self::quiet_lifecycle].
+        ///
+        /// If stderr was kept open because of
+        /// [`keep_stderr_until_ready`][Self::keep_stderr_until_ready], it
+        /// is redirected to `/dev/null` once everything else has succeeded.
+        pub fn drop_privileges(&mut self) -> Result<(), Failed> {
+            self.enter_chroot()?;
+
+            if let Some(gids) = self.config.supplementary_gids() {
+                let res = unsafe {
+                    libc::setgroups(gids.len() as _, gids.as_ptr())
+                };
+                if res < 0 {
                     error!(
-                        "Fatal: failed to change owner of pid file: {}", err
+                        "Fatal: failed to set supplementary groups: {}",
+                        io::Error::last_os_error()
                     );
                     return Err(Failed)
                 }
             }
 
-            if let Err(err) = flock(fd, FlockArg::LockExclusiveNonblock) {
-                error!("Fatal: cannot lock PID file {}: {}",
-                    path.display(), err
+            // The group has to be changed first since we lose the
+            // permission to do so when changing the user.
+            if let Some(group) = self.config.group.as_ref() {
+                if let Err(err) = setgid(group.gid) {
+                    error!(
+                        "Fatal: failed to set group '{}': {}",
+                        group.name, err
+                    );
+                    return Err(Failed)
+                }
+            }
+
+            #[cfg(all(feature = "caps", target_os = "linux"))]
+            if self.config.capabilities.is_some() {
+                capabilities::keep_caps(true)?;
+            }
+
+            if let Some(user) = self.config.user.as_ref() {
+                if let Err(err) = setuid(user.uid) {
+                    error!(
+                        "Fatal: failed to set user '{}': {}",
+                        user.name, err
+                    );
+                    return Err(Failed)
+                }
+            }
+
+            #[cfg(all(feature = "caps", target_os = "linux"))]
+            if let Some(caps) = self.config.capabilities.as_ref() {
+                capabilities::set_caps(caps)?;
+                capabilities::keep_caps(false)?;
+            }
+
+            // Changing user or group resets the dumpable flag on Linux, so
+            // this has to happen afterwards.
+            self.apply_core_dumps()?;
+
+            self.write_pid_file()?;
+            self.ready();
+            if !self.quiet_lifecycle {
+                self.log_privileges();
+            }
+
+            if let Some(dev_null) = self.stderr_pending.take() {
+                Self::redirect_to_dev_null(&dev_null, &[
+                    (io::stderr().as_fd().as_raw_fd(), "stderr")
+                ])?;
+            }
+
+            Ok(())
+        }
+
+        /// Logs the state of the process after dropping privileges.
+        fn log_privileges(&self) {
+            let cwd = match env::current_dir() {
+                Ok(cwd) => cwd.display().to_string(),
+                Err(_) => String::from("<unknown>"),
+            };
+            info!(
+                target: super::LIFECYCLE_TARGET,
+                "Running with uid={} euid={} gid={} egid={} chroot={} cwd={}",
+                getuid(), geteuid(), getgid(), getegid(),
+                match self.config.chroot.as_ref() {
+                    Some(path) if self.chrooted => {
+                        path.display().to_string()
+                    }
+                    _ => String::from("none"),
+                },
+                cwd,
+            );
+            current_limits().log();
+        }
+
+        /// Sets the user ID used for file system access checks.
+        ///
+        /// This changes the identity under which the current thread accesses
+        /// the file system to the user ID `uid` without changing the
+        /// effective user ID otherwise. On success, returns the previous
+        /// file system user ID, which can be used to restore it later.
+        ///
+        /// This is only available on Linux.
+        #[cfg(target_os = "linux")]
+        pub fn set_fsuid(&self, uid: u32) -> Result<u32, Failed> {
+            let uid = Uid::from_raw(uid);
+            let prev = setfsuid(uid);
+
+            // setfsuid(2) doesn’t report errors, so we have to check that
+            // the change actually happened.
+            if setfsuid(uid) != uid {
+                error!("Failed to set file system user ID to {}.", uid);
+                return Err(Failed)
+            }
+            Ok(prev.as_raw())
+        }
+
+        /// Sets the group ID used for file system access checks.
+        ///
+        /// This changes the identity under which the current thread accesses
+        /// the file system to the group ID `gid` without changing the
+        /// effective group ID otherwise. On success, returns the previous
+        /// file system group ID, which can be used to restore it later.
+        ///
+        /// This is only available on Linux.
+        #[cfg(target_os = "linux")]
+        pub fn set_fsgid(&self, gid: u32) -> Result<u32, Failed> {
+            let gid = Gid::from_raw(gid);
+            let prev = setfsgid(gid);
+
+            // setfsgid(2) doesn’t report errors, so we have to check that
+            // the change actually happened.
+            if setfsgid(gid) != gid {
+                error!("Failed to set file system group ID to {}.", gid);
+                return Err(Failed)
+            }
+            Ok(prev.as_raw())
+        }
+
+        /// Sets up the handling of `SIGPIPE`.
+        ///
+        /// By default, `SIGPIPE` is ignored, so writing to a closed pipe or
+        /// socket results in an error rather than killing the process. The
+        /// Rust runtime normally does this already, but we make sure. If
+        /// the `ignore-sigpipe` option is `false`, the default disposition
+        /// is restored instead.
+        fn setup_sigpipe(&self) -> Result<(), Failed> {
+            let handler = if self.config.ignore_sigpipe.unwrap_or(true) {
+                SigHandler::SigIgn
+            }
+            else {
+                SigHandler::SigDfl
+            };
+            // Safety: We don’t install an actual handler function.
+            if let Err(err) = unsafe { signal(Signal::SIGPIPE, handler) } {
+                error!("Fatal: failed to set up SIGPIPE handling: {}", err);
+                return Err(Failed)
+            }
+            Ok(())
+        }
+
+        /// Forks worker processes.
+        ///
+        /// Forks `n` child processes, each of which runs `worker` with its
+        /// index from `0` to `n - 1` as the argument and exits once the
+        /// closure returns. Any file descriptors open at this point, such
+        /// as listener sockets, are inherited by the children.
+        ///
+        /// The calling process becomes the master which waits for its
+        /// children to terminate. If `restart` is `true`, a worker that
+        /// exits with an error or is killed by a signal is restarted after
+        /// a short pause. The method returns once all workers have
+        /// terminated for good.
+        ///
+        /// The method should be called after [`setup_daemon`] and, typically,
+        /// [`drop_privileges`] have been called.
+        ///
+        /// [`setup_daemon`]: Self::setup_daemon
+        /// [`drop_privileges`]: Self::drop_privileges
+        pub fn fork_workers(
+            &mut self,
+            n: usize,
+            restart: bool,
+            mut worker: impl FnMut(usize) -> Result<(), Failed>,
+        ) -> Result<(), Failed> {
+            let mut children = HashMap::new();
+            for idx in 0..n {
+                let pid = self.fork_worker(idx, &mut worker)?;
+                children.insert(pid, idx);
+            }
+
+            while !children.is_empty() {
+                let (pid, crashed) = match waitpid(None, None) {
+                    Ok(WaitStatus::Exited(pid, code)) => (pid, code != 0),
+                    Ok(WaitStatus::Signaled(pid, _, _)) => (pid, true),
+                    Ok(_) => continue,
+                    Err(Errno::EINTR) => continue,
+                    Err(err) => {
+                        error!("Fatal: failed to wait for workers: {}", err);
+                        return Err(Failed)
+                    }
+                };
+                let idx = match children.remove(&pid) {
+                    Some(idx) => idx,
+                    None => continue,
+                };
+                if crashed {
+                    if restart {
+                        warn!("Worker {} (PID {}) crashed. Restarting.",
+                            idx, pid
+                        );
+                        thread::sleep(Duration::from_secs(1));
+                        let pid = self.fork_worker(idx, &mut worker)?;
+                        children.insert(pid, idx);
+                    }
+                    else {
+                        warn!("Worker {} (PID {}) crashed.", idx, pid);
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        /// Forks a single worker process.
+        ///
+        /// Returns the PID of the worker in the parent. Never returns in
+        /// the child.
+        fn fork_worker(
+            &mut self,
+            idx: usize,
+            worker: &mut impl FnMut(usize) -> Result<(), Failed>,
+        ) -> Result<Pid, Failed> {
+            match unsafe { fork() } {
+                Ok(ForkResult::Parent { child }) => Ok(child),
+                Ok(ForkResult::Child) => {
+                    // The exit callbacks belong to the parent.
+                    super::clear_exit_callbacks();
+                    self.run_fork_hooks();
+                    let res = worker(idx);
+                    super::exit(if res.is_ok() { 0 } else { 1 })
+                }
+                Err(err) => {
+                    error!("Fatal: failed to fork worker: {}", err);
+                    Err(Failed)
+                }
+            }
+        }
+
+        /// Creates the pid file if requested.
+        fn create_pid_file(&mut self) -> Result<(), Failed> {
+            let path = match self.config.pid_file.as_ref() {
+                Some(path) => path,
+                None => return Ok(())
+            };
+
+            // We must not truncate the file before we hold the lock or we
+            // would wipe the PID of a running instance.
+            let fd = match open(
+                path.as_path(),
+                OFlag::O_WRONLY | OFlag::O_CREAT,
+                Mode::from_bits_truncate(0o666)
+            ) {
+                Ok(fd) => fd,
+                Err(err) => {
+                    error!("Fatal: failed to create PID file {}: {}",
+                        path.display(), err
+                    );
+                    return Err(Failed)
+                }
+            };
+
+            if self.config.pid_file_lock() {
+                self.lock_pid_file(fd, path)?;
+            }
+            if unsafe { libc::ftruncate(fd, 0) } < 0 {
+                error!(
+                    "Fatal: failed to truncate PID file {}: {}",
+                    path.display(), io::Error::last_os_error()
                 );
                 return Err(Failed)
             }
-            self.pid_file = Some(fd);
-            Ok(())
+            self.pid_file = Some(fd);
+            Ok(())
+        }
+
+        /// Enables or disables core dumps as configured.
+        ///
+        /// If core dumps are disabled, the `RLIMIT_CORE` resource limit is
+        /// set to zero and, on Linux, the process is marked as not
+        /// dumpable. If they are enabled, the soft limit is raised to the
+        /// hard limit and the process is marked as dumpable.
+        fn apply_core_dumps(&self) -> Result<(), Failed> {
+            let enable = match self.config.core_dumps {
+                Some(enable) => enable,
+                None => return Ok(())
+            };
+            let res = if enable {
+                getrlimit(Resource::RLIMIT_CORE).and_then(|(_, hard)| {
+                    setrlimit(Resource::RLIMIT_CORE, hard, hard)
+                })
+            }
+            else {
+                setrlimit(Resource::RLIMIT_CORE, 0, 0)
+            };
+            if let Err(err) = res {
+                error!("Fatal: failed to set core dump limit: {}", err);
+                return Err(Failed)
+            }
+
+            #[cfg(target_os = "linux")]
+            {
+                let dumpable = libc::c_ulong::from(enable);
+                let res = unsafe {
+                    libc::prctl(libc::PR_SET_DUMPABLE, dumpable, 0, 0, 0)
+                };
+                if res < 0 {
+                    error!(
+                        "Fatal: failed to set dumpable flag: {}",
+                        io::Error::last_os_error()
+                    );
+                    return Err(Failed)
+                }
+            }
+
+            Ok(())
+        }
+
+        /// Changes the owner of the PID file to the configured user and group.
+        ///
+        /// This allows the daemon to update and remove the file after
+        /// dropping privileges. Since we use the open descriptor, this also
+        /// works after changing into a chroot. Failing to change the owner
+        /// is not fatal and only logged.
+        fn chown_pid_file(&self) {
+            let fd = match self.pid_file {
+                Some(fd) => fd,
+                None => return
+            };
+            let uid = self.config.user.as_ref().map(|user| user.uid);
+            let gid = self.config.group.as_ref().map(|group| group.gid);
+            if uid.is_none() && gid.is_none() {
+                return
+            }
+            if let Err(err) = fchown(fd, uid, gid) {
+                warn!("Failed to change owner of PID file: {}", err);
+            }
+        }
+
+        /// Locks the PID file.
+        ///
+        /// If the file is locked by someone else, retries until the
+        /// configured wait time has passed. If the wait time is so large
+        /// that the deadline can’t be represented, retries forever.
+        fn lock_pid_file(&self, fd: RawFd, path: &Path) -> Result<(), Failed> {
+            let deadline = Instant::now().checked_add(Duration::from_secs(
+                self.config.pid_file_wait.unwrap_or(0)
+            ));
+            let mut backoff = Duration::from_millis(10);
+            loop {
+                match flock(fd, FlockArg::LockExclusiveNonblock) {
+                    Ok(()) => return Ok(()),
+                    Err(Errno::EWOULDBLOCK) if deadline.map(|deadline| {
+                        Instant::now() < deadline
+                    }).unwrap_or(true) => {
+                        let mut sleep = backoff;
+                        if let Some(deadline) = deadline {
+                            sleep = sleep.min(
+                                deadline.saturating_duration_since(
+                                    Instant::now()
+                                )
+                            );
+                        }
+                        thread::sleep(sleep);
+                        backoff = (backoff * 2).min(Duration::from_secs(1));
+                    }
+                    Err(Errno::EWOULDBLOCK) => {
+                        error!(
+                            "Fatal: cannot lock PID file {}: {}",
+                            path.display(), Self::describe_pid_owner(path)
+                        );
+                        return Err(Failed)
+                    }
+                    Err(err) => {
+                        error!("Fatal: cannot lock PID file {}: {}",
+                            path.display(), err
+                        );
+                        return Err(Failed)
+                    }
+                }
+            }
+        }
+
+        /// Describes the process holding the lock on the PID file.
+        ///
+        /// This is only used for the error message when locking fails, so
+        /// reading the file without holding the lock is fine. The owner may
+        /// exit or still be writing its PID between our lock attempt and
+        /// reading the file, so the result is a hint only.
+        fn describe_pid_owner(path: &Path) -> String {
+            let content = match fs::read_to_string(path) {
+                Ok(content) => content,
+                Err(err) => {
+                    return format!(
+                        "locked by another process, \
+                         but reading it failed: {}", err
+                    )
+                }
+            };
+            let first = content.lines().next().unwrap_or("").trim();
+            if first.is_empty() {
+                return String::from(
+                    "locked by another process, but the file is empty"
+                )
+            }
+            match u32::from_str(first) {
+                Ok(pid) => format!(
+                    "another instance appears to be running as PID {}", pid
+                ),
+                Err(_) => String::from(
+                    "locked by another process, \
+                     but the file contains no valid PID"
+                ),
+            }
+        }
+
+        /// Updates the pid in the pid file after forking.
+        fn write_pid_file(&mut self) -> Result<(), Failed> {
+            if let Some(pid_file) = self.pid_file {
+                let pid = match self.config.pid_file_format() {
+                    PidFileFormat::Bare => format!("{}", getpid()),
+                    PidFileFormat::Extended => {
+                        let start = SystemTime::now().duration_since(
+                            UNIX_EPOCH
+                        ).map(|since| since.as_secs()).unwrap_or(0);
+                        let exe = env::args_os().next().unwrap_or_default();
+                        format!(
+                            "{}\n{}\n{}\n",
+                            getpid(), start, Path::new(&exe).display()
+                        )
+                    }
+                };
+                match write(pid_file, pid.as_bytes()) {
+                    Ok(len) if len == pid.len() => {}
+                    Ok(_) => {
+                        error!(
+                            "Fatal: failed to write PID to PID file: \
+                             short write"
+                        );
+                        return Err(Failed)
+                    }
+                    Err(err) => {
+                        error!(
+                            "Fatal: failed to write PID to PID file: {}", err
+                        );
+                        return Err(Failed)
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        /// Creates the pipe between the original and the final process.
+        ///
+        /// Both ends are close-on-exec so that programs started by the
+        /// daemon don’t keep the original process waiting.
+        fn create_ready_pipe(&mut self) -> Result<(), Failed> {
+            let (reader, writer) = match Self::cloexec_pipe() {
+                Ok(fds) => fds,
+                Err(err) => {
+                    error!("Fatal: failed to create pipe: {}", err);
+                    return Err(Failed)
+                }
+            };
+            self.ready_reader = Some(reader);
+            self.ready_writer = Some(writer);
+            Ok(())
+        }
+
+        /// Creates a pipe with both ends set to close-on-exec.
+        #[cfg(any(
+            target_os = "android", target_os = "dragonfly",
+            target_os = "freebsd", target_os = "illumos",
+            target_os = "linux", target_os = "netbsd",
+            target_os = "openbsd", target_os = "solaris"
+        ))]
+        fn cloexec_pipe() -> Result<(OwnedFd, OwnedFd), Errno> {
+            let (reader, writer) = nix::unistd::pipe2(OFlag::O_CLOEXEC)?;
+
+            // Safety: pipe2 just gave us these descriptors.
+            Ok(unsafe {
+                (OwnedFd::from_raw_fd(reader), OwnedFd::from_raw_fd(writer))
+            })
+        }
+
+        /// Creates a pipe with both ends set to close-on-exec.
+        ///
+        /// There is no `pipe2` on this system, so the flag has to be set
+        /// separately.
+        #[cfg(not(any(
+            target_os = "android", target_os = "dragonfly",
+            target_os = "freebsd", target_os = "illumos",
+            target_os = "linux", target_os = "netbsd",
+            target_os = "openbsd", target_os = "solaris"
+        )))]
+        fn cloexec_pipe() -> Result<(OwnedFd, OwnedFd), Errno> {
+            let (reader, writer) = nix::unistd::pipe()?;
+
+            // Safety: pipe just gave us these descriptors.
+            let res = unsafe {
+                (OwnedFd::from_raw_fd(reader), OwnedFd::from_raw_fd(writer))
+            };
+            for fd in [&res.0, &res.1] {
+                fcntl(
+                    fd.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)
+                )?;
+            }
+            Ok(res)
+        }
+
+        /// Peforms a fork and exits the parent process.
+        ///
+        /// If the parent is the original process, it waits for the final
+        /// process to signal that it has written its PID file before
+        /// exiting. If the final process fails before, the parent exits with
+        /// exit code 1.
+        ///
+        /// The parent does not run the exit callbacks since they are
+        /// inherited by the child which continues as the daemon.
+        fn perform_fork(&mut self) -> Result<(), Failed> {
+            match unsafe { fork() } {
+                Ok(res) => {
+                    if res.is_parent() {
+                        let reader = match self.ready_reader.take() {
+                            Some(reader) => reader,
+                            None => std::process::exit(0)
+                        };
+                        // Drop our write end or we will never see EOF.
+                        self.ready_writer = None;
+                        let mut buf = Vec::new();
+                        let _ = fs::File::from(reader).read_to_end(&mut buf);
+                        std::process::exit(if buf.is_empty() { 1 } else { 0 })
+                    }
+                    self.ready_reader = None;
+                    self.run_fork_hooks();
+                    Ok(())
+                }
+                Err(err) => {
+                    error!("Fatal: failed to detach: {}", err);
+                    Err(Failed)
+                }
+            }
+        }
+
+        /// Binds the admin socket if configured.
+        ///
+        /// If the config contains an admin socket path, removes any stale
+        /// socket file at that path, binds a new Unix stream socket, and
+        /// returns the listener. Otherwise returns `Ok(None)`.
+        ///
+        /// The socket is created with the configured mode, `0o600` by
+        /// default, without ever being accessible more widely in between.
+        /// If an owner or group are configured for the socket, the socket
+        /// file is changed accordingly.
+        ///
+        /// On Linux, the admin socket can also be given as a name prefixed
+        /// with `@` in which case the socket is bound in the abstract
+        /// namespace rather than the file system. Since file system
+        /// permissions don’t apply to such a socket, the configured mode,
+        /// owner, and group are not applied to it. Instead, every accepted
+        /// connection should be checked via
+        /// [`check_admin_peer`][Self::check_admin_peer].
+        ///
+        /// This method should be called after
+        /// [`drop_privileges`][Self::drop_privileges] so that the socket is
+        /// owned by the target user. The configured path is adjusted for a
+        /// changed file system root.
+        pub fn bind_admin_socket(
+            &self
+        ) -> Result<Option<UnixListener>, Failed> {
+            let path = match self.config.admin_socket.as_ref() {
+                Some(AdminSocket::Path(path)) => path,
+                Some(AdminSocket::Abstract(name)) => {
+                    return Self::bind_abstract_admin_socket(name).map(Some)
+                }
+                None => return Ok(None)
+            };
+            let path = match self.adjust_path(path.clone().into()) {
+                Ok(path) => path,
+                Err(_) => {
+                    error!(
+                        "Fatal: admin socket {} is outside of chroot.",
+                        path.display()
+                    );
+                    return Err(Failed)
+                }
+            };
+            let mode = self.config.admin_socket_mode();
+
+            if let Err(err) = fs::remove_file(&path) {
+                if err.kind() != io::ErrorKind::NotFound {
+                    error!(
+                        "Fatal: failed to remove old admin socket {}: {}",
+                        path.display(), err
+                    );
+                    return Err(Failed)
+                }
+            }
+
+            // Restrict the umask while binding so the socket is never more
+            // accessible than it should be.
+            let res = Self::with_umask(0o777 & !mode, || {
+                UnixListener::bind(&path)
+            });
+            let listener = match res {
+                Ok(listener) => listener,
+                Err(err) => {
+                    error!(
+                        "Fatal: failed to bind admin socket {}: {}",
+                        path.display(), err
+                    );
+                    return Err(Failed)
+                }
+            };
+
+            if let Err(err) = fs::set_permissions(
+                &path, fs::Permissions::from_mode(mode)
+            ) {
+                error!(
+                    "Fatal: failed to set mode of admin socket {}: {}",
+                    path.display(), err
+                );
+                return Err(Failed)
+            }
+
+            let owner = self.config.admin_socket_user.as_ref();
+            let group = self.config.admin_socket_group.as_ref();
+            if owner.is_some() || group.is_some() {
+                if let Err(err) = chown(
+                    &path,
+                    owner.map(|user| user.uid),
+                    group.map(|group| group.gid),
+                ) {
+                    error!(
+                        "Fatal: failed to change owner of admin socket {}: {}",
+                        path.display(), err
+                    );
+                    return Err(Failed)
+                }
+            }
+
+            Ok(Some(listener))
+        }
+
+        /// Binds the admin socket in the abstract namespace.
+        #[cfg(target_os = "linux")]
+        fn bind_abstract_admin_socket(
+            name: &str
+        ) -> Result<UnixListener, Failed> {
+            use std::os::linux::net::SocketAddrExt;
+
+            let res = std::os::unix::net::SocketAddr::from_abstract_name(
+                name.as_bytes()
+            ).and_then(|addr| UnixListener::bind_addr(&addr));
+            match res {
+                Ok(listener) => Ok(listener),
+                Err(err) => {
+                    error!(
+                        "Fatal: failed to bind admin socket @{}: {}",
+                        name, err
+                    );
+                    Err(Failed)
+                }
+            }
+        }
+
+        /// Binds the admin socket in the abstract namespace.
+        ///
+        /// The abstract namespace only exists on Linux, so this always
+        /// fails.
+        #[cfg(not(target_os = "linux"))]
+        fn bind_abstract_admin_socket(
+            name: &str
+        ) -> Result<UnixListener, Failed> {
+            error!(
+                "Fatal: abstract admin socket @{} not supported on this \
+                 system.",
+                name
+            );
+            Err(Failed)
+        }
+
+        /// Checks whether the peer of an admin connection is permitted.
+        ///
+        /// Since file system permissions don’t apply to an admin socket in
+        /// the abstract namespace, any process that shares the network
+        /// namespace can connect to it. This method should therefore be
+        /// called for every connection accepted on such a socket. It can
+        /// be used with a socket in the file system as an additional
+        /// safeguard, too.
+        ///
+        /// The user and group of the connecting process are determined via
+        /// the `SO_PEERCRED` socket option. The connection is permitted if
+        /// the user is root or the user the process is running as, or if
+        /// it matches the configured `admin-socket-user` or the group
+        /// matches the configured `admin-socket-group`.
+        ///
+        /// Returns `false` and logs a warning if the connection is not
+        /// permitted or the credentials cannot be determined. The latter is
+        /// always the case on systems other than Linux.
+        pub fn check_admin_peer(&self, stream: &UnixStream) -> bool {
+            let (uid, gid) = match Self::peer_credentials(stream) {
+                Ok(creds) => creds,
+                Err(err) => {
+                    warn!(
+                        "Rejected admin connection: \
+                         cannot determine peer credentials: {}",
+                        err
+                    );
+                    return false
+                }
+            };
+            let permitted = uid.is_root()
+                || uid == geteuid()
+                || self.config.admin_socket_user.as_ref().map(|user| {
+                    user.uid == uid
+                }).unwrap_or(false)
+                || self.config.admin_socket_group.as_ref().map(|group| {
+                    group.gid == gid
+                }).unwrap_or(false);
+            if !permitted {
+                warn!(
+                    "Rejected admin connection from uid={} gid={}.",
+                    uid, gid
+                );
+            }
+            permitted
+        }
+
+        /// Returns the user and group of the peer of a Unix socket.
+        #[cfg(target_os = "linux")]
+        fn peer_credentials(
+            stream: &UnixStream
+        ) -> Result<(Uid, Gid), io::Error> {
+            use nix::sys::socket::{getsockopt, sockopt::PeerCredentials};
+
+            let cred = getsockopt(stream, PeerCredentials)?;
+            Ok((Uid::from_raw(cred.uid()), Gid::from_raw(cred.gid())))
+        }
+
+        /// Returns the user and group of the peer of a Unix socket.
+        ///
+        /// This isn’t supported on this system and always fails.
+        #[cfg(not(target_os = "linux"))]
+        fn peer_credentials(
+            stream: &UnixStream
+        ) -> Result<(Uid, Gid), io::Error> {
+            let _ = stream;
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "not supported on this system"
+            ))
+        }
+
+        /// Changes the current working directory in necessary.
+        fn change_working_dir(&self, background: bool) -> Result<(), Failed> {
+            let working_dir = match self.config.expand_working_dir() {
+                Ok(path) => path,
+                Err(err) => {
+                    error!("Fatal: {}", err);
+                    return Err(Failed)
+                }
+            };
+            let mut path = working_dir.as_deref().or(
+                self.config.chroot.as_ref().map(ConfigPath::as_path)
+            );
+            if background {
+                path = path.or(Some(Path::new("/")));
+            }
+            if let Some(path) = path {
+                if let Err(err) = set_current_dir(path) {
+                    error!("Fatal: failed to set working directory {}: {}",
+                        path.display(), err
+                    );
+                    return Err(Failed)
+                }
+            }
+
+            Ok(())
+        }
+
+        /// Changes the stdio streams to /dev/null.
+        fn redirect_stdio(&mut self) -> Result<(), Failed> {
+            let mut fds = vec![
+                (io::stdin().as_fd().as_raw_fd(), "stdin"),
+                (io::stdout().as_fd().as_raw_fd(), "stdout"),
+            ];
+            if !self.keep_stderr {
+                fds.push((io::stderr().as_fd().as_raw_fd(), "stderr"));
+            }
+            let dev_null = Self::open_dev_null()?;
+            Self::redirect_to_dev_null(&dev_null, &fds)?;
+            if self.keep_stderr {
+                self.stderr_pending = Some(dev_null);
+            }
+            Ok(())
+        }
+
+        /// Opens /dev/null for redirecting file descriptors to it.
+        fn open_dev_null() -> Result<OwnedFd, Failed> {
+            match open(
+                "/dev/null", OFlag::O_RDWR | OFlag::O_CLOEXEC,
+                Mode::empty()
+            ) {
+                // Safety: open just gave us this descriptor.
+                Ok(fd) => Ok(unsafe { OwnedFd::from_raw_fd(fd) }),
+                Err(err) => {
+                    error!("Fatal: failed to open /dev/null: {}", err);
+                    Err(Failed)
+                }
+            }
+        }
+
+        /// Redirects the given file descriptors to /dev/null.
+        ///
+        /// The descriptors are given together with a name for use in error
+        /// messages.
+        fn redirect_to_dev_null(
+            dev_null: &OwnedFd, fds: &[(RawFd, &str)]
+        ) -> Result<(), Failed> {
+            for &(fd, name) in fds {
+                if let Err(err) = dup2(dev_null.as_raw_fd(), fd) {
+                    error!(
+                        "Fatal: failed to redirect {} to /dev/null: {}",
+                        name, err
+                    );
+                    return Err(Failed)
+                }
+            }
+            Ok(())
+        }
+    }
+
+
+    //-------- Notify --------------------------------------------------------
+
+    /// Sending notifications to the service manager.
+    ///
+    /// This implements the client side of systemd’s notification protocol.
+    /// If the process was started with the `NOTIFY_SOCKET` environment
+    /// variable set, notifications are sent as datagrams to the Unix socket
+    /// given in the variable. Both socket paths and abstract socket names
+    /// starting with `@` are supported. If the variable is not set, all
+    /// notifications are silently ignored.
+    ///
+    /// Failing to deliver a notification is not considered fatal. Instead,
+    /// a warning is logged and the process carries on.
+    #[derive(Clone, Debug, Default)]
+    pub struct Notify {
+        /// The address of the notification socket if there is one.
+        socket: Option<NotifySocket>,
+    }
+
+    /// The address of the notification socket.
+    #[derive(Clone, Debug)]
+    enum NotifySocket {
+        /// A socket in the file system.
+        Path(PathBuf),
+
+        /// A socket in the abstract namespace.
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        Abstract(Vec<u8>),
+    }
+
+    impl Notify {
+        /// Creates a value from the `NOTIFY_SOCKET` environment variable.
+        pub fn from_env() -> Self {
+            let var = match env::var_os("NOTIFY_SOCKET") {
+                Some(var) if !var.is_empty() => var,
+                _ => return Self::default(),
+            };
+            let bytes = var.as_bytes();
+            if let Some(name) = bytes.strip_prefix(b"@") {
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                return Self {
+                    socket: Some(NotifySocket::Abstract(name.into()))
+                };
+
+                #[cfg(not(any(target_os = "linux", target_os = "android")))]
+                {
+                    let _ = name;
+                    warn!(
+                        "Ignoring abstract notify socket not supported \
+                         on this system."
+                    );
+                    return Self::default()
+                }
+            }
+            Self { socket: Some(NotifySocket::Path(var.into())) }
+        }
+
+        /// Returns whether notifications are actually sent.
+        pub fn is_enabled(&self) -> bool {
+            self.socket.is_some()
+        }
+
+        /// Notifies the service manager that the service is ready.
+        pub fn notify_ready(&self) {
+            self.send("READY=1")
+        }
+
+        /// Notifies the service manager that the service is reloading.
+        ///
+        /// Once reloading has finished, the service should call
+        /// [`notify_ready`][Self::notify_ready] again.
+        pub fn notify_reloading(&self) {
+            match clock_gettime(ClockId::CLOCK_MONOTONIC) {
+                Ok(now) => {
+                    self.send(&format!(
+                        "RELOADING=1\nMONOTONIC_USEC={}",
+                        now.num_microseconds()
+                    ))
+                }
+                Err(_) => self.send("RELOADING=1")
+            }
+        }
+
+        /// Notifies the service manager that the service is stopping.
+        pub fn notify_stopping(&self) {
+            self.send("STOPPING=1")
+        }
+
+        /// Sends a free-form status message to the service manager.
+        ///
+        /// Since the message must be a single line, any line breaks are
+        /// replaced by spaces.
+        pub fn notify_status(&self, status: &str) {
+            self.send(&format!("STATUS={}", status.replace('\n', " ")))
+        }
+
+        /// Notifies the service manager of the main PID of the service.
+        ///
+        /// If `pid` is `None`, the PID of the current process is used.
+        pub fn notify_main_pid(&self, pid: Option<u32>) {
+            self.send(&format!(
+                "MAINPID={}", pid.unwrap_or_else(std::process::id)
+            ))
+        }
+
+        /// Sends a notification message.
+        ///
+        /// Logs a warning if sending fails.
+        fn send(&self, msg: &str) {
+            let socket = match self.socket.as_ref() {
+                Some(socket) => socket,
+                None => return,
+            };
+            if let Err(err) = Self::try_send(socket, msg) {
+                warn!("Failed to send notification to service manager: {}",
+                    err
+                );
+            }
+        }
+
+        /// Tries to send a notification message.
+        fn try_send(
+            socket: &NotifySocket, msg: &str
+        ) -> Result<(), io::Error> {
+            let sock = UnixDatagram::unbound()?;
+            let len = match socket {
+                NotifySocket::Path(ref path) => {
+                    sock.send_to(msg.as_bytes(), path)?
+                }
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                NotifySocket::Abstract(ref name) => {
+                    use std::os::linux::net::SocketAddrExt;
+                    use std::os::unix::net::SocketAddr;
+
+                    sock.send_to_addr(
+                        msg.as_bytes(), &SocketAddr::from_abstract_name(name)?
+                    )?
+                }
+            };
+            if len != msg.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other, "short write"
+                ))
+            }
+            Ok(())
+        }
+    }
+
+
+    //-------- EnvSockets ----------------------------------------------------
+
+    /// Listener sockets passed in via the environment.
+    ///
+    /// This implements the receiving side of systemd’s socket activation.
+    /// The service manager passes a number of already bound sockets to the
+    /// process as file descriptors starting at 3, announcing their number in
+    /// the `LISTEN_FDS` environment variable and the PID of the process they
+    /// are intended for in `LISTEN_PID`. Optionally, `LISTEN_FDNAMES`
+    /// contains a colon-separated list of names for the sockets.
+    ///
+    /// Currently, TCP, UDP, and Unix domain sockets are supported. Sockets
+    /// can be taken either by the address or path they are bound to or by
+    /// their name.
+    #[derive(Debug, Default)]
+    pub struct EnvSockets {
+        /// The sockets not yet taken.
+        sockets: Vec<SocketInfo>,
+    }
+
+    impl EnvSockets {
+        /// The first file descriptor passed in.
+        const LISTEN_FDS_START: RawFd = 3;
+
+        /// Collects the sockets passed in via the environment.
+        ///
+        /// If the environment variables are missing or not intended for the
+        /// current process, the returned value is empty. The variables are
+        /// removed from the environment so they are not passed on to child
+        /// processes.
+        pub fn from_env() -> Self {
+            let res = Self::collect_env();
+            env::remove_var("LISTEN_PID");
+            env::remove_var("LISTEN_FDS");
+            env::remove_var("LISTEN_FDNAMES");
+            res
+        }
+
+        /// Collects the sockets from the environment variables.
+        fn collect_env() -> Self {
+            let pid = env::var("LISTEN_PID").ok().and_then(|pid| {
+                u32::from_str(&pid).ok()
+            });
+            if pid != Some(std::process::id()) {
+                return Self::default()
+            }
+            let count = match env::var("LISTEN_FDS").ok().and_then(|fds| {
+                RawFd::from_str(&fds).ok()
+            }) {
+                Some(count) if count > 0 => count,
+                _ => return Self::default(),
+            };
+            let names = env::var("LISTEN_FDNAMES").unwrap_or_default();
+            let mut names = names.split(':');
+
+            let end = match Self::LISTEN_FDS_START.checked_add(count) {
+                Some(end) => end,
+                None => return Self::default(),
+            };
+            let mut sockets = Vec::new();
+            for fd in Self::LISTEN_FDS_START..end {
+                let name = names.next().filter(|name| {
+                    !name.is_empty()
+                }).map(String::from);
+                if let Some(info) = SocketInfo::from_fd(fd, name) {
+                    sockets.push(info)
+                }
+            }
+            Self { sockets }
+        }
+
+        /// Returns whether there are no sockets left.
+        pub fn is_empty(&self) -> bool {
+            self.sockets.is_empty()
+        }
+
+        /// Returns the number of sockets left.
+        pub fn len(&self) -> usize {
+            self.sockets.len()
+        }
+
+        /// Returns an iterator over the names of the sockets left.
+        ///
+        /// Sockets without a name are skipped.
+        pub fn names(&self) -> impl Iterator<Item = &str> {
+            self.sockets.iter().filter_map(|info| info.name.as_deref())
+        }
+
+        /// Returns whether there is a TCP listener for the given address.
+        pub fn has_tcp(&self, addr: &SocketAddr) -> bool {
+            self.position(SocketType::Tcp, |info| {
+                info.has_inet_addr(addr)
+            }).is_some()
+        }
+
+        /// Returns whether there is a UDP socket for the given address.
+        pub fn has_udp(&self, addr: &SocketAddr) -> bool {
+            self.position(SocketType::Udp, |info| {
+                info.has_inet_addr(addr)
+            }).is_some()
+        }
+
+        /// Takes the TCP listener for the given address.
+        pub fn take_tcp(&mut self, addr: &SocketAddr) -> Option<TcpListener> {
+            self.take(SocketType::Tcp, |info| {
+                info.has_inet_addr(addr)
+            }).map(Into::into)
+        }
+
+        /// Takes the UDP socket for the given address.
+        pub fn take_udp(&mut self, addr: &SocketAddr) -> Option<UdpSocket> {
+            self.take(SocketType::Udp, |info| {
+                info.has_inet_addr(addr)
+            }).map(Into::into)
+        }
+
+        /// Takes the TCP listener with the given name.
+        pub fn take_tcp_by_name(&mut self, name: &str) -> Option<TcpListener> {
+            self.take(SocketType::Tcp, |info| {
+                info.name.as_deref() == Some(name)
+            }).map(Into::into)
+        }
+
+        /// Takes the TCP listener for the given address or binds a new one.
+        ///
+        /// If there is no listener for `addr` among the sockets passed in,
+        /// a new listener is bound to it.
+        ///
+        /// If `reuseport` is `true`, the `SO_REUSEPORT` option is set on
+        /// the new listener before binding it. This allows several
+        /// processes to listen on the same address, e.g., a number of
+        /// workers or the old and new instance of a daemon during an
+        /// upgrade. On Linux, this requires kernel 3.9 or later and incoming
+        /// connections are distributed between all listeners. Other systems
+        /// may not distribute connections. To prevent port hijacking, all
+        /// processes binding to the address need to have the same
+        /// effective user ID.
+        pub fn take_or_bind_tcp(
+            &mut self, addr: &SocketAddr, reuseport: bool
+        ) -> Result<TcpListener, Failed> {
+            if let Some(res) = self.take_tcp(addr) {
+                return Ok(res)
+            }
+            if reuseport {
+                return bind_reuseport(addr, Type::STREAM).map(Into::into)
+            }
+            TcpListener::bind(addr).map_err(|err| {
+                error!("Fatal: failed to bind TCP listener {}: {}", addr, err);
+                Failed
+            })
+        }
+
+        /// Takes the UDP socket for the given address or binds a new one.
+        ///
+        /// If there is no socket for `addr` among the sockets passed in, a
+        /// new socket is bound to it. If `reuseport` is `true`, the
+        /// `SO_REUSEPORT` option is set on the new socket before binding it.
+        /// See [`take_or_bind_tcp`][Self::take_or_bind_tcp] for the
+        /// caveats.
+        pub fn take_or_bind_udp(
+            &mut self, addr: &SocketAddr, reuseport: bool
+        ) -> Result<UdpSocket, Failed> {
+            if let Some(res) = self.take_udp(addr) {
+                return Ok(res)
+            }
+            if reuseport {
+                return bind_reuseport(addr, Type::DGRAM).map(Into::into)
+            }
+            UdpSocket::bind(addr).map_err(|err| {
+                error!("Fatal: failed to bind UDP socket {}: {}", addr, err);
+                Failed
+            })
+        }
+
+        /// Takes the UDP socket with the given name.
+        pub fn take_udp_by_name(&mut self, name: &str) -> Option<UdpSocket> {
+            self.take(SocketType::Udp, |info| {
+                info.name.as_deref() == Some(name)
+            }).map(Into::into)
+        }
+
+        /// Takes the first remaining TCP listener.
+        pub fn pop_tcp(&mut self) -> Option<TcpListener> {
+            self.take(SocketType::Tcp, |_| true).map(Into::into)
+        }
+
+        /// Takes the first remaining UDP socket.
+        pub fn pop_udp(&mut self) -> Option<UdpSocket> {
+            self.take(SocketType::Udp, |_| true).map(Into::into)
+        }
+
+        /// Takes the Unix stream listener bound to the given path.
+        pub fn take_unix(&mut self, path: &Path) -> Option<UnixListener> {
+            self.take(SocketType::Unix, |info| {
+                info.has_unix_path(path)
+            }).map(Into::into)
+        }
+
+        /// Takes the Unix datagram socket bound to the given path.
+        pub fn take_unix_datagram(
+            &mut self, path: &Path
+        ) -> Option<UnixDatagram> {
+            self.take(SocketType::UnixDatagram, |info| {
+                info.has_unix_path(path)
+            }).map(Into::into)
+        }
+
+        /// Takes the first remaining Unix stream listener.
+        ///
+        /// This also returns unnamed or abstract sockets which can’t be
+        /// taken by their path.
+        pub fn pop_unix(&mut self) -> Option<UnixListener> {
+            self.take(SocketType::Unix, |_| true).map(Into::into)
+        }
+
+        /// Takes the first remaining Unix datagram socket.
+        pub fn pop_unix_datagram(&mut self) -> Option<UnixDatagram> {
+            self.take(SocketType::UnixDatagram, |_| true).map(Into::into)
+        }
+
+        /// Returns the index of the first matching socket.
+        fn position(
+            &self, sock_type: SocketType, op: impl Fn(&SocketInfo) -> bool
+        ) -> Option<usize> {
+            self.sockets.iter().position(|info| {
+                info.sock_type == sock_type && op(info)
+            })
+        }
+
+        /// Removes the first matching socket and returns its descriptor.
+        fn take(
+            &mut self, sock_type: SocketType, op: impl Fn(&SocketInfo) -> bool
+        ) -> Option<OwnedFd> {
+            let idx = self.position(sock_type, op)?;
+            Some(self.sockets.remove(idx).fd)
+        }
+    }
+
+
+    //-------- SocketInfo ----------------------------------------------------
+
+    /// Information about a socket passed in via the environment.
+    #[derive(Debug)]
+    struct SocketInfo {
+        /// The file descriptor of the socket.
+        fd: OwnedFd,
+
+        /// The type of the socket.
+        sock_type: SocketType,
+
+        /// The address the socket is bound to.
+        addr: LocalAddr,
+
+        /// The name of the socket if given.
+        name: Option<String>,
+    }
+
+    impl SocketInfo {
+        /// Creates the socket info for a file descriptor.
+        ///
+        /// Returns `None` if the descriptor isn’t open or isn’t a socket of
+        /// a supported type. In this case, the descriptor is left alone.
+        /// Otherwise, takes ownership of the descriptor and marks it as
+        /// close-on-exec.
+        fn from_fd(fd: RawFd, name: Option<String>) -> Option<Self> {
+            if fcntl(fd, FcntlArg::F_GETFD).is_err() {
+                return None
+            }
+            let stream = match Self::get_type(fd)? {
+                libc::SOCK_STREAM => true,
+                libc::SOCK_DGRAM => false,
+                _ => return None,
+            };
+            let addr = Self::get_addr(fd)?;
+            let sock_type = match (&addr, stream) {
+                (LocalAddr::Inet(_), true) => SocketType::Tcp,
+                (LocalAddr::Inet(_), false) => SocketType::Udp,
+                (LocalAddr::Unix(_), true) => SocketType::Unix,
+                (LocalAddr::Unix(_), false) => SocketType::UnixDatagram,
+            };
+            // Safety: systemd passes these descriptors to us and nobody
+            //         else owns them. We checked above that it is open.
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            let _ = fcntl(
+                fd.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)
+            );
+            Some(Self { fd, sock_type, addr, name })
+        }
+
+        /// Returns whether the socket is bound to the given IP address.
+        fn has_inet_addr(&self, addr: &SocketAddr) -> bool {
+            matches!(self.addr, LocalAddr::Inet(ref own) if own == addr)
+        }
+
+        /// Returns whether the socket is bound to the given path.
+        fn has_unix_path(&self, path: &Path) -> bool {
+            matches!(self.addr, LocalAddr::Unix(Some(ref own)) if own == path)
+        }
+
+        /// Returns the socket type of the descriptor via `getsockopt`.
+        fn get_type(fd: RawFd) -> Option<libc::c_int> {
+            let mut sock_type: libc::c_int = 0;
+            let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+            let res = unsafe {
+                libc::getsockopt(
+                    fd, libc::SOL_SOCKET, libc::SO_TYPE,
+                    &mut sock_type as *mut _ as *mut libc::c_void,
+                    &mut len,
+                )
+            };
+            if res < 0 {
+                None
+            }
+            else {
+                Some(sock_type)
+            }
+        }
+
+        /// Returns the address the socket is bound to.
+        ///
+        /// Returns `None` if the socket is not an IPv4, IPv6, or Unix
+        /// domain socket.
+        fn get_addr(fd: RawFd) -> Option<LocalAddr> {
+            let mut storage: libc::sockaddr_storage = unsafe {
+                mem::zeroed()
+            };
+            let mut len = mem::size_of_val(&storage) as libc::socklen_t;
+            let res = unsafe {
+                libc::getsockname(
+                    fd,
+                    &mut storage as *mut _ as *mut libc::sockaddr,
+                    &mut len,
+                )
+            };
+            if res < 0 {
+                return None
+            }
+            match libc::c_int::from(storage.ss_family) {
+                libc::AF_INET => {
+                    let addr = unsafe {
+                        &*(&storage as *const _ as *const libc::sockaddr_in)
+                    };
+                    Some(LocalAddr::Inet(SocketAddr::V4(SocketAddrV4::new(
+                        Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+                        u16::from_be(addr.sin_port),
+                    ))))
+                }
+                libc::AF_INET6 => {
+                    let addr = unsafe {
+                        &*(&storage as *const _ as *const libc::sockaddr_in6)
+                    };
+                    Some(LocalAddr::Inet(SocketAddr::V6(SocketAddrV6::new(
+                        Ipv6Addr::from(addr.sin6_addr.s6_addr),
+                        u16::from_be(addr.sin6_port),
+                        addr.sin6_flowinfo,
+                        addr.sin6_scope_id,
+                    ))))
+                }
+                libc::AF_UNIX => {
+                    let addr = unsafe {
+                        &*(&storage as *const _ as *const libc::sockaddr_un)
+                    };
+                    let offset = addr.sun_path.as_ptr() as usize
+                        - addr as *const _ as usize;
+                    let path_len = (len as usize).saturating_sub(offset).min(
+                        addr.sun_path.len()
+                    );
+                    let path = unsafe {
+                        std::slice::from_raw_parts(
+                            addr.sun_path.as_ptr() as *const u8, path_len
+                        )
+                    };
+                    // An empty path means an unnamed socket, a leading
+                    // zero byte an abstract one. Neither has a path.
+                    if path.first().copied().unwrap_or(0) == 0 {
+                        return Some(LocalAddr::Unix(None))
+                    }
+                    let path = match path.iter().position(|&ch| ch == 0) {
+                        Some(end) => &path[..end],
+                        None => path,
+                    };
+                    Some(LocalAddr::Unix(Some(
+                        OsStr::from_bytes(path).into()
+                    )))
+                }
+                _ => None
+            }
+        }
+    }
+
+
+    //-------- LocalAddr -----------------------------------------------------
+
+    /// The local address of a socket passed in via the environment.
+    #[derive(Debug)]
+    enum LocalAddr {
+        /// An IPv4 or IPv6 socket address.
+        Inet(SocketAddr),
+
+        /// A Unix domain socket path.
+        ///
+        /// This is `None` for unnamed and abstract sockets.
+        Unix(Option<PathBuf>),
+    }
+
+
+    //-------- SocketType ----------------------------------------------------
+
+    /// The type of a socket passed in via the environment.
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    enum SocketType {
+        /// A TCP listener socket.
+        Tcp,
+
+        /// A UDP socket.
+        Udp,
+
+        /// A Unix domain stream listener socket.
+        Unix,
+
+        /// A Unix domain datagram socket.
+        UnixDatagram,
+    }
+
+
+    //-------- ListenerHandle ------------------------------------------------
+
+    /// A handle for stopping a listener socket from accepting connections.
+    ///
+    /// The handle refers to the same socket as the listener it was created
+    /// from, so it can be kept elsewhere – typically by whoever handles
+    /// shutdown – while the listener is used to accept connections. Once
+    /// [`stop_accepting`][Self::stop_accepting] is called, no new
+    /// connections are accepted and a blocked `accept` call returns with
+    /// an error on Linux. Connections already accepted are not affected, so
+    /// in-flight requests can still be finished.
+    ///
+    /// The handle can be cloned cheaply. All clones refer to the same
+    /// socket.
+    #[derive(Clone, Debug)]
+    pub struct ListenerHandle {
+        /// A duplicate of the listener’s socket or `None` once stopped.
+        fd: Arc<Mutex<Option<OwnedFd>>>,
+    }
+
+    impl ListenerHandle {
+        /// Creates a new handle for the given listener.
+        ///
+        /// This works with any listener socket, e.g., a `TcpListener` or
+        /// `UnixListener` retrieved from [`EnvSockets`].
+        pub fn new(listener: &impl AsFd) -> Result<Self, Failed> {
+            match listener.as_fd().try_clone_to_owned() {
+                Ok(fd) => Ok(Self { fd: Arc::new(Mutex::new(Some(fd))) }),
+                Err(err) => {
+                    error!("Fatal: failed to duplicate listener: {}", err);
+                    Err(Failed)
+                }
+            }
+        }
+
+        /// Stops the listener from accepting new connections.
+        ///
+        /// Calling this more than once has no further effect.
+        pub fn stop_accepting(&self) {
+            let fd = match self.fd.lock() {
+                Ok(mut fd) => fd.take(),
+                Err(err) => err.into_inner().take(),
+            };
+            if let Some(fd) = fd {
+                let res = unsafe {
+                    libc::shutdown(fd.as_raw_fd(), libc::SHUT_RDWR)
+                };
+                if res < 0 {
+                    let err = io::Error::last_os_error();
+                    // Not all systems allow shutting down a listening
+                    // socket.
+                    if err.raw_os_error() != Some(libc::ENOTCONN) {
+                        warn!("Failed to stop listener: {}", err);
+                    }
+                }
+            }
+        }
+
+        /// Returns whether the listener has been stopped.
+        pub fn is_stopped(&self) -> bool {
+            match self.fd.lock() {
+                Ok(fd) => fd.is_none(),
+                Err(err) => err.into_inner().is_none(),
+            }
+        }
+    }
+
+
+    //-------- Socket Options ------------------------------------------------
+
+    /// Sets the `TCP_NODELAY` option of a TCP socket.
+    ///
+    /// If `enable` is `true`, Nagle’s algorithm is disabled and data is
+    /// sent as soon as possible.
+    pub fn set_tcp_nodelay(
+        sock: &impl AsFd, enable: bool
+    ) -> Result<(), Failed> {
+        SockRef::from(sock).set_nodelay(enable).map_err(|err| {
+            option_failed("TCP_NODELAY", err)
+        })
+    }
+
+    /// Sets the `SO_KEEPALIVE` option of a socket.
+    pub fn set_keepalive(
+        sock: &impl AsFd, enable: bool
+    ) -> Result<(), Failed> {
+        SockRef::from(sock).set_keepalive(enable).map_err(|err| {
+            option_failed("SO_KEEPALIVE", err)
+        })
+    }
+
+    /// Sets the size of the receive buffer of a socket via `SO_RCVBUF`.
+    ///
+    /// Note that the system may adjust the size. Linux, for instance,
+    /// doubles it.
+    pub fn set_recv_buffer_size(
+        sock: &impl AsFd, size: usize
+    ) -> Result<(), Failed> {
+        check_buffer_size(size, "SO_RCVBUF")?;
+        SockRef::from(sock).set_recv_buffer_size(size).map_err(|err| {
+            option_failed("SO_RCVBUF", err)
+        })
+    }
+
+    /// Sets the size of the send buffer of a socket via `SO_SNDBUF`.
+    ///
+    /// Note that the system may adjust the size. Linux, for instance,
+    /// doubles it.
+    pub fn set_send_buffer_size(
+        sock: &impl AsFd, size: usize
+    ) -> Result<(), Failed> {
+        check_buffer_size(size, "SO_SNDBUF")?;
+        SockRef::from(sock).set_send_buffer_size(size).map_err(|err| {
+            option_failed("SO_SNDBUF", err)
+        })
+    }
+
+    /// Switches a socket into or out of non-blocking mode.
+    ///
+    /// This works with any socket type, including those for which the
+    /// standard library doesn’t provide a method.
+    pub fn set_nonblocking(
+        sock: &impl AsFd, enable: bool
+    ) -> Result<(), Failed> {
+        SockRef::from(sock).set_nonblocking(enable).map_err(|err| {
+            error!("Failed to set non-blocking mode: {}", err);
+            Failed
+        })
+    }
+
+    /// Checks that a buffer size fits into a socket option value.
+    ///
+    /// The system call takes a C int, so larger values would otherwise be
+    /// silently truncated.
+    fn check_buffer_size(size: usize, name: &str) -> Result<(), Failed> {
+        if libc::c_int::try_from(size).is_err() {
+            error!("Buffer size {} too large for {}.", size, name);
+            return Err(Failed)
+        }
+        Ok(())
+    }
+
+    /// Logs the failure to set a socket option.
+    fn option_failed(name: &str, err: io::Error) -> Failed {
+        error!("Failed to set socket option {}: {}", name, err);
+        Failed
+    }
+
+    /// Binds a new socket with `SO_REUSEPORT` set.
+    ///
+    /// The `sock_type` is either `Type::STREAM` for a TCP listener or
+    /// `Type::DGRAM` for a UDP socket.
+    fn bind_reuseport(
+        addr: &SocketAddr, sock_type: Type
+    ) -> Result<Socket, Failed> {
+        let fail = |err: io::Error| {
+            error!("Fatal: failed to bind socket {}: {}", addr, err);
+            Failed
+        };
+
+        // Where available, this creates the socket with SOCK_CLOEXEC.
+        let sock = Socket::new(
+            Domain::for_address(*addr), sock_type, None
+        ).map_err(fail)?;
+        if sock_type == Type::STREAM {
+            // The standard library sets this for TCP listeners, too.
+            sock.set_reuse_address(true).map_err(fail)?;
+        }
+        sock.set_reuse_port(true).map_err(fail)?;
+        sock.bind(&(*addr).into()).map_err(fail)?;
+        if sock_type == Type::STREAM {
+            sock.listen(128).map_err(fail)?;
+        }
+        Ok(sock)
+    }
+
+
+    //-------- Signals -------------------------------------------------------
+
+    /// Synchronous handling of the signals commonly used by daemons.
+    ///
+    /// Creating a value blocks the signals `SIGHUP`, `SIGTERM`, `SIGINT`,
+    /// `SIGUSR1`, and `SIGUSR2` for the current thread so they can be
+    /// received via [`wait`][Self::wait] instead of interrupting the
+    /// process. Because threads inherit the signal mask of the thread that
+    /// creates them, the value should be created early on in the main
+    /// thread, before any other threads are spawned and before calling
+    /// [`Process::drop_privileges`].
+    ///
+    /// A typical signal loop calls [`Logger::rotate_log`] when receiving
+    /// [`Signal::Hup`][super::Signal::Hup] and shuts down the daemon upon
+    /// [`Signal::Term`][super::Signal::Term] or
+    /// [`Signal::Int`][super::Signal::Int]. Listeners registered via
+    /// [`stop_on_shutdown`][Self::stop_on_shutdown] stop accepting
+    /// connections before these two signals are returned.
+    ///
+    /// [`Logger::rotate_log`]: crate::logging::Logger::rotate_log
+    #[derive(Debug)]
+    pub struct Signals {
+        /// The set of signals we are waiting for.
+        set: SigSet,
+
+        /// The listeners to stop upon shutdown.
+        listeners: Vec<ListenerHandle>,
+    }
+
+    impl Signals {
+        /// Blocks the handled signals for the current thread.
+        pub fn new() -> Result<Self, Failed> {
+            let mut set = SigSet::empty();
+            set.add(Signal::SIGHUP);
+            set.add(Signal::SIGTERM);
+            set.add(Signal::SIGINT);
+            set.add(Signal::SIGUSR1);
+            set.add(Signal::SIGUSR2);
+            if let Err(err) = set.thread_block() {
+                error!("Fatal: failed to block signals: {}", err);
+                return Err(Failed)
+            }
+            Ok(Signals { set, listeners: Vec::new() })
+        }
+
+        /// Registers a listener to be stopped upon shutdown.
+        ///
+        /// When [`wait`][Self::wait] receives a signal asking the daemon to
+        /// shut down, it calls [`ListenerHandle::stop_accepting`] for all
+        /// registered listeners before returning. This way, no new
+        /// connections are accepted while in-flight requests are finished.
+        pub fn stop_on_shutdown(&mut self, listener: ListenerHandle) {
+            self.listeners.push(listener)
+        }
+
+        /// Waits until one of the signals is received.
+        pub fn wait(&self) -> Result<super::Signal, Failed> {
+            let res = self.wait_signal()?;
+            if res.is_shutdown() {
+                self.stop_listeners()
+            }
+            Ok(res)
+        }
+
+        /// Waits for a signal without acting on it.
+        fn wait_signal(&self) -> Result<super::Signal, Failed> {
+            loop {
+                match self.set.wait() {
+                    Ok(Signal::SIGHUP) => return Ok(super::Signal::Hup),
+                    Ok(Signal::SIGTERM) => return Ok(super::Signal::Term),
+                    Ok(Signal::SIGINT) => return Ok(super::Signal::Int),
+                    Ok(Signal::SIGUSR1) => return Ok(super::Signal::User1),
+                    Ok(Signal::SIGUSR2) => return Ok(super::Signal::User2),
+                    Ok(_) | Err(Errno::EINTR) => { }
+                    Err(err) => {
+                        error!("Fatal: failed to wait for signals: {}", err);
+                        return Err(Failed)
+                    }
+                }
+            }
+        }
+
+        /// Stops all registered listeners from accepting connections.
+        fn stop_listeners(&self) {
+            for listener in &self.listeners {
+                listener.stop_accepting()
+            }
+        }
+    }
+
+
+    //-------- AsyncSignals --------------------------------------------------
+
+    /// Asynchronous handling of the signals commonly used by daemons.
+    ///
+    /// This is the async variant of [`Signals`] for use with the Tokio
+    /// runtime. Instead of blocking the signals, it installs handlers for
+    /// `SIGHUP`, `SIGTERM`, `SIGINT`, `SIGUSR1`, and `SIGUSR2` so they can
+    /// be received via [`recv`][Self::recv]. Once created, the signals no
+    /// longer terminate the process, even after the value is dropped.
+    ///
+    /// The value has to be created from within a Tokio runtime with the
+    /// IO driver enabled. As with [`Signals`], it should be created before
+    /// calling [`Process::drop_privileges`].
+    #[cfg(feature = "tokio")]
+    #[derive(Debug)]
+    pub struct AsyncSignals {
+        /// The streams for the handled signals.
+        streams: [(tokio::signal::unix::Signal, super::Signal); 5],
+
+        /// The listeners to stop upon shutdown.
+        listeners: Vec<ListenerHandle>,
+    }
+
+    #[cfg(feature = "tokio")]
+    impl AsyncSignals {
+        /// Installs the handlers for the handled signals.
+        pub fn new() -> Result<Self, Failed> {
+            use tokio::signal::unix::SignalKind;
+
+            let stream = |kind: SignalKind, sig| {
+                match tokio::signal::unix::signal(kind) {
+                    Ok(stream) => Ok((stream, sig)),
+                    Err(err) => {
+                        error!(
+                            "Fatal: failed to install signal handler: {}",
+                            err
+                        );
+                        Err(Failed)
+                    }
+                }
+            };
+            use super::Signal::{Hup, Int, Term, User1, User2};
+
+            Ok(AsyncSignals {
+                streams: [
+                    stream(SignalKind::hangup(), Hup)?,
+                    stream(SignalKind::terminate(), Term)?,
+                    stream(SignalKind::interrupt(), Int)?,
+                    stream(SignalKind::user_defined1(), User1)?,
+                    stream(SignalKind::user_defined2(), User2)?,
+                ],
+                listeners: Vec::new(),
+            })
+        }
+
+        /// Registers a listener to be stopped upon shutdown.
+        ///
+        /// See [`Signals::stop_on_shutdown`] for details.
+        pub fn stop_on_shutdown(&mut self, listener: ListenerHandle) {
+            self.listeners.push(listener)
+        }
+
+        /// Waits until one of the signals is received.
+        pub async fn recv(&mut self) -> Result<super::Signal, Failed> {
+            use std::task::Poll;
+
+            let res = std::future::poll_fn(|cx| {
+                for (stream, sig) in self.streams.iter_mut() {
+                    match stream.poll_recv(cx) {
+                        Poll::Ready(Some(())) => {
+                            return Poll::Ready(Ok(*sig))
+                        }
+                        Poll::Ready(None) => {
+                            error!("Fatal: signal handler has gone away.");
+                            return Poll::Ready(Err(Failed))
+                        }
+                        Poll::Pending => { }
+                    }
+                }
+                Poll::Pending
+            }).await?;
+            if res.is_shutdown() {
+                for listener in &self.listeners {
+                    listener.stop_accepting()
+                }
+            }
+            Ok(res)
+        }
+    }
+
+
+    //-------- Watchdog ------------------------------------------------------
+
+    /// The systemd service watchdog.
+    ///
+    /// If the service manager expects the process to regularly send
+    /// keep-alive pings, it sets the `WATCHDOG_USEC` environment variable to
+    /// the timeout in microseconds and, optionally, `WATCHDOG_PID` to the
+    /// PID of the process that should send the pings. The variables are
+    /// ignored if the latter is present but doesn’t match our own PID.
+    #[derive(Clone, Debug, Default)]
+    pub struct Watchdog {
+        /// The watchdog timeout if the watchdog is enabled.
+        timeout: Option<Duration>,
+
+        /// The notifier to send pings through.
+        notify: Notify,
+    }
+
+    impl Watchdog {
+        /// Creates the watchdog from the environment.
+        pub fn from_env() -> Self {
+            let notify = Notify::from_env();
+            if !notify.is_enabled() {
+                return Self::default()
+            }
+            if let Ok(pid) = env::var("WATCHDOG_PID") {
+                if u32::from_str(&pid).ok() != Some(std::process::id()) {
+                    return Self::default()
+                }
+            }
+            let timeout = env::var("WATCHDOG_USEC").ok().and_then(|usec| {
+                u64::from_str(&usec).ok()
+            }).filter(|usec| *usec > 0).map(Duration::from_micros);
+            Self { timeout, notify }
+        }
+
+        /// Returns the watchdog timeout if the watchdog is enabled.
+        pub fn timeout(&self) -> Option<Duration> {
+            self.timeout
+        }
+
+        /// Returns the interval for sending pings.
+        ///
+        /// This is half of the timeout, as recommended by systemd. Returns
+        /// `None` if the watchdog is not enabled, in which case there is no
+        /// need to send pings at all.
+        pub fn interval(&self) -> Option<Duration> {
+            self.timeout.map(|timeout| timeout / 2)
+        }
+
+        /// Sends a keep-alive ping to the service manager.
+        ///
+        /// Does nothing if the watchdog is not enabled.
+        pub fn ping(&self) {
+            if self.timeout.is_some() {
+                self.notify.send("WATCHDOG=1")
+            }
+        }
+    }
+
+
+    //-------- Config --------------------------------------------------------
+
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    pub struct Config {
+        /// The optional PID file for server mode.
+        #[serde(rename = "pid-file")]
+        pid_file: Option<ConfigPath>,
+
+        /// Whether to lock the PID file.
+        ///
+        /// If this is `None`, the file is locked.
+        #[serde(rename = "pid-file-lock")]
+        pid_file_lock: Option<bool>,
+
+        /// The format of the PID file.
+        ///
+        /// If this is `None`, the bare format is used.
+        #[serde(rename = "pid-file-format")]
+        pid_file_format: Option<PidFileFormat>,
+
+        /// The number of seconds to wait for the PID file lock.
+        ///
+        /// If this is `None`, we don’t wait at all.
+        #[serde(rename = "pid-file-wait")]
+        pid_file_wait: Option<u64>,
+
+        /// The optional working directory for server mode.
+        ///
+        /// If the path starts with one of the systemd directory variables,
+        /// such as `$STATE_DIRECTORY`, it is kept as is and only expanded
+        /// when changing the working directory.
+        #[serde(rename = "working-dir")]
+        working_dir: Option<ConfigPath>,
+
+        /// The optional directory to chroot to in server mode.
+        chroot: Option<ConfigPath>,
+
+        /// Whether a chroot directory modifiable by the daemon is an error.
+        ///
+        /// If this is `None`, only a warning is logged.
+        #[serde(rename = "strict-chroot")]
+        strict_chroot: Option<bool>,
+
+        /// The network namespace to enter in server mode.
+        ///
+        /// This is either the name of a namespace in `/var/run/netns` or,
+        /// if it contains a slash, the path to a namespace file.
+        netns: Option<String>,
+
+        /// The name of the user to change to in server mode.
+        user: Option<UserId>,
+
+        /// The name of the group to change to in server mode.
+        group: Option<GroupId>,
+
+        /// The supplementary groups to set in server mode.
+        ///
+        /// If this is `None`, the supplementary groups are left alone.
+        #[serde(rename = "supplementary-groups")]
+        supplementary_groups: Option<Vec<GroupId>>,
+
+        /// The capabilities to keep after changing user.
+        ///
+        /// If this is `None`, all capabilities are lost when changing to a
+        /// user other than root.
+        #[cfg(all(feature = "caps", target_os = "linux"))]
+        capabilities: Option<Vec<Capability>>,
+
+        /// Whether to clear inherited supplementary groups.
+        ///
+        /// This only applies if `supplementary_groups` is `None` and a user
+        /// or group is given. If this is `None`, the groups are cleared.
+        #[serde(rename = "clear-supplementary-groups")]
+        clear_supplementary_groups: Option<bool>,
+
+        /// The optional path of the admin socket.
+        ///
+        /// On Linux, this can also be a name in the abstract namespace.
+        #[serde(rename = "admin-socket")]
+        admin_socket: Option<AdminSocket>,
+
+        /// The file mode of the admin socket.
+        ///
+        /// If this is `None`, `0o600` is used.
+        #[serde(rename = "admin-socket-mode")]
+        admin_socket_mode: Option<u32>,
+
+        /// The user owning the admin socket.
+        #[serde(rename = "admin-socket-user")]
+        admin_socket_user: Option<UserId>,
+
+        /// The group owning the admin socket.
+        #[serde(rename = "admin-socket-group")]
+        admin_socket_group: Option<GroupId>,
+
+        /// Whether to ignore `SIGPIPE`.
+        ///
+        /// If this is `None`, the signal is ignored.
+        #[serde(rename = "ignore-sigpipe")]
+        ignore_sigpipe: Option<bool>,
+
+        /// The resource limits to set.
+        #[serde(default)]
+        rlimits: Rlimits,
+
+        /// Whether to enable or disable core dumps.
+        ///
+        /// This is applied when dropping privileges after changing user
+        /// and group and overrides a core limit given in `rlimits`. If this
+        /// is `None`, core dumps are left as they are.
+        #[serde(rename = "core-dumps")]
+        core_dumps: Option<bool>,
+    }
+
+    impl Config {
+        pub fn from_config_file(
+            file: &mut ConfigFile
+        ) -> Result<Self, Failed> {
+            Ok(Config {
+                pid_file: file.take_path("pid-file")?,
+                pid_file_lock: file.take_bool("pid-file-lock")?,
+                pid_file_format: file.take_from_str("pid-file-format")?,
+                pid_file_wait: file.take_u64("pid-file-wait")?,
+                working_dir: Self::take_working_dir(file)?,
+                chroot: file.take_path("chroot")?,
+                strict_chroot: file.take_bool("strict-chroot")?,
+                netns: file.take_string("netns")?,
+                user: file.take_from_str("user")?,
+                group: file.take_from_str("group")?,
+                supplementary_groups: file.take_from_str_array(
+                    "supplementary-groups"
+                )?,
+                clear_supplementary_groups: file.take_bool(
+                    "clear-supplementary-groups"
+                )?,
+                #[cfg(all(feature = "caps", target_os = "linux"))]
+                capabilities: file.take_from_str_array("capabilities")?,
+                admin_socket: AdminSocket::from_config_file(
+                    file, "admin-socket"
+                )?,
+                admin_socket_mode: file.take_mode("admin-socket-mode")?,
+                admin_socket_user: file.take_from_str("admin-socket-user")?,
+                admin_socket_group: file.take_from_str(
+                    "admin-socket-group"
+                )?,
+                ignore_sigpipe: file.take_bool("ignore-sigpipe")?,
+                rlimits: Rlimits::from_config_file(file)?,
+                core_dumps: file.take_bool("core-dumps")?,
+            })
+        }
+
+        /// Creates the process from command line arguments only.
+        pub fn from_args(args: Args) -> Self {
+            Config {
+                pid_file: args.pid_file,
+                pid_file_lock: args.no_pid_file_lock.then_some(false),
+                pid_file_format: None,
+                pid_file_wait: args.pid_file_wait,
+                working_dir: args.working_dir,
+                chroot: args.chroot,
+                strict_chroot: None,
+                netns: args.netns,
+                user: args.user,
+                group: args.group,
+                supplementary_groups: args.supplementary_groups,
+                clear_supplementary_groups: None,
+                #[cfg(all(feature = "caps", target_os = "linux"))]
+                capabilities: args.capabilities,
+                admin_socket: None,
+                admin_socket_mode: None,
+                admin_socket_user: None,
+                admin_socket_group: None,
+                ignore_sigpipe: None,
+                rlimits: Rlimits::default(),
+                core_dumps: None,
+            }
+        }
+
+        /// Applies the arguments to the process.
+        pub fn apply_args(&mut self, args: Args) {
+            if let Some(pid_file) = args.pid_file {
+                self.pid_file = Some(pid_file)
+            }
+            if args.no_pid_file_lock {
+                self.pid_file_lock = Some(false)
+            }
+            if let Some(wait) = args.pid_file_wait {
+                self.pid_file_wait = Some(wait)
+            }
+            if let Some(working_dir) = args.working_dir {
+                self.working_dir = Some(working_dir)
+            }
+            if let Some(chroot) = args.chroot {
+                self.chroot = Some(chroot)
+            }
+            if let Some(netns) = args.netns {
+                self.netns = Some(netns)
+            }
+            if let Some(user) = args.user {
+                self.user = Some(user)
+            }
+            if let Some(group) = args.group {
+                self.group = Some(group)
+            }
+            if let Some(groups) = args.supplementary_groups {
+                self.supplementary_groups = Some(groups)
+            }
+            #[cfg(all(feature = "caps", target_os = "linux"))]
+            if let Some(caps) = args.capabilities {
+                self.capabilities = Some(caps)
+            }
+        }
+
+        /// Sets the supplementary groups to the given group names.
+        ///
+        /// When dropping privileges, the supplementary groups of the
+        /// process are set to exactly these groups. Returns an error if any
+        /// of the groups is unknown.
+        pub fn with_supplementary_groups<'a>(
+            mut self, groups: impl IntoIterator<Item = &'a str>
+        ) -> Result<Self, Failed> {
+            let mut res = Vec::new();
+            for name in groups {
+                match GroupId::from_str(name) {
+                    Ok(group) => res.push(group),
+                    Err(err) => {
+                        error!("Fatal: {}", err);
+                        return Err(Failed)
+                    }
+                }
+            }
+            self.supplementary_groups = Some(res);
+            Ok(self)
+        }
+
+        /// Validates the configuration and adds the results to `report`.
+        ///
+        /// Checks that the options are consistent, that the configured user
+        /// and group exist, that the directory for the PID file is writable,
+        /// and that the chroot and working directories are accessible
+        /// directories.
+        pub fn validate(&self, report: &mut ValidationReport) {
+            for issue in self.consistency_warnings() {
+                report.pass("consistency", format!("warning: {}", issue));
+            }
+            if let Some(user) = self.user.as_ref() {
+                report.pass(
+                    "user",
+                    format!("user '{}' has UID {}", user.name, user.uid)
+                );
+            }
+            if let Some(group) = self.group.as_ref() {
+                report.pass(
+                    "group",
+                    format!("group '{}' has GID {}", group.name, group.gid)
+                );
+            }
+            for group in self.supplementary_groups.iter().flatten() {
+                report.pass(
+                    "supplementary-groups",
+                    format!("group '{}' has GID {}", group.name, group.gid)
+                );
+            }
+            if let Some(path) = self.pid_file.as_ref() {
+                let dir = parent_dir(path).unwrap_or_else(|| Path::new("/"));
+                match access(dir, AccessFlags::W_OK) {
+                    Ok(()) => report.pass(
+                        "pid-file",
+                        format!("directory {} is writable", dir.display())
+                    ),
+                    Err(err) => report.fail(
+                        "pid-file",
+                        format!(
+                            "directory {} is not writable: {}",
+                            dir.display(), err
+                        )
+                    ),
+                }
+            }
+            if let Some(path) = self.chroot.as_ref() {
+                Self::validate_dir("chroot", path, report);
+            }
+            match self.expand_working_dir() {
+                Ok(Some(path)) => {
+                    Self::validate_dir("working-dir", &path, report);
+                }
+                Ok(None) => { }
+                Err(err) => report.fail("working-dir", err),
+            }
+        }
+
+        /// Checks that the options of the configuration are consistent.
+        ///
+        /// This catches combinations of options that are individually valid
+        /// but together are likely to result in insecure or unintended
+        /// behaviour. Since there are legitimate uses for all of them, each
+        /// problem is only logged as a warning and the check never fails.
+        pub fn check_consistency(&self) -> Result<(), Failed> {
+            for issue in self.consistency_warnings() {
+                warn!(
+                    "Possibly inconsistent process configuration: {}", issue
+                );
+            }
+            Ok(())
+        }
+
+        /// Checks the consistency together with the logging configuration.
+        ///
+        /// In addition to the checks of both configurations, this makes sure
+        /// that the log files, the PID file, and the admin socket don’t
+        /// use the same path. Each problem is logged as an error.
+        pub fn check_consistency_with(
+            &self, log: &logging::Config
+        ) -> Result<(), Failed> {
+            let mut res = self.check_consistency();
+            if log.check_consistency().is_err() {
+                res = Err(Failed)
+            }
+            if self.check_path_collisions(log).is_err() {
+                res = Err(Failed)
+            }
+            res
+        }
+
+        /// Checks that the PID file, admin socket, and log files differ.
+        ///
+        /// Logs an error for each pair of options using the same path and
+        /// returns an error if there was at least one.
+        fn check_path_collisions(
+            &self, log: &logging::Config
+        ) -> Result<(), Failed> {
+            let mut res = Ok(());
+            let log_paths = log.file_paths();
+            let mut paths = Vec::new();
+            if let Some(path) = self.pid_file.as_ref() {
+                paths.push(("pid-file", path));
+            }
+            if let Some(path) = self.admin_socket.as_ref().and_then(
+                AdminSocket::path
+            ) {
+                paths.push(("admin-socket", path));
+            }
+            for (idx, (name, path)) in paths.iter().enumerate() {
+                let others = paths[idx + 1..].iter().map(|(name, path)| {
+                    (*name, path.as_path())
+                }).chain(log_paths.iter().copied());
+                for (other_name, other_path) in others {
+                    if path.is_same_file(other_path) {
+                        error!(
+                            "Fatal: inconsistent configuration: '{}' and \
+                             '{}' are both set to {}.",
+                            name, other_name, path.display()
+                        );
+                        res = Err(Failed);
+                    }
+                }
+            }
+            res
+        }
+
+        /// Logs the effective settings at debug level.
+        ///
+        /// Each setting is logged as a record of its own. Where an option
+        /// that isn’t set has a default, the default is logged.
+        pub fn log_settings(&self) {
+            log_opt_setting(
+                "pid-file", self.pid_file.as_ref().map(|path| path.display())
+            );
+            log_setting("pid-file-lock", self.pid_file_lock());
+            log_setting(
+                "pid-file-format", <&str>::from(self.pid_file_format())
+            );
+            log_opt_setting("pid-file-wait", self.pid_file_wait);
+            log_opt_setting(
+                "working-dir",
+                self.working_dir.as_ref().map(|path| path.display())
+            );
+            log_opt_setting(
+                "chroot", self.chroot.as_ref().map(|path| path.display())
+            );
+            log_setting("strict-chroot", self.strict_chroot.unwrap_or(false));
+            log_opt_setting("netns", self.netns.as_ref());
+            log_opt_setting(
+                "user", self.user.as_ref().map(|user| &user.name)
+            );
+            log_opt_setting(
+                "group", self.group.as_ref().map(|group| &group.name)
+            );
+            log_opt_setting(
+                "supplementary-groups",
+                self.supplementary_groups.as_ref().map(|groups| {
+                    groups.iter().map(|group| {
+                        group.name.as_str()
+                    }).collect::<toml_edit::Array>()
+                })
+            );
+            log_setting(
+                "clear-supplementary-groups",
+                self.clear_supplementary_groups.unwrap_or(true)
+            );
+            #[cfg(all(feature = "caps", target_os = "linux"))]
+            log_opt_setting(
+                "capabilities",
+                self.capabilities.as_ref().map(|caps| {
+                    caps.iter().map(|cap| {
+                        String::from(*cap)
+                    }).collect::<toml_edit::Array>()
+                })
+            );
+            log_opt_setting(
+                "admin-socket",
+                self.admin_socket.as_ref()
+            );
+            log_setting(
+                "admin-socket-mode",
+                format_args!("0o{:03o}", self.admin_socket_mode())
+            );
+            log_opt_setting(
+                "admin-socket-user",
+                self.admin_socket_user.as_ref().map(|user| &user.name)
+            );
+            log_opt_setting(
+                "admin-socket-group",
+                self.admin_socket_group.as_ref().map(|group| &group.name)
+            );
+            log_setting(
+                "ignore-sigpipe", self.ignore_sigpipe.unwrap_or(true)
+            );
+            log_opt_setting("rlimits.nofile", self.rlimits.nofile);
+            log_opt_setting("rlimits.nproc", self.rlimits.nproc);
+            log_opt_setting("rlimits.core", self.rlimits.core);
+            log_opt_setting("core-dumps", self.core_dumps);
+        }
+
+        /// Returns a description of every questionable option combination.
+        fn consistency_warnings(&self) -> Vec<&'static str> {
+            let mut res = Vec::new();
+            if self.chroot.is_some() && self.user.is_none() {
+                res.push(
+                    "'chroot' is set but no 'user'; the process will remain \
+                     root inside the chroot"
+                );
+            }
+            #[cfg(all(feature = "caps", target_os = "linux"))]
+            if self.capabilities.is_some() && self.user.is_none() {
+                res.push(
+                    "'capabilities' is set but no 'user'; the process will \
+                     keep all capabilities of root"
+                );
+            }
+            if self.supplementary_groups.is_some()
+                && self.clear_supplementary_groups == Some(false)
+            {
+                res.push(
+                    "'supplementary-groups' is set but \
+                     'clear-supplementary-groups' is false"
+                );
+            }
+            if self.pid_file_format.is_some() && self.pid_file.is_none() {
+                res.push(
+                    "'pid-file-format' is set but 'pid-file' is missing"
+                );
+            }
+            res
+        }
+
+        /// Checks that `path` is an accessible directory.
+        fn validate_dir(
+            name: &str, path: &Path, report: &mut ValidationReport
+        ) {
+            match fs::metadata(path) {
+                Ok(meta) if meta.is_dir() => {
+                    match access(path, AccessFlags::X_OK) {
+                        Ok(()) => report.pass(
+                            name,
+                            format!(
+                                "directory {} is accessible", path.display()
+                            )
+                        ),
+                        Err(err) => report.fail(
+                            name,
+                            format!(
+                                "directory {} is not accessible: {}",
+                                path.display(), err
+                            )
+                        ),
+                    }
+                }
+                Ok(_) => report.fail(
+                    name, format!("{} is not a directory", path.display())
+                ),
+                Err(err) => report.fail(
+                    name,
+                    format!("cannot access {}: {}", path.display(), err)
+                ),
+            }
+        }
+
+        /// Takes the working directory from the config file.
+        ///
+        /// Relative paths are resolved relative to the config file unless
+        /// they start with a variable which is expanded later.
+        fn take_working_dir(
+            file: &mut ConfigFile
+        ) -> Result<Option<ConfigPath>, Failed> {
+            Ok(file.take_raw_path("working-dir")?.map(|path| {
+                if Self::dir_var(&path).is_some() {
+                    path.into()
+                }
+                else {
+                    file.dir().join(path).into()
+                }
+            }))
+        }
+
+        /// Returns the working directory with variables expanded.
+        ///
+        /// If the configured working directory starts with one of the
+        /// directory variables set by systemd, e.g., `$STATE_DIRECTORY`,
+        /// this part is replaced by the value of the environment variable.
+        /// If the variable contains a colon-separated list of directories,
+        /// the first one is used. Returns an error message if the variable
+        /// is not one of the known ones or if it isn’t set.
+        fn expand_working_dir(&self) -> Result<Option<PathBuf>, String> {
+            const VARS: &[&str] = &[
+                "STATE_DIRECTORY", "RUNTIME_DIRECTORY", "CACHE_DIRECTORY",
+                "LOGS_DIRECTORY", "CONFIGURATION_DIRECTORY",
+            ];
+
+            let path = match self.working_dir.as_ref() {
+                Some(path) => path.as_path(),
+                None => return Ok(None)
+            };
+            let var = match Self::dir_var(path) {
+                Some(var) => var,
+                None => return Ok(Some(path.into()))
+            };
+            if !VARS.contains(&var) {
+                return Err(format!(
+                    "unknown variable '${}' in working directory {}",
+                    var, path.display()
+                ))
+            }
+            let value = match env::var_os(var) {
+                Some(value) if !value.is_empty() => value,
+                _ => {
+                    return Err(format!(
+                        "environment variable {} for working directory {} \
+                         is not set",
+                        var, path.display()
+                    ))
+                }
+            };
+            let first = value.as_bytes().split(|&ch| ch == b':').next();
+            let base = Path::new(OsStr::from_bytes(first.unwrap_or_default()));
+            let mut components = path.components();
+            components.next();
+            Ok(Some(base.join(components.as_path())))
+        }
+
+        /// Returns the variable name if the path starts with one.
+        fn dir_var(path: &Path) -> Option<&str> {
+            match path.components().next() {
+                Some(Component::Normal(first)) => {
+                    first.to_str().and_then(|first| first.strip_prefix('$'))
+                }
+                _ => None
+            }
+        }
+
+        /// Returns the path of the network namespace file if configured.
+        fn netns_path(&self) -> Option<PathBuf> {
+            self.netns.as_ref().map(|netns| {
+                if netns.contains('/') {
+                    PathBuf::from(netns)
+                }
+                else {
+                    Path::new("/var/run/netns").join(netns)
+                }
+            })
+        }
+
+        /// Returns whether the PID file should be locked.
+        fn pid_file_lock(&self) -> bool {
+            self.pid_file_lock.unwrap_or(true)
+        }
+
+        /// Returns the supplementary groups to set when dropping privileges.
+        ///
+        /// Unless explicitly given, the groups inherited from the original
+        /// user are cleared when changing user or group so we don’t keep
+        /// any of its privileges. Returns `None` if the groups should be
+        /// left alone.
+        fn supplementary_gids(&self) -> Option<Vec<libc::gid_t>> {
+            if let Some(groups) = self.supplementary_groups.as_ref() {
+                return Some(
+                    groups.iter().map(|group| group.gid.as_raw()).collect()
+                )
+            }
+            if (self.user.is_some() || self.group.is_some())
+                && self.clear_supplementary_groups.unwrap_or(true)
+            {
+                Some(Vec::new())
+            }
+            else {
+                None
+            }
+        }
+
+        /// Returns the format of the PID file.
+        fn pid_file_format(&self) -> PidFileFormat {
+            self.pid_file_format.unwrap_or_default()
+        }
+
+        /// Returns the file mode for the admin socket.
+        fn admin_socket_mode(&self) -> u32 {
+            self.admin_socket_mode.unwrap_or(0o600)
+        }
+    }
+
+
+    //-------- Args ----------------------------------------------------------
+
+    #[derive(Clone, Debug, clap::Args)]
+    #[group(id = "process-args")]
+    pub struct Args {
+        /// The file for keep the daemon process's PID in
+        #[arg(long, value_name = "PATH")]
+        pid_file: Option<ConfigPath>,
+
+        /// Don't lock the PID file
+        #[arg(long)]
+        no_pid_file_lock: bool,
+
+        /// Seconds to wait for the PID file lock to become available
+        #[arg(long, value_name = "SECS")]
+        pid_file_wait: Option<u64>,
+
+        /// The working directory of the daemon process
+        #[arg(long, value_name = "PATH")]
+        working_dir: Option<ConfigPath>,
+
+        /// Root directory for the daemon process
+        #[arg(long, value_name = "PATH")]
+        chroot: Option<ConfigPath>,
+
+        /// Network namespace name or path for the daemon process
+        #[arg(long, value_name = "NAME")]
+        netns: Option<String>,
+
+        /// User for the daemon process
+        #[arg(long, value_name = "UID")]
+        user: Option<UserId>,
+
+        /// Group for the daemon process
+        #[arg(long, value_name = "GID")]
+        group: Option<GroupId>,
+
+        /// Comma-separated supplementary groups for the daemon process
+        #[arg(long, value_name = "GIDS", value_delimiter = ',')]
+        supplementary_groups: Option<Vec<GroupId>>,
+
+        /// Comma-separated capabilities to keep after changing user
+        #[cfg(all(feature = "caps", target_os = "linux"))]
+        #[arg(long, value_name = "CAPS", value_delimiter = ',')]
+        capabilities: Option<Vec<Capability>>,
+    }
+
+    impl Args {
+        pub fn into_config(self) -> Config {
+            Config::from_args(self)
+        }
+    }
+
+
+    //-------- UserId --------------------------------------------------------
+
+    /// A user ID in configuration.
+    ///
+    /// The user can be given either by name or by its numerical ID. The
+    /// latter doesn’t need to exist in the user database.
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    #[serde(try_from = "String", into = "String", expecting = "a user name")]
+    struct UserId {
+        /// The numerical user ID.
+        uid: Uid,
+
+        /// The user name.
+        ///
+        /// We keep this information so we can produce the actual config.
+        name: String,
+    }
+
+    impl TryFrom<String> for UserId {
+        type Error = String;
+
+        fn try_from(name: String) -> Result<Self, Self::Error> {
+            if let Some(uid) = parse_numeric_id(&name) {
+                let uid = Uid::from_raw(uid);
+                let name = match User::from_uid(uid) {
+                    Ok(Some(user)) => user.name,
+                    _ => format!("#{}", uid),
+                };
+                return Ok(UserId { uid, name })
+            }
+            match User::from_name(&name) {
+                Ok(Some(user)) => {
+                    Ok(UserId { uid: user.uid, name })
+                }
+                Ok(None) => {
+                    Err(format!("unknown user '{}'", name))
+                }
+                Err(err) => {
+                    Err(format!("failed to resolve user '{}': {}", name, err))
+                }
+            }
+        }
+    }
+
+    impl FromStr for UserId {
+        type Err = String;
+
+        fn from_str(name: &str) -> Result<Self, Self::Err> {
+            String::from(name).try_into()
+        }
+    }
+
+    impl From<UserId> for String {
+        fn from(user: UserId) -> Self {
+            user.name
+        }
+    }
+
+
+    //-------- AdminSocket ---------------------------------------------------
+
+    /// The address of the admin socket in configuration.
+    ///
+    /// A value starting with `@` is the name of a socket in the Linux
+    /// abstract namespace. Any other value is a path in the file system.
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    #[serde(
+        try_from = "String", into = "String",
+        expecting = "a path or an abstract socket name"
+    )]
+    enum AdminSocket {
+        /// A socket in the file system.
+        Path(ConfigPath),
+
+        /// A socket in the abstract namespace with the given name.
+        Abstract(String),
+    }
+
+    impl AdminSocket {
+        /// Takes the admin socket from the given key of a config file.
+        ///
+        /// A relative path is resolved against the directory of the
+        /// config file.
+        fn from_config_file(
+            file: &mut ConfigFile, key: &str
+        ) -> Result<Option<Self>, Failed> {
+            let value = match file.take_string(key)? {
+                Some(value) => value,
+                None => return Ok(None)
+            };
+            match Self::from_value(value, Some(file.dir())) {
+                Ok(res) => Ok(Some(res)),
+                Err(err) => {
+                    error!(
+                        "Failed in config file {}: \
+                         illegal value in '{}': {}.",
+                        file.path().display(), file.section_key(key), err
+                    );
+                    Err(Failed)
+                }
+            }
+        }
+
+        /// Creates the value from its string form.
+        ///
+        /// If `dir` is given, a relative path is resolved against it.
+        fn from_value(
+            value: String, dir: Option<&Path>
+        ) -> Result<Self, String> {
+            match value.strip_prefix('@') {
+                Some(_) if !cfg!(target_os = "linux") => {
+                    Err(String::from(
+                        "abstract sockets are only supported on Linux"
+                    ))
+                }
+                Some("") => Err(String::from("empty abstract socket name")),
+                Some(name) => Ok(AdminSocket::Abstract(name.into())),
+                None => {
+                    Ok(AdminSocket::Path(match dir {
+                        Some(dir) => dir.join(value).into(),
+                        None => value.into(),
+                    }))
+                }
+            }
+        }
+
+        /// Returns the path if the socket lives in the file system.
+        fn path(&self) -> Option<&ConfigPath> {
+            match self {
+                AdminSocket::Path(path) => Some(path),
+                AdminSocket::Abstract(_) => None,
+            }
+        }
+    }
+
+    impl TryFrom<String> for AdminSocket {
+        type Error = String;
+
+        fn try_from(value: String) -> Result<Self, Self::Error> {
+            Self::from_value(value, None)
+        }
+    }
+
+    impl From<AdminSocket> for String {
+        fn from(socket: AdminSocket) -> Self {
+            socket.to_string()
+        }
+    }
+
+    impl fmt::Display for AdminSocket {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                AdminSocket::Path(path) => path.display().fmt(f),
+                AdminSocket::Abstract(name) => write!(f, "@{}", name),
+            }
+        }
+    }
+
+
+    //-------- GroupId -------------------------------------------------------
+
+    /// A group ID in configuration.
+    ///
+    /// The group can be given either by name or by its numerical ID. The
+    /// latter doesn’t need to exist in the group database.
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    #[serde(try_from = "String", into = "String", expecting = "a group name")]
+    struct GroupId {
+        /// The numerical user ID.
+        gid: Gid,
+
+        /// The user name.
+        ///
+        /// We keep this information so we can produce the actual config.
+        name: String,
+    }
+
+    impl TryFrom<String> for GroupId {
+        type Error = String;
+
+        fn try_from(name: String) -> Result<Self, Self::Error> {
+            if let Some(gid) = parse_numeric_id(&name) {
+                let gid = Gid::from_raw(gid);
+                let name = match Group::from_gid(gid) {
+                    Ok(Some(group)) => group.name,
+                    _ => format!("#{}", gid),
+                };
+                return Ok(GroupId { gid, name })
+            }
+            match Group::from_name(&name) {
+                Ok(Some(group)) => {
+                    Ok(GroupId { gid: group.gid, name })
+                }
+                Ok(None) => {
+                    Err(format!("unknown user '{}'", name))
+                }
+                Err(err) => {
+                    Err(format!("failed to resolve user '{}': {}", name, err))
+                }
+            }
+        }
+    }
+
+    impl FromStr for GroupId {
+        type Err = String;
+
+        fn from_str(name: &str) -> Result<Self, Self::Err> {
+            String::from(name).try_into()
+        }
+    }
+
+    impl From<GroupId> for String {
+        fn from(user: GroupId) -> Self {
+            user.name
+        }
+    }
+
+
+    /// Parses a numerical user or group ID.
+    ///
+    /// The ID may be preceded by a `#` which is how we display IDs that
+    /// don’t have a name.
+    fn parse_numeric_id(s: &str) -> Option<u32> {
+        let s = s.strip_prefix('#').unwrap_or(s);
+        if s.is_empty() || !s.bytes().all(|ch| ch.is_ascii_digit()) {
+            return None
+        }
+        u32::from_str(s).ok()
+    }
+
+
+    //-------- Capability ----------------------------------------------------
+
+    /// A Linux capability in configuration.
+    ///
+    /// Capabilities are given by their name, e.g., `CAP_NET_BIND_SERVICE`.
+    /// The name is case-insensitive and the `CAP_` prefix can be left out.
+    #[cfg(all(feature = "caps", target_os = "linux"))]
+    #[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+    #[serde(try_from = "String", into = "String")]
+    struct Capability(caps::Capability);
+
+    #[cfg(all(feature = "caps", target_os = "linux"))]
+    impl From<Capability> for String {
+        fn from(cap: Capability) -> Self {
+            cap.0.to_string()
+        }
+    }
+
+    #[cfg(all(feature = "caps", target_os = "linux"))]
+    impl TryFrom<String> for Capability {
+        type Error = String;
+
+        fn try_from(s: String) -> Result<Self, Self::Error> {
+            Self::from_str(&s)
+        }
+    }
+
+    #[cfg(all(feature = "caps", target_os = "linux"))]
+    impl FromStr for Capability {
+        type Err = String;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            caps::Capability::from_str(&caps::to_canonical(s)).map(
+                Capability
+            ).map_err(|_| format!("unknown capability '{}'", s))
+        }
+    }
+
+
+    //-------- capabilities --------------------------------------------------
+
+    /// Handling of Linux capabilities.
+    #[cfg(all(feature = "caps", target_os = "linux"))]
+    mod capabilities {
+        use super::*;
+        use caps::{CapSet, CapsHashSet};
+
+        /// Sets whether capabilities are kept when changing user.
+        pub fn keep_caps(keep: bool) -> Result<(), Failed> {
+            caps::securebits::set_keepcaps(keep).map_err(|err| {
+                error!("Fatal: failed to keep capabilities: {}", err);
+                Failed
+            })
+        }
+
+        /// Sets the process’s capabilities to exactly the given ones.
+        ///
+        /// The capabilities are set as the effective, permitted,
+        /// inheritable, and ambient capabilities, so they are also passed
+        /// on to programs the process executes.
+        pub fn set_caps(caps: &[Capability]) -> Result<(), Failed> {
+            let set: CapsHashSet = caps.iter().map(|cap| cap.0).collect();
+            for cset in [
+                CapSet::Permitted, CapSet::Effective, CapSet::Inheritable
+            ] {
+                if let Err(err) = caps::set(None, cset, &set) {
+                    error!("Fatal: failed to set capabilities: {}", err);
+                    return Err(Failed)
+                }
+            }
+            for cap in caps {
+                if let Err(err) = caps::raise(None, CapSet::Ambient, cap.0) {
+                    error!(
+                        "Fatal: failed to raise ambient capability {}: {}",
+                        cap.0, err
+                    );
+                    return Err(Failed)
+                }
+            }
+            Ok(())
+        }
+    }
+
+
+    //-------- PidFileFormat -------------------------------------------------
+
+    /// The format of the PID file.
+    #[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+    #[serde(try_from = "String", into = "&'static str")]
+    enum PidFileFormat {
+        /// Only the PID.
+        #[default]
+        Bare,
+
+        /// The PID, start time, and executable on separate lines.
+        ///
+        /// The start time is given in seconds since the Unix epoch. Since
+        /// the PID is on the first line, readers of the bare format can
+        /// still use the file.
+        Extended,
+    }
+
+    impl From<PidFileFormat> for &'static str {
+        fn from(format: PidFileFormat) -> Self {
+            match format {
+                PidFileFormat::Bare => "bare",
+                PidFileFormat::Extended => "extended",
+            }
+        }
+    }
+
+    impl TryFrom<String> for PidFileFormat {
+        type Error = &'static str;
+
+        fn try_from(s: String) -> Result<Self, Self::Error> {
+            Self::from_str(&s)
+        }
+    }
+
+    impl FromStr for PidFileFormat {
+        type Err = &'static str;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "bare" => Ok(PidFileFormat::Bare),
+                "extended" => Ok(PidFileFormat::Extended),
+                _ => Err("expected 'bare' or 'extended'")
+            }
+        }
+    }
+
+
+    //-------- Rlimits -------------------------------------------------------
+
+    /// The resource limits to set for the process.
+    ///
+    /// In the config file, the limits are given in the `rlimits` table. Each
+    /// limit is either a single value used for both the soft and hard limit
+    /// or a table with `soft` and `hard` keys. If the table only contains
+    /// `soft`, the current hard limit is kept. A value is either a number or
+    /// the string `"unlimited"`.
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    struct Rlimits {
+        /// The maximum number of open file descriptors.
+        nofile: Option<RlimitPair>,
+
+        /// The maximum number of processes of the user.
+        nproc: Option<RlimitPair>,
+
+        /// The maximum size of a core dump in bytes.
+        core: Option<RlimitPair>,
+    }
+
+    impl Rlimits {
+        /// Takes the limits from the `rlimits` table of a config file.
+        fn from_config_file(file: &mut ConfigFile) -> Result<Self, Failed> {
+            let mut table = match file.take_table("rlimits")? {
+                Some(table) => table,
+                None => return Ok(Self::default())
+            };
+            let res = Rlimits {
+                nofile: RlimitPair::from_config_file(&mut table, "nofile")?,
+                nproc: RlimitPair::from_config_file(&mut table, "nproc")?,
+                core: RlimitPair::from_config_file(&mut table, "core")?,
+            };
+            table.check_exhausted()?;
+            Ok(res)
+        }
+
+        /// Applies the limits to the current process.
+        ///
+        /// This needs to happen before dropping privileges since raising
+        /// the hard limits requires root.
+        fn apply(&self) -> Result<(), Failed> {
+            if let Some(pair) = self.nofile {
+                pair.apply("nofile", Resource::RLIMIT_NOFILE)?;
+            }
+            if let Some(pair) = self.nproc {
+                pair.apply("nproc", Resource::RLIMIT_NPROC)?;
+            }
+            if let Some(pair) = self.core {
+                pair.apply("core", Resource::RLIMIT_CORE)?;
+            }
+            Ok(())
+        }
+    }
+
+
+    //-------- RlimitPair ----------------------------------------------------
+
+    /// The soft and hard value of a resource limit.
+    #[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+    struct RlimitPair {
+        /// The soft limit.
+        soft: RlimitValue,
+
+        /// The hard limit.
+        ///
+        /// If this is `None`, the current hard limit is kept.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        hard: Option<RlimitValue>,
+    }
+
+    impl RlimitPair {
+        /// Takes a limit from the given key of a config file.
+        fn from_config_file(
+            file: &mut ConfigFile, key: &str
+        ) -> Result<Option<Self>, Failed> {
+            if !file.is_table(key) {
+                return Ok(RlimitValue::from_config_file(file, key)?.map(
+                    |value| RlimitPair { soft: value, hard: Some(value) }
+                ))
+            }
+            if let Some(mut table) = file.take_table(key)? {
+                let soft = RlimitValue::from_config_file(&mut table, "soft")?;
+                let hard = RlimitValue::from_config_file(&mut table, "hard")?;
+                table.check_exhausted()?;
+                let (soft, hard) = match (soft, hard) {
+                    (Some(soft), hard) => (soft, hard),
+                    (None, Some(hard)) => (hard, Some(hard)),
+                    (None, None) => return Ok(None)
+                };
+                if hard.map(|hard| {
+                    soft.as_raw() > hard.as_raw()
+                }).unwrap_or(false) {
+                    error!(
+                        "Failed in config file {}: soft limit for \
+                         '{}' is greater than the hard limit.",
+                        file.path().display(), file.section_key(key)
+                    );
+                    return Err(Failed)
+                }
+                return Ok(Some(RlimitPair { soft, hard }))
+            }
+            Ok(None)
         }
 
-        /// Updates the pid in the pid file after forking.
-        fn write_pid_file(&self) -> Result<(), Failed> {
-            if let Some(pid_file) = self.pid_file {
-                let pid = format!("{}", getpid());
-                match write(pid_file, pid.as_bytes()) {
-                    Ok(len) if len == pid.len() => {}
-                    Ok(_) => {
-                        error!(
-                            "Fatal: failed to write PID to PID file: \
-                             short write"
-                        );
-                        return Err(Failed)
-                    }
+        /// Applies the limit.
+        ///
+        /// If no hard limit is given, the current hard limit is kept.
+        fn apply(self, name: &str, resource: Resource) -> Result<(), Failed> {
+            let hard = match self.hard {
+                Some(hard) => hard.as_raw(),
+                None => match getrlimit(resource) {
+                    Ok((_, hard)) => hard,
                     Err(err) => {
                         error!(
-                            "Fatal: failed to write PID to PID file: {}", err
+                            "Fatal: failed to get resource limit '{}': {}",
+                            name, err
                         );
                         return Err(Failed)
                     }
                 }
+            };
+            if let Err(err) = setrlimit(resource, self.soft.as_raw(), hard) {
+                error!(
+                    "Fatal: failed to set resource limit '{}': {}",
+                    name, err
+                );
+                return Err(Failed)
             }
             Ok(())
         }
+    }
 
-        /// Peforms a fork and exits the parent process.
-        fn perform_fork(&self) -> Result<(), Failed> {
-            match unsafe { fork() } {
-                Ok(res) => {
-                    if res.is_parent() {
-                        std::process::exit(0)
+
+    //-------- RlimitValue ---------------------------------------------------
+
+    /// The value of a resource limit.
+    #[derive(Clone, Copy, Debug)]
+    enum RlimitValue {
+        /// A limited value.
+        Limited(u64),
+
+        /// No limit.
+        Unlimited,
+    }
+
+    impl RlimitValue {
+        /// Takes a value from the given key of a config file.
+        fn from_config_file(
+            file: &mut ConfigFile, key: &str
+        ) -> Result<Option<Self>, Failed> {
+            match file.take_value(key)? {
+                Some(toml_edit::Value::Integer(value)) => {
+                    if let Ok(value) = u64::try_from(*value.value()) {
+                        return Ok(Some(RlimitValue::Limited(value)))
                     }
-                    Ok(())
                 }
-                Err(err) => {
-                    error!("Fatal: failed to detach: {}", err);
-                    Err(Failed)
+                Some(toml_edit::Value::String(value)) => {
+                    if value.value() == "unlimited" {
+                        return Ok(Some(RlimitValue::Unlimited))
+                    }
                 }
+                Some(_) => { }
+                None => return Ok(None)
             }
+            error!(
+                "Failed in config file {}: \
+                 '{}' expected to be a positive integer or 'unlimited'.",
+                file.path().display(), file.section_key(key)
+            );
+            Err(Failed)
         }
 
-        /// Changes the current working directory in necessary.
-        fn change_working_dir(&self, background: bool) -> Result<(), Failed> {
-            let mut path = self.config.working_dir.as_ref().or(
-                self.config.chroot.as_ref()
-            ).map(ConfigPath::as_path);
-            if background {
-                path = path.or(Some(Path::new("/")));
-            }
-            if let Some(path) = path {
-                if let Err(err) = set_current_dir(path) {
-                    error!("Fatal: failed to set working directory {}: {}",
-                        path.display(), err
-                    );
-                    return Err(Failed)
+        /// Returns the raw value for use with `setrlimit`.
+        fn as_raw(self) -> libc::rlim_t {
+            match self {
+                RlimitValue::Limited(value) => {
+                    libc::rlim_t::try_from(value).unwrap_or(
+                        libc::RLIM_INFINITY
+                    )
                 }
+                RlimitValue::Unlimited => libc::RLIM_INFINITY,
             }
-
-            Ok(())
         }
+    }
 
-        /// Changes the stdio streams to /dev/null.
-        fn redirect_stdio(&self) -> Result<(), Failed> {
-            let dev_null = match open(
-                "/dev/null", OFlag::O_RDWR,
-                Mode::empty()
-            ) {
-                Ok(fd) => fd,
-                Err(err) => {
-                    error!("Fatal: failed to open /dev/null: {}", err);
-                    return Err(Failed)
+    impl fmt::Display for RlimitPair {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self.hard {
+                Some(hard) => {
+                    write!(f, "{{ soft = {}, hard = {} }}", self.soft, hard)
                 }
-            };
-
-            if let Err(err) = dup2(dev_null, io::stdin().as_fd().as_raw_fd()) {
-                error!(
-                    "Fatal: failed to redirect stdio to /dev/null: {}", err
-                );
-                return Err(Failed)
+                None => write!(f, "{{ soft = {} }}", self.soft),
             }
-            if let Err(err) = dup2(dev_null, io::stdout().as_fd().as_raw_fd()) {
-                error!(
-                    "Fatal: failed to redirect stdout to /dev/null: {}", err
-                );
-                return Err(Failed)
+        }
+    }
+
+    impl fmt::Display for RlimitValue {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match *self {
+                RlimitValue::Limited(value) => value.fmt(f),
+                RlimitValue::Unlimited => f.write_str("\"unlimited\""),
             }
-            if let Err(err) = dup2(dev_null, io::stderr().as_fd().as_raw_fd()) {
-                error!(
-                    "Fatal: failed to redirect stderr to /dev/null: {}", err
-                );
-                return Err(Failed)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for RlimitValue {
+        fn deserialize<D: serde::Deserializer<'de>>(
+            deserializer: D
+        ) -> Result<Self, D::Error> {
+            #[derive(Deserialize)]
+            #[serde(untagged)]
+            enum Raw {
+                Number(u64),
+                Name(String),
             }
 
-            if let Err(err) = close(dev_null) {
-                error!(
-                    "Fatal: failed to close /dev/null: {}", err
-                );
-                return Err(Failed)
+            match Raw::deserialize(deserializer)? {
+                Raw::Number(value) => Ok(RlimitValue::Limited(value)),
+                Raw::Name(value) if value == "unlimited" => {
+                    Ok(RlimitValue::Unlimited)
+                }
+                Raw::Name(_) => Err(serde::de::Error::custom(
+                    "expected a positive integer or 'unlimited'"
+                )),
             }
+        }
+    }
 
-            Ok(())
+    impl Serialize for RlimitValue {
+        fn serialize<S: serde::Serializer>(
+            &self, serializer: S
+        ) -> Result<S::Ok, S::Error> {
+            match *self {
+                RlimitValue::Limited(value) => serializer.serialize_u64(value),
+                RlimitValue::Unlimited => serializer.serialize_str("unlimited"),
+            }
         }
     }
 
+    //-------- LimitsReport --------------------------------------------------
 
-    //-------- Config --------------------------------------------------------
+    /// Returns the resource limits the process is currently running under.
+    ///
+    /// The limits are read via `getrlimit`. They reflect both limits set
+    /// through the `rlimits` config table and those inherited from whoever
+    /// started the process.
+    pub fn current_limits() -> LimitsReport {
+        LimitsReport {
+            nofile: ResourceLimit::current(Resource::RLIMIT_NOFILE),
+            nproc: ResourceLimit::current(Resource::RLIMIT_NPROC),
+            core: ResourceLimit::current(Resource::RLIMIT_CORE),
+        }
+    }
 
-    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
-    pub struct Config {
-        /// The optional PID file for server mode.
-        #[serde(rename = "pid-file")]
-        pid_file: Option<ConfigPath>,
+    /// The current resource limits of the process.
+    ///
+    /// A value of this type is returned by [`current_limits`]. Each limit
+    /// is `None` if it couldn’t be determined.
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct LimitsReport {
+        /// The maximum number of open file descriptors.
+        nofile: Option<ResourceLimit>,
 
-        /// The optional working directory for server mode.
-        #[serde(rename = "working-dir")]
-        working_dir: Option<ConfigPath>,
+        /// The maximum number of processes of the user.
+        nproc: Option<ResourceLimit>,
 
-        /// The optional directory to chroot to in server mode.
-        chroot: Option<ConfigPath>,
+        /// The maximum size of a core dump in bytes.
+        core: Option<ResourceLimit>,
+    }
 
-        /// The name of the user to change to in server mode.
-        user: Option<UserId>,
+    impl LimitsReport {
+        /// Returns the limit for the number of open file descriptors.
+        pub fn nofile(&self) -> Option<ResourceLimit> {
+            self.nofile
+        }
 
-        /// The name of the group to change to in server mode.
-        group: Option<GroupId>,
-    }
+        /// Returns the limit for the number of processes of the user.
+        pub fn nproc(&self) -> Option<ResourceLimit> {
+            self.nproc
+        }
 
-    impl Config {
-        pub fn from_config_file(
-            file: &mut ConfigFile
-        ) -> Result<Self, Failed> {
-            Ok(Config {
-                pid_file: file.take_path("pid-file")?,
-                working_dir: file.take_path("working-dir")?,
-                chroot: file.take_path("chroot")?,
-                user: file.take_from_str("user")?,
-                group: file.take_from_str("group")?,
-            })
+        /// Returns the limit for the size of core dumps.
+        pub fn core(&self) -> Option<ResourceLimit> {
+            self.core
         }
 
-        /// Creates the process from command line arguments only.
-        pub fn from_args(args: Args) -> Self {
-            Config {
-                pid_file: args.pid_file,
-                working_dir: args.working_dir,
-                chroot: args.chroot,
-                user: args.user,
-                group: args.group,
+        /// Logs the limits at info level.
+        pub fn log(&self) {
+            fn display(limit: Option<ResourceLimit>) -> String {
+                match limit {
+                    Some(limit) => limit.to_string(),
+                    None => String::from("<unknown>"),
+                }
             }
+
+            info!(
+                target: super::LIFECYCLE_TARGET,
+                "Resource limits (soft/hard): nofile={} nproc={} core={}",
+                display(self.nofile), display(self.nproc),
+                display(self.core),
+            );
         }
+    }
 
-        /// Applies the arguments to the process.
-        pub fn apply_args(&mut self, args: Args) {
-            if let Some(pid_file) = args.pid_file {
-                self.pid_file = Some(pid_file)
-            }
-            if let Some(working_dir) = args.working_dir {
-                self.working_dir = Some(working_dir)
-            }
-            if let Some(chroot) = args.chroot {
-                self.chroot = Some(chroot)
-            }
-            if let Some(user) = args.user {
-                self.user = Some(user)
+
+    //-------- ResourceLimit -------------------------------------------------
+
+    /// The soft and hard value of a current resource limit.
+    #[derive(Clone, Copy, Debug)]
+    pub struct ResourceLimit {
+        /// The soft limit or `None` if unlimited.
+        soft: Option<u64>,
+
+        /// The hard limit or `None` if unlimited.
+        hard: Option<u64>,
+    }
+
+    impl ResourceLimit {
+        /// Reads the current limit for a resource.
+        fn current(resource: Resource) -> Option<Self> {
+            // rlim_t is u64 on most but not all systems.
+            #[allow(clippy::useless_conversion)]
+            fn value(raw: libc::rlim_t) -> Option<u64> {
+                if raw == libc::RLIM_INFINITY {
+                    None
+                }
+                else {
+                    u64::try_from(raw).ok()
+                }
             }
-            if let Some(group) = args.group {
-                self.group = Some(group)
+
+            let (soft, hard) = getrlimit(resource).ok()?;
+            Some(ResourceLimit { soft: value(soft), hard: value(hard) })
+        }
+
+        /// Returns the soft limit.
+        ///
+        /// Returns `None` if the resource is unlimited.
+        pub fn soft(self) -> Option<u64> {
+            self.soft
+        }
+
+        /// Returns the hard limit.
+        ///
+        /// Returns `None` if the resource is unlimited.
+        pub fn hard(self) -> Option<u64> {
+            self.hard
+        }
+    }
+
+    impl fmt::Display for ResourceLimit {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            fn value(
+                value: Option<u64>, f: &mut fmt::Formatter
+            ) -> fmt::Result {
+                match value {
+                    Some(value) => value.fmt(f),
+                    None => f.write_str("unlimited"),
+                }
             }
+
+            value(self.soft, f)?;
+            f.write_str("/")?;
+            value(self.hard, f)
         }
     }
 
 
-    //-------- Args ----------------------------------------------------------
+    //-------- Tests ---------------------------------------------------------
 
-    #[derive(Clone, Debug, clap::Args)]
-    #[group(id = "process-args")]
-    pub struct Args {
-        /// The file for keep the daemon process's PID in
-        #[arg(long, value_name = "PATH")]
-        pid_file: Option<ConfigPath>,
+    #[cfg(test)]
+    mod test {
+        use super::*;
+        use std::net::TcpStream;
 
-        /// The working directory of the daemon process
-        #[arg(long, value_name = "PATH")]
-        working_dir: Option<ConfigPath>,
+        /// Returns a path for a socket in the temporary directory.
+        fn socket_path(name: &str) -> PathBuf {
+            env::temp_dir().join(format!(
+                "daemonbase-test-{}-{}.sock", std::process::id(), name
+            ))
+        }
 
-        /// Root directory for the daemon process
-        #[arg(long, value_name = "PATH")]
-        chroot: Option<ConfigPath>,
+        #[test]
+        fn resource_monitor_stops_on_drop() {
+            let monitor = super::super::ResourceMonitor::spawn(
+                Duration::from_secs(3600)
+            );
+            assert_eq!(
+                monitor.is_running(),
+                super::super::ResourceUsage::is_supported()
+            );
+            let start = Instant::now();
+            drop(monitor);
+            assert!(start.elapsed() < Duration::from_secs(60));
+        }
 
-        /// User for the daemon process
-        #[arg(long, value_name = "UID")]
-        user: Option<UserId>,
+        #[test]
+        fn redact_args() {
+            let args = [
+                "daemon", "--config", "daemon.conf", "--password", "secret",
+                "--api-token=secret", "--user=nobody", "--auth-key", "-v",
+            ];
+            assert_eq!(
+                super::super::redact_args(
+                    args.iter().map(std::ffi::OsString::from)
+                ),
+                [
+                    "\"daemon\"", "\"--config\"", "\"daemon.conf\"",
+                    "\"--password\"", "<redacted>",
+                    "\"--api-token=<redacted>\"", "\"--user=nobody\"",
+                    "\"--auth-key\"", "\"-v\"",
+                ]
+            );
+        }
 
-        /// Group for the daemon process
-        #[arg(long, value_name = "GID")]
-        group: Option<GroupId>,
-    }
+        #[test]
+        fn notify_closed_socket() {
+            // The socket file still exists but nobody is listening.
+            let path = socket_path("notify-closed");
+            let _ = fs::remove_file(&path);
+            drop(UnixDatagram::bind(&path).unwrap());
+            let notify = Notify {
+                socket: Some(NotifySocket::Path(path.clone()))
+            };
+            assert!(notify.is_enabled());
+            assert!(
+                Notify::try_send(
+                    notify.socket.as_ref().unwrap(), "READY=1"
+                ).is_err()
+            );
+            notify.notify_ready();
+            notify.notify_status("still\nrunning");
+            notify.notify_stopping();
+            fs::remove_file(&path).unwrap();
+        }
 
-    impl Args {
-        pub fn into_config(self) -> Config {
-            Config::from_args(self)
+        #[test]
+        fn notify_removed_socket() {
+            let path = socket_path("notify-removed");
+            let _ = fs::remove_file(&path);
+            let notify = Notify {
+                socket: Some(NotifySocket::Path(path))
+            };
+            assert!(
+                Notify::try_send(
+                    notify.socket.as_ref().unwrap(), "READY=1"
+                ).is_err()
+            );
+            notify.notify_ready();
+            notify.notify_reloading();
+            notify.notify_main_pid(None);
         }
-    }
 
+        #[test]
+        fn notify_delivers() {
+            let path = socket_path("notify-delivers");
+            let _ = fs::remove_file(&path);
+            let server = UnixDatagram::bind(&path).unwrap();
+            let notify = Notify {
+                socket: Some(NotifySocket::Path(path.clone()))
+            };
+            notify.notify_ready();
+            let mut buf = [0u8; 64];
+            let len = server.recv(&mut buf).unwrap();
+            assert_eq!(&buf[..len], b"READY=1");
+            fs::remove_file(&path).unwrap();
+        }
 
-    //-------- UserId --------------------------------------------------------
+        #[test]
+        fn notify_messages() {
+            let path = socket_path("notify-messages");
+            let _ = fs::remove_file(&path);
+            let server = UnixDatagram::bind(&path).unwrap();
+            let notify = Notify {
+                socket: Some(NotifySocket::Path(path.clone()))
+            };
+            let mut buf = [0u8; 64];
+            let mut recv = || {
+                let len = server.recv(&mut buf).unwrap();
+                String::from_utf8(buf[..len].to_vec()).unwrap()
+            };
 
-    /// A user ID in configuration.
-    #[derive(Clone, Debug, Deserialize, Serialize)]
-    #[serde(try_from = "String", into = "String", expecting = "a user name")]
-    struct UserId {
-        /// The numerical user ID.
-        uid: Uid,
+            notify.notify_reloading();
+            let msg = recv();
+            let mut lines = msg.lines();
+            assert_eq!(lines.next(), Some("RELOADING=1"));
+            assert!(
+                lines.next().unwrap().strip_prefix("MONOTONIC_USEC=")
+                    .unwrap().parse::<u64>().is_ok()
+            );
+            assert_eq!(lines.next(), None);
 
-        /// The user name.
-        ///
-        /// We keep this information so we can produce the actual config.
-        name: String,
-    }
+            notify.notify_stopping();
+            assert_eq!(recv(), "STOPPING=1");
+            notify.notify_status("loading\nconfig");
+            assert_eq!(recv(), "STATUS=loading config");
+            notify.notify_main_pid(Some(42));
+            assert_eq!(recv(), "MAINPID=42");
+            fs::remove_file(&path).unwrap();
+        }
 
-    impl TryFrom<String> for UserId {
-        type Error = String;
+        #[test]
+        fn supplementary_gids() {
+            let config = |content: &str| {
+                Config::from_config_file(
+                    &mut ConfigFile::parse(
+                        content, Path::new("/test.conf")
+                    ).unwrap()
+                ).unwrap()
+            };
 
-        fn try_from(name: String) -> Result<Self, Self::Error> {
-            match User::from_name(&name) {
-                Ok(Some(user)) => {
-                    Ok(UserId { uid: user.uid, name })
-                }
-                Ok(None) => {
-                    Err(format!("unknown user '{}'", name))
-                }
-                Err(err) => {
-                    Err(format!("failed to resolve user '{}': {}", name, err))
-                }
+            assert_eq!(config("").supplementary_gids(), None);
+            assert_eq!(
+                config("user = \"65534\"").supplementary_gids(),
+                Some(Vec::new())
+            );
+            assert_eq!(
+                config("group = \"65534\"").supplementary_gids(),
+                Some(Vec::new())
+            );
+            assert_eq!(
+                config(
+                    "user = \"65534\"\nclear-supplementary-groups = false"
+                ).supplementary_gids(),
+                None
+            );
+            assert_eq!(
+                config(
+                    "supplementary-groups = [\"65534\", \"#65533\"]"
+                ).supplementary_gids(),
+                Some(vec![65534, 65533])
+            );
+        }
+
+        #[test]
+        fn admin_socket_config() {
+            let socket = |value: &str| {
+                AdminSocket::from_value(
+                    value.into(), Some(Path::new("/etc/daemon"))
+                )
+            };
+            assert!(matches!(
+                socket("admin.sock").unwrap(),
+                AdminSocket::Path(path)
+                    if path.as_path() == Path::new("/etc/daemon/admin.sock")
+            ));
+            assert!(socket("@").is_err());
+            if cfg!(target_os = "linux") {
+                assert!(matches!(
+                    socket("@admin").unwrap(),
+                    AdminSocket::Abstract(name) if name == "admin"
+                ));
+            }
+            else {
+                assert!(socket("@admin").is_err());
             }
         }
-    }
 
-    impl FromStr for UserId {
-        type Err = String;
+        #[test]
+        #[cfg(target_os = "linux")]
+        fn peer_credentials() {
+            let (left, _right) = UnixStream::pair().unwrap();
+            assert_eq!(
+                Process::peer_credentials(&left).unwrap(),
+                (geteuid(), getegid())
+            );
+        }
 
-        fn from_str(name: &str) -> Result<Self, Self::Err> {
-            String::from(name).try_into()
+        #[test]
+        #[cfg(target_os = "linux")]
+        fn abstract_admin_socket() {
+            use std::os::linux::net::SocketAddrExt;
+
+            let name = format!("daemonbase-test-{}-admin", std::process::id());
+            let mut file = ConfigFile::parse(
+                &format!("admin-socket = \"@{}\"\n", name),
+                Path::new("/test.conf")
+            ).unwrap();
+            let process = Process::from_config(
+                Config::from_config_file(&mut file).unwrap()
+            );
+            let listener = process.bind_admin_socket().unwrap().unwrap();
+            let _client = UnixStream::connect_addr(
+                &std::os::unix::net::SocketAddr::from_abstract_name(
+                    name.as_bytes()
+                ).unwrap()
+            ).unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            assert!(process.check_admin_peer(&stream));
         }
-    }
 
-    impl From<UserId> for String {
-        fn from(user: UserId) -> Self {
-            user.name
+        #[test]
+        fn rlimit_soft_only() {
+            let rlimits = |content: &str| {
+                Rlimits::from_config_file(
+                    &mut ConfigFile::parse(
+                        content, Path::new("/test.conf")
+                    ).unwrap()
+                )
+            };
+            // Use the current soft limit so other tests aren’t affected.
+            let (soft, hard) = getrlimit(Resource::RLIMIT_NOFILE).unwrap();
+            let value = if soft == libc::RLIM_INFINITY {
+                String::from("\"unlimited\"")
+            }
+            else {
+                soft.to_string()
+            };
+            let limits = rlimits(&format!(
+                "[rlimits]\nnofile = {{ soft = {} }}\n", value
+            )).unwrap();
+            let nofile = limits.nofile.unwrap();
+            assert!(nofile.hard.is_none());
+            assert_eq!(nofile.to_string(), format!("{{ soft = {} }}", value));
+            limits.apply().unwrap();
+            assert_eq!(
+                getrlimit(Resource::RLIMIT_NOFILE).unwrap(), (soft, hard)
+            );
+
+            assert!(rlimits(
+                "[rlimits]\nnofile = { soft = 20, hard = 10 }\n"
+            ).is_err());
+        }
+
+        #[test]
+        fn log_file_collides_with_pid_file() {
+            let path = env::temp_dir().join(format!(
+                "daemonbase-test-{}-collision", std::process::id()
+            ));
+            let content = format!(
+                "pid-file = \"{0}\"\nlog = \"file\"\nlog-file = \"{0}\"\n",
+                path.display()
+            );
+            let mut file = ConfigFile::parse(
+                &content, Path::new("/test.conf")
+            ).unwrap();
+            let log = logging::Config::from_config_file(&mut file).unwrap();
+            let mut process = Process::from_config(
+                Config::from_config_file(&mut file).unwrap()
+            );
+            assert!(process.validate().is_ok());
+            process.set_log_config(&log);
+            assert!(process.validate().is_err());
+            assert!(process.setup_daemon(false).is_err());
+            assert!(!path.exists());
+        }
+
+        #[test]
+        fn log_file_accessible_as_target() {
+            // The check needs a target user other than ourselves.
+            match User::from_name("nobody") {
+                Ok(Some(user)) if user.uid != Uid::effective() => { }
+                _ => return
+            }
+            let dir = env::temp_dir().join(format!(
+                "daemonbase-test-{}-log-access", std::process::id()
+            ));
+            fs::create_dir_all(&dir).unwrap();
+            fs::set_permissions(
+                &dir, fs::Permissions::from_mode(0o700)
+            ).unwrap();
+            let content = format!(
+                "user = \"nobody\"\n\
+                 log = \"file\"\n\
+                 log-file = \"{}\"\n",
+                dir.join("daemon.log").display()
+            );
+            let mut file = ConfigFile::parse(
+                &content, Path::new("/test.conf")
+            ).unwrap();
+            let log = logging::Config::from_config_file(&mut file).unwrap();
+            let mut process = Process::from_config(
+                Config::from_config_file(&mut file).unwrap()
+            );
+            let ok = process.check_paths_accessible_as_target().is_ok();
+            process.set_log_config(&log);
+            let log_ok = process.check_paths_accessible_as_target().is_ok();
+            fs::remove_dir_all(&dir).unwrap();
+            assert!(ok);
+            assert!(!log_ok);
+        }
+
+        #[test]
+        fn chroot_dir_owned_by_user() {
+            let dir = env::temp_dir().join(format!(
+                "daemonbase-test-{}-chroot", std::process::id()
+            ));
+            fs::create_dir_all(&dir).unwrap();
+            fs::set_permissions(
+                &dir, fs::Permissions::from_mode(0o555)
+            ).unwrap();
+            let process = |uid: u32| {
+                let mut file = ConfigFile::parse(
+                    &format!("user = \"{}\"\nstrict-chroot = true\n", uid),
+                    Path::new("/test.conf")
+                ).unwrap();
+                Process::from_config(
+                    Config::from_config_file(&mut file).unwrap()
+                )
+            };
+
+            // Even a read-only directory can be changed by its owner.
+            let owner = getuid().as_raw();
+            assert!(process(owner).check_chroot_dir(&dir).is_err());
+            let other = if owner == 65534 { 65533 } else { 65534 };
+            assert!(process(other).check_chroot_dir(&dir).is_ok());
+            fs::remove_dir(&dir).unwrap();
+        }
+
+        #[test]
+        fn socket_options() {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let sock = TcpStream::connect(
+                listener.local_addr().unwrap()
+            ).unwrap();
+            set_tcp_nodelay(&sock, true).unwrap();
+            assert!(sock.nodelay().unwrap());
+            set_tcp_nodelay(&sock, false).unwrap();
+            assert!(!sock.nodelay().unwrap());
+            set_keepalive(&sock, true).unwrap();
+            assert!(SockRef::from(&sock).keepalive().unwrap());
+            set_recv_buffer_size(&sock, 65536).unwrap();
+            set_send_buffer_size(&sock, 65536).unwrap();
+            set_nonblocking(&sock, true).unwrap();
+            assert!(set_recv_buffer_size(&sock, usize::MAX).is_err());
+
+            // Options on something that isn’t a socket fail.
+            let file = fs::File::open("/dev/null").unwrap();
+            assert!(set_tcp_nodelay(&file, true).is_err());
+        }
+
+        #[test]
+        fn socket_info_from_fd() {
+            use std::os::fd::IntoRawFd;
+
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let info = SocketInfo::from_fd(
+                listener.into_raw_fd(), Some("web".into())
+            ).unwrap();
+            assert_eq!(info.sock_type, SocketType::Tcp);
+            assert!(info.has_inet_addr(&addr));
+
+            // A file is left open and untouched.
+            let file = fs::File::open("/dev/null").unwrap();
+            assert!(SocketInfo::from_fd(file.as_raw_fd(), None).is_none());
+            assert!(fcntl(file.as_raw_fd(), FcntlArg::F_GETFD).is_ok());
+
+            // A descriptor that isn’t open is skipped.
+            assert!(SocketInfo::from_fd(RawFd::MAX, None).is_none());
         }
-    }
 
+        #[test]
+        fn signals_stop_listeners_on_shutdown() {
+            assert!(super::super::Signal::Term.is_shutdown());
+            assert!(super::super::Signal::Int.is_shutdown());
+            assert!(!super::super::Signal::Hup.is_shutdown());
 
-    //-------- GroupId -------------------------------------------------------
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let handle = ListenerHandle::new(&listener).unwrap();
+            let mut signals = Signals {
+                set: SigSet::empty(), listeners: Vec::new()
+            };
+            signals.stop_on_shutdown(handle.clone());
+            assert!(!handle.is_stopped());
+            signals.stop_listeners();
+            assert!(handle.is_stopped());
 
-    /// A user ID in configuration.
-    #[derive(Clone, Debug, Deserialize, Serialize)]
-    #[serde(try_from = "String", into = "String", expecting = "a user name")]
-    struct GroupId {
-        /// The numerical user ID.
-        gid: Gid,
+            // The listener itself is still open but accepts nothing.
+            listener.set_nonblocking(true).unwrap();
+            assert!(listener.accept().is_err());
+        }
 
-        /// The user name.
-        ///
-        /// We keep this information so we can produce the actual config.
-        name: String,
-    }
+        #[test]
+        #[cfg(feature = "tokio")]
+        fn async_signals() {
+            use nix::sys::signal::raise;
 
-    impl TryFrom<String> for GroupId {
-        type Error = String;
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all().build().unwrap();
+            runtime.block_on(async {
+                let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+                let handle = ListenerHandle::new(&listener).unwrap();
+                let mut signals = AsyncSignals::new().unwrap();
+                signals.stop_on_shutdown(handle.clone());
 
-        fn try_from(name: String) -> Result<Self, Self::Error> {
-            match Group::from_name(&name) {
-                Ok(Some(group)) => {
-                    Ok(GroupId { gid: group.gid, name })
-                }
-                Ok(None) => {
-                    Err(format!("unknown user '{}'", name))
-                }
-                Err(err) => {
-                    Err(format!("failed to resolve user '{}': {}", name, err))
-                }
-            }
+                raise(Signal::SIGUSR2).unwrap();
+                assert_eq!(
+                    signals.recv().await.unwrap(), super::super::Signal::User2
+                );
+                assert!(!handle.is_stopped());
+
+                raise(Signal::SIGTERM).unwrap();
+                assert_eq!(
+                    signals.recv().await.unwrap(), super::super::Signal::Term
+                );
+                assert!(handle.is_stopped());
+            });
         }
-    }
 
-    impl FromStr for GroupId {
-        type Err = String;
+        #[test]
+        fn take_or_bind_reuseport() {
+            let mut sockets = EnvSockets { sockets: Vec::new() };
+            let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+            let first = sockets.take_or_bind_tcp(&addr, true).unwrap();
+            let addr = first.local_addr().unwrap();
+            let second = sockets.take_or_bind_tcp(&addr, true).unwrap();
+            assert_eq!(second.local_addr().unwrap(), addr);
+            let flags = fcntl(second.as_raw_fd(), FcntlArg::F_GETFD).unwrap();
+            assert!(FdFlag::from_bits_truncate(flags).contains(
+                FdFlag::FD_CLOEXEC
+            ));
 
-        fn from_str(name: &str) -> Result<Self, Self::Err> {
-            String::from(name).try_into()
+            let first = sockets.take_or_bind_udp(&addr, true).unwrap();
+            let second = sockets.take_or_bind_udp(&addr, true).unwrap();
+            assert_eq!(first.local_addr().unwrap(), addr);
+            assert_eq!(second.local_addr().unwrap(), addr);
         }
-    }
 
-    impl From<GroupId> for String {
-        fn from(user: GroupId) -> Self {
-            user.name
+        #[test]
+        #[cfg(all(feature = "caps", target_os = "linux"))]
+        fn capability_from_str() {
+            for name in ["CAP_NET_BIND_SERVICE", "net_bind_service"] {
+                assert_eq!(
+                    Capability::from_str(name).unwrap().0,
+                    caps::Capability::CAP_NET_BIND_SERVICE
+                );
+            }
+            assert!(Capability::from_str("CAP_FROBNICATE").is_err());
         }
     }
 }
@@ -518,10 +4934,17 @@ mod unix {
 ///
 #[cfg(not(unix))]
 mod noop {
-    use std::path::{PathBuf, StripPrefixError};
+    use std::fs;
+    use std::net::{SocketAddr, TcpListener, UdpSocket};
+    use std::path::{Path, PathBuf, StripPrefixError};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+    use log::error;
     use serde::{Deserialize, Serialize};
-    use crate::config::ConfigFile;
+    use crate::config::{ConfigFile, ValidationReport};
     use crate::error::Failed;
+    use crate::logging;
 
 
     //-------- Process -------------------------------------------------------
@@ -549,6 +4972,50 @@ mod noop {
             Ok(path)
         }
 
+        /// Adjusts a path for use right now.
+        ///
+        /// Since the file system root is never changed on this system, this
+        /// returns the path unchanged.
+        pub fn current_path(
+            &self, path: PathBuf
+        ) -> Result<PathBuf, StripPrefixError> {
+            Ok(path)
+        }
+
+        /// Logs the command line and environment of the process.
+        ///
+        /// The command line arguments are logged at info level, the
+        /// environment variables at debug level. Variables whose name
+        /// suggests that they contain secrets, such as passwords or tokens,
+        /// are left out. Likewise, the values of such options are replaced
+        /// with `<redacted>`.
+        ///
+        /// This should be called after logging has been switched to its
+        /// final target.
+        pub fn log_invocation(&self) {
+            super::log_invocation()
+        }
+
+        /// Logs the effective configuration at debug level.
+        ///
+        /// Both the process configuration and the logging configuration
+        /// given via `log` are logged with one record per setting. Values
+        /// of settings whose name suggests a secret are redacted.
+        ///
+        /// This should be called after logging has been switched to its
+        /// final target.
+        pub fn log_effective_config(&self, log: &logging::Config) {
+            log.log_settings();
+        }
+
+        /// Sets the logging configuration used by the daemon.
+        ///
+        /// Since there are no process options on this system that could
+        /// collide with the log files, this does nothing.
+        pub fn set_log_config(&mut self, log: &logging::Config) {
+            let _ = log;
+        }
+
         /// Sets up the process as a daemon.
         ///
         /// If `background` is `true`, the daemon will be set up to run in
@@ -576,6 +5043,475 @@ mod noop {
         pub fn drop_privileges(&mut self) -> Result<(), Failed> {
             Ok(())
         }
+
+        /// Opens files for reading for later use.
+        ///
+        /// Opens all files in `paths` for reading and returns them in the
+        /// same order. If opening any of the files fails, logs an error
+        /// naming the file and returns an error.
+        pub fn preopen(
+            &self, paths: &[&Path]
+        ) -> Result<Vec<fs::File>, Failed> {
+            paths.iter().map(|path| {
+                fs::File::open(path).map_err(|err| {
+                    error!(
+                        "Fatal: failed to open {}: {}", path.display(), err
+                    );
+                    Failed
+                })
+            }).collect()
+        }
+
+        /// Checks that the daemon could be set up without actually doing so.
+        ///
+        /// Since there is nothing to set up on this system, this always
+        /// succeeds.
+        pub fn validate(&self) -> Result<(), Failed> {
+            Ok(())
+        }
+
+        /// Checks that configured paths are accessible as the target user.
+        ///
+        /// Since privileges are never dropped on this system, this does
+        /// nothing.
+        pub fn check_paths_accessible_as_target(&self) -> Result<(), Failed> {
+            Ok(())
+        }
+
+        /// Checks that a path is accessible as the target user.
+        ///
+        /// Since privileges are never dropped on this system, this does
+        /// nothing.
+        pub fn check_path_as_target(
+            &self, path: &Path, write: bool
+        ) -> Result<(), Failed> {
+            let _ = (path, write);
+            Ok(())
+        }
+
+        /// Registers a hook to be run in the child process after forking.
+        ///
+        /// Since this system never forks, the hook is never run.
+        pub fn on_fork(&mut self, hook: impl FnMut() + Send + 'static) {
+            let _ = hook;
+        }
+
+        /// Sets whether to keep stderr open until the daemon is ready.
+        ///
+        /// Since the standard streams are never redirected on this system,
+        /// this does nothing.
+        pub fn keep_stderr_until_ready(&mut self, keep: bool) {
+            let _ = keep;
+        }
+
+        /// Signals the original process that the daemon is ready.
+        ///
+        /// Since this system never forks, this does nothing.
+        pub fn ready(&mut self) {
+        }
+
+        /// Sets whether to suppress the crate’s own lifecycle messages.
+        ///
+        /// Since privileges are never dropped on this system, there are no
+        /// messages to suppress and this does nothing.
+        pub fn quiet_lifecycle(&mut self, quiet: bool) {
+            let _ = quiet;
+        }
+
+        /// Returns the umask the process had before it was set up.
+        ///
+        /// There is no umask on this system, so this always returns `None`.
+        pub fn original_umask(&self) -> Option<u32> {
+            None
+        }
+
+        /// Runs a closure with the given umask.
+        ///
+        /// There is no umask on this system, so this just runs `op`.
+        pub fn with_umask<T>(mask: u32, op: impl FnOnce() -> T) -> T {
+            let _ = mask;
+            op()
+        }
+
+        /// Moves the process into a network namespace.
+        ///
+        /// Network namespaces are not supported on this system, so this
+        /// method always fails.
+        pub fn enter_netns(&self, path: &Path) -> Result<(), Failed> {
+            error!(
+                "Fatal: cannot enter network namespace {}: \
+                 not supported on this system.",
+                path.display()
+            );
+            Err(Failed)
+        }
+
+        /// Changes the file system root if requested.
+        ///
+        /// This does nothing on this system.
+        pub fn enter_chroot(&mut self) -> Result<(), Failed> {
+            Ok(())
+        }
+
+        /// Forks worker processes.
+        ///
+        /// Forking is not supported on this system, so this method always
+        /// logs an error and fails.
+        pub fn fork_workers(
+            &mut self,
+            n: usize,
+            restart: bool,
+            worker: impl FnMut(usize) -> Result<(), Failed>,
+        ) -> Result<(), Failed> {
+            let _ = (n, restart, worker);
+            error!(
+                "Fatal: worker processes are not supported on this system."
+            );
+            Err(Failed)
+        }
+    }
+
+
+    //-------- Notify --------------------------------------------------------
+
+    /// Sending notifications to the service manager.
+    ///
+    /// There is no service manager to notify on this system, so all
+    /// notifications are ignored.
+    #[derive(Clone, Debug, Default)]
+    pub struct Notify;
+
+    impl Notify {
+        /// Creates a value from the environment.
+        pub fn from_env() -> Self {
+            Self
+        }
+
+        /// Returns whether notifications are actually sent.
+        pub fn is_enabled(&self) -> bool {
+            false
+        }
+
+        /// Notifies the service manager that the service is ready.
+        pub fn notify_ready(&self) { }
+
+        /// Notifies the service manager that the service is reloading.
+        pub fn notify_reloading(&self) { }
+
+        /// Notifies the service manager that the service is stopping.
+        pub fn notify_stopping(&self) { }
+
+        /// Sends a free-form status message to the service manager.
+        pub fn notify_status(&self, status: &str) {
+            let _ = status;
+        }
+
+        /// Notifies the service manager of the main PID of the service.
+        pub fn notify_main_pid(&self, pid: Option<u32>) {
+            let _ = pid;
+        }
+    }
+
+
+    //-------- EnvSockets ----------------------------------------------------
+
+    /// Listener sockets passed in via the environment.
+    ///
+    /// Socket activation is not supported on this system, so there are
+    /// never any sockets.
+    #[derive(Debug, Default)]
+    pub struct EnvSockets;
+
+    impl EnvSockets {
+        /// Collects the sockets passed in via the environment.
+        pub fn from_env() -> Self {
+            Self
+        }
+
+        /// Returns whether there are no sockets left.
+        pub fn is_empty(&self) -> bool {
+            true
+        }
+
+        /// Returns the number of sockets left.
+        pub fn len(&self) -> usize {
+            0
+        }
+
+        /// Returns an iterator over the names of the sockets left.
+        pub fn names(&self) -> impl Iterator<Item = &str> {
+            std::iter::empty()
+        }
+
+        /// Returns whether there is a TCP listener for the given address.
+        pub fn has_tcp(&self, addr: &SocketAddr) -> bool {
+            let _ = addr;
+            false
+        }
+
+        /// Returns whether there is a UDP socket for the given address.
+        pub fn has_udp(&self, addr: &SocketAddr) -> bool {
+            let _ = addr;
+            false
+        }
+
+        /// Takes the TCP listener for the given address.
+        pub fn take_tcp(&mut self, addr: &SocketAddr) -> Option<TcpListener> {
+            let _ = addr;
+            None
+        }
+
+        /// Takes the UDP socket for the given address.
+        pub fn take_udp(&mut self, addr: &SocketAddr) -> Option<UdpSocket> {
+            let _ = addr;
+            None
+        }
+
+        /// Takes the TCP listener for the given address or binds a new one.
+        ///
+        /// Since there are no sockets passed in on this system, this always
+        /// binds a new listener. Setting `SO_REUSEPORT` is not supported.
+        pub fn take_or_bind_tcp(
+            &mut self, addr: &SocketAddr, reuseport: bool
+        ) -> Result<TcpListener, Failed> {
+            let _ = reuseport;
+            TcpListener::bind(addr).map_err(|err| {
+                error!("Fatal: failed to bind TCP listener {}: {}", addr, err);
+                Failed
+            })
+        }
+
+        /// Takes the UDP socket for the given address or binds a new one.
+        ///
+        /// Since there are no sockets passed in on this system, this always
+        /// binds a new socket. Setting `SO_REUSEPORT` is not supported.
+        pub fn take_or_bind_udp(
+            &mut self, addr: &SocketAddr, reuseport: bool
+        ) -> Result<UdpSocket, Failed> {
+            let _ = reuseport;
+            UdpSocket::bind(addr).map_err(|err| {
+                error!("Fatal: failed to bind UDP socket {}: {}", addr, err);
+                Failed
+            })
+        }
+
+        /// Takes the TCP listener with the given name.
+        pub fn take_tcp_by_name(&mut self, name: &str) -> Option<TcpListener> {
+            let _ = name;
+            None
+        }
+
+        /// Takes the UDP socket with the given name.
+        pub fn take_udp_by_name(&mut self, name: &str) -> Option<UdpSocket> {
+            let _ = name;
+            None
+        }
+
+        /// Takes the first remaining TCP listener.
+        pub fn pop_tcp(&mut self) -> Option<TcpListener> {
+            None
+        }
+
+        /// Takes the first remaining UDP socket.
+        pub fn pop_udp(&mut self) -> Option<UdpSocket> {
+            None
+        }
+    }
+
+
+    //-------- ListenerHandle ------------------------------------------------
+
+    /// A handle for stopping a listener socket from accepting connections.
+    ///
+    /// Stopping listeners is not supported on this system. The handle only
+    /// remembers whether it has been stopped.
+    #[derive(Clone, Debug, Default)]
+    pub struct ListenerHandle {
+        /// Whether the listener has been stopped.
+        stopped: Arc<AtomicBool>,
+    }
+
+    impl ListenerHandle {
+        /// Creates a new handle for the given listener.
+        pub fn new<T>(listener: &T) -> Result<Self, Failed> {
+            let _ = listener;
+            Ok(Self::default())
+        }
+
+        /// Stops the listener from accepting new connections.
+        pub fn stop_accepting(&self) {
+            self.stopped.store(true, Ordering::Relaxed)
+        }
+
+        /// Returns whether the listener has been stopped.
+        pub fn is_stopped(&self) -> bool {
+            self.stopped.load(Ordering::Relaxed)
+        }
+    }
+
+
+    //-------- LimitsReport --------------------------------------------------
+
+    /// Returns the resource limits the process is currently running under.
+    ///
+    /// Resource limits are not supported on this system, so all limits are
+    /// unknown.
+    pub fn current_limits() -> LimitsReport {
+        LimitsReport
+    }
+
+    /// The current resource limits of the process.
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct LimitsReport;
+
+    impl LimitsReport {
+        /// Returns the limit for the number of open file descriptors.
+        pub fn nofile(&self) -> Option<ResourceLimit> {
+            None
+        }
+
+        /// Returns the limit for the number of processes of the user.
+        pub fn nproc(&self) -> Option<ResourceLimit> {
+            None
+        }
+
+        /// Returns the limit for the size of core dumps.
+        pub fn core(&self) -> Option<ResourceLimit> {
+            None
+        }
+
+        /// Logs the limits at info level.
+        ///
+        /// Since there are no limits on this system, this does nothing.
+        pub fn log(&self) {
+        }
+    }
+
+
+    //-------- ResourceLimit -------------------------------------------------
+
+    /// The soft and hard value of a current resource limit.
+    #[derive(Clone, Copy, Debug)]
+    pub struct ResourceLimit {
+        /// The soft limit or `None` if unlimited.
+        soft: Option<u64>,
+
+        /// The hard limit or `None` if unlimited.
+        hard: Option<u64>,
+    }
+
+    impl ResourceLimit {
+        /// Returns the soft limit.
+        ///
+        /// Returns `None` if the resource is unlimited.
+        pub fn soft(self) -> Option<u64> {
+            self.soft
+        }
+
+        /// Returns the hard limit.
+        ///
+        /// Returns `None` if the resource is unlimited.
+        pub fn hard(self) -> Option<u64> {
+            self.hard
+        }
+    }
+
+
+    //-------- Signals -------------------------------------------------------
+
+    /// Synchronous handling of the signals commonly used by daemons.
+    ///
+    /// Signals are not supported on this system, so no signal is ever
+    /// received.
+    #[derive(Debug)]
+    pub struct Signals;
+
+    impl Signals {
+        /// Creates a new value.
+        pub fn new() -> Result<Self, Failed> {
+            Ok(Signals)
+        }
+
+        /// Registers a listener to be stopped upon shutdown.
+        ///
+        /// Since no signal is ever received, the listener is never
+        /// stopped.
+        pub fn stop_on_shutdown(&mut self, listener: ListenerHandle) {
+            let _ = listener;
+        }
+
+        /// Waits until one of the signals is received.
+        ///
+        /// Since this never happens, this method never returns.
+        pub fn wait(&self) -> Result<super::Signal, Failed> {
+            loop {
+                std::thread::park()
+            }
+        }
+    }
+
+
+    //-------- AsyncSignals --------------------------------------------------
+
+    /// Asynchronous handling of the signals commonly used by daemons.
+    ///
+    /// Signals are not supported on this system, so no signal is ever
+    /// received.
+    #[cfg(feature = "tokio")]
+    #[derive(Debug)]
+    pub struct AsyncSignals;
+
+    #[cfg(feature = "tokio")]
+    impl AsyncSignals {
+        /// Creates a new value.
+        pub fn new() -> Result<Self, Failed> {
+            Ok(AsyncSignals)
+        }
+
+        /// Registers a listener to be stopped upon shutdown.
+        ///
+        /// Since no signal is ever received, the listener is never
+        /// stopped.
+        pub fn stop_on_shutdown(&mut self, listener: ListenerHandle) {
+            let _ = listener;
+        }
+
+        /// Waits until one of the signals is received.
+        ///
+        /// Since this never happens, the returned future never resolves.
+        pub async fn recv(&mut self) -> Result<super::Signal, Failed> {
+            std::future::pending().await
+        }
+    }
+
+
+    //-------- Watchdog ------------------------------------------------------
+
+    /// The service watchdog.
+    ///
+    /// There is no service manager on this system, so the watchdog is
+    /// never enabled.
+    #[derive(Clone, Debug, Default)]
+    pub struct Watchdog;
+
+    impl Watchdog {
+        /// Creates the watchdog from the environment.
+        pub fn from_env() -> Self {
+            Self
+        }
+
+        /// Returns the watchdog timeout if the watchdog is enabled.
+        pub fn timeout(&self) -> Option<Duration> {
+            None
+        }
+
+        /// Returns the interval for sending pings.
+        pub fn interval(&self) -> Option<Duration> {
+            None
+        }
+
+        /// Sends a keep-alive ping to the service manager.
+        pub fn ping(&self) { }
     }
 
 
@@ -589,7 +5525,9 @@ mod noop {
         pub fn from_config_file(
             file: &mut ConfigFile
         ) -> Result<Self, Failed> {
-            let _ = file;
+            // Resource limits are not supported here, so we just ignore
+            // them.
+            let _ = file.take_table("rlimits")?;
             Ok(Self)
         }
 
@@ -603,6 +5541,46 @@ mod noop {
         pub fn apply_args(&mut self, args: Args) {
             let _ = args;
         }
+
+        /// Sets the supplementary groups to the given group names.
+        ///
+        /// Groups are not supported on this system, so this does nothing.
+        pub fn with_supplementary_groups<'a>(
+            self, groups: impl IntoIterator<Item = &'a str>
+        ) -> Result<Self, Failed> {
+            let _ = groups;
+            Ok(self)
+        }
+
+        /// Validates the configuration and adds the results to `report`.
+        ///
+        /// There is nothing to validate on this system.
+        pub fn validate(&self, report: &mut ValidationReport) {
+            let _ = report;
+        }
+
+        /// Checks that the options of the configuration are consistent.
+        ///
+        /// Since there are no options on this system, this always succeeds.
+        pub fn check_consistency(&self) -> Result<(), Failed> {
+            Ok(())
+        }
+
+        /// Checks the consistency together with the logging configuration.
+        ///
+        /// Since there are no options on this system, only the logging
+        /// configuration is checked.
+        pub fn check_consistency_with(
+            &self, log: &logging::Config
+        ) -> Result<(), Failed> {
+            log.check_consistency()
+        }
+
+        /// Logs the effective settings at debug level.
+        ///
+        /// Since there are no options on this system, this does nothing.
+        pub fn log_settings(&self) {
+        }
     }
 
     //-------- Args ----------------------------------------------------------
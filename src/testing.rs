@@ -0,0 +1,130 @@
+//! Support for testing daemonization.
+//!
+//! Setting up a daemon process changes global state of the process: it
+//! forks, changes the working directory, and may change the user. This
+//! makes it impossible to test these steps directly in a test.
+//!
+//! This module provides [`run_forked`] which runs a closure in a forked
+//! child process and reports the closure’s result back to the calling
+//! process over a pipe. A test can thus perform the full sequence of
+//! [`Process::setup_daemon`] and [`Process::drop_privileges`] in the
+//! closure, check the resulting state, and report the outcome.
+//!
+//! The module is only available on Unix systems and if the `testing`
+//! feature is enabled.
+//!
+//! [`Process::setup_daemon`]: crate::process::Process::setup_daemon
+//! [`Process::drop_privileges`]: crate::process::Process::drop_privileges
+
+use std::fs;
+use std::io::{Read, Write};
+use std::os::fd::FromRawFd;
+use nix::sys::wait::waitpid;
+use nix::unistd::{fork, pipe, ForkResult};
+
+
+//------------ run_forked ----------------------------------------------------
+
+/// Runs a closure in a forked child process and returns its result.
+///
+/// The closure is run in a child process. It may daemonize the child
+/// further, including forking into the background. Whichever process
+/// returns from the closure reports the result back through a pipe and
+/// then exits. The function waits until all processes holding the pipe
+/// have exited or closed it and returns the reported result.
+///
+/// If the child process exits without returning from the closure, e.g.,
+/// because it panicked or because setting up the daemon failed, an error
+/// is returned.
+///
+/// Since forking a multi-threaded process only carries over the calling
+/// thread, the closure should avoid relying on locks held by other threads.
+pub fn run_forked<F>(op: F) -> Result<String, String>
+where F: FnOnce() -> Result<String, String> {
+    let (read_fd, write_fd) = pipe().map_err(|err| {
+        format!("failed to create pipe: {}", err)
+    })?;
+    // Safety: pipe just gave us these descriptors.
+    let mut reader = unsafe { fs::File::from_raw_fd(read_fd) };
+    let mut writer = unsafe { fs::File::from_raw_fd(write_fd) };
+
+    match unsafe { fork() } {
+        Ok(ForkResult::Child) => {
+            drop(reader);
+            let report = match op() {
+                Ok(res) => format!("ok:{}", res),
+                Err(err) => format!("err:{}", err),
+            };
+            let _ = writer.write_all(report.as_bytes());
+            drop(writer);
+            // Don’t run any of the parent’s exit handlers or destructors.
+            unsafe { libc::_exit(0) }
+        }
+        Ok(ForkResult::Parent { child }) => {
+            drop(writer);
+            let mut report = String::new();
+            let read = reader.read_to_string(&mut report);
+            let _ = waitpid(child, None);
+            if let Err(err) = read {
+                return Err(format!("failed to read child report: {}", err))
+            }
+            if let Some(res) = report.strip_prefix("ok:") {
+                Ok(res.into())
+            }
+            else if let Some(err) = report.strip_prefix("err:") {
+                Err(err.into())
+            }
+            else {
+                Err("child exited without reporting a result".into())
+            }
+        }
+        Err(err) => Err(format!("failed to fork: {}", err))
+    }
+}
+
+
+//============ Tests =========================================================
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::path::Path;
+    use crate::config::ConfigFile;
+    use crate::process::{Config, Process};
+
+    #[test]
+    fn reports_result() {
+        assert_eq!(run_forked(|| Ok("done".into())), Ok("done".into()));
+        assert_eq!(run_forked(|| Err("failed".into())), Err("failed".into()));
+        assert!(run_forked(|| unsafe { libc::_exit(1) }).is_err());
+    }
+
+    #[test]
+    fn creates_pid_file() {
+        let path = env::temp_dir().join(format!(
+            "daemonbase-test-{}-forked.pid", std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        let res = run_forked(|| {
+            let mut file = ConfigFile::parse(
+                &format!("pid-file = \"{}\"\n", path.display()),
+                Path::new("/test.conf")
+            ).map_err(|_| String::from("failed to parse config"))?;
+            let config = Config::from_config_file(&mut file).map_err(|_| {
+                String::from("failed to read config")
+            })?;
+            let mut process = Process::from_config(config);
+            process.setup_daemon(false).map_err(|_| {
+                String::from("failed to set up daemon")
+            })?;
+            process.drop_privileges().map_err(|_| {
+                String::from("failed to drop privileges")
+            })?;
+            Ok(std::process::id().to_string())
+        });
+        let pid = res.unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), pid);
+        fs::remove_file(&path).unwrap();
+    }
+}